@@ -0,0 +1,38 @@
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let commit = command_output("git", &["rev-parse", "--short", "HEAD"]);
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]);
+
+    println!("cargo:rustc-env=BUILD_COMMIT={}", commit);
+    println!("cargo:rustc-env=BUILD_RUSTC={}", rustc_version);
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // HEAD only changes when switching branches, new commits change the branch's ref, which is
+    // packed into packed-refs after a gc.  Files that don't exist would rerun every build.
+    let git_dir = command_output("git", &["rev-parse", "--git-dir"]);
+    let head_ref = command_output("git", &["symbolic-ref", "-q", "HEAD"]);
+
+    let watched = [
+        Path::new(&git_dir).join("HEAD"),
+        Path::new(&git_dir).join("packed-refs"),
+        Path::new(&git_dir).join(head_ref),
+    ];
+
+    for path in watched.iter().filter(|path| path.is_file()) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,392 @@
+use anyhow::Result;
+
+use crate::configuration::AlertsConfiguration;
+use crate::configuration::NtfyConfiguration;
+use crate::configuration::PushoverConfiguration;
+use crate::update::Update;
+
+use serde::Serialize;
+
+use std::collections::HashSet;
+
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Alert sent when a condition trips and again when it clears
+#[derive(Clone, Debug, Serialize)]
+pub struct Alert {
+    /// budget, continuous_flow, battery_low, or disconnected
+    pub alert: &'static str,
+    pub status: Status,
+    pub device_id: String,
+    pub location: String,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Firing,
+    Resolved,
+}
+
+/// One alert condition checked against an update
+struct Condition<'a> {
+    /// Identifies the condition so it alerts once while tripped
+    key: String,
+    tripped: bool,
+    alert: &'static str,
+    location: &'a String,
+    message: String,
+}
+
+/// Pushover messages API
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+
+/// Checks downloader updates against the alert conditions and sends alerts to webhooks and push
+/// notification services
+pub struct Alerts {
+    configuration: AlertsConfiguration,
+    client: reqwest::Client,
+    /// Keys of conditions that are currently tripped
+    active: HashSet<String>,
+}
+
+impl Alerts {
+    pub fn new(configuration: AlertsConfiguration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(configuration.timeout())
+            .build()
+            .expect("Could not build HTTP client");
+
+        Alerts {
+            configuration,
+            client,
+            active: HashSet::new(),
+        }
+    }
+
+    pub async fn start(mut self, mut updates_rx: broadcast::Receiver<Update>) {
+        info!(
+            "Sending alerts to {} webhooks{}{}",
+            self.configuration.webhooks().len(),
+            if self.configuration.ntfy().is_some() {
+                ", ntfy"
+            } else {
+                ""
+            },
+            if self.configuration.pushover().is_some() {
+                ", Pushover"
+            } else {
+                ""
+            }
+        );
+
+        crate::spawn_named(
+            async move {
+                loop {
+                    let update = match updates_rx.recv().await {
+                        Ok(u) => u,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Alerting fell behind, skipped {} updates", skipped);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    };
+
+                    for alert in self.check(&update) {
+                        self.send(&alert).await;
+                    }
+                }
+            },
+            "alerts",
+        );
+    }
+
+    /// Alerts for conditions that tripped or cleared with `update`
+    fn check(&mut self, update: &Update) -> Vec<Alert> {
+        let configuration = &self.configuration;
+        let device_id = update.device_id();
+        let mut conditions = vec![];
+
+        match update {
+            Update::Bridge {
+                location,
+                connected,
+                ..
+            }
+            | Update::Sensor {
+                location,
+                connected,
+                ..
+            } if configuration.disconnected() => conditions.push(Condition {
+                key: format!("disconnected/{}", device_id),
+                tripped: !connected,
+                alert: "disconnected",
+                location,
+                message: format!("{} at {} is disconnected from Flume", device_id, location),
+            }),
+            _ => (),
+        }
+
+        match update {
+            Update::Sensor {
+                location,
+                battery_level,
+                ..
+            } if configuration.battery_low() => conditions.push(Condition {
+                key: format!("battery_low/{}", device_id),
+                tripped: matches!(battery_level.as_str(), "low" | "critical"),
+                alert: "battery_low",
+                location,
+                message: format!(
+                    "Sensor {} at {} battery is {}",
+                    device_id, location, battery_level
+                ),
+            }),
+            Update::Usage {
+                location,
+                continuous_flow_minutes,
+                ..
+            } => conditions.push(Condition {
+                key: format!("continuous_flow/{}", device_id),
+                tripped: *continuous_flow_minutes >= configuration.continuous_flow_minutes(),
+                alert: "continuous_flow",
+                location,
+                message: format!(
+                    "Water has been flowing at {} for {:.0} minutes",
+                    location, continuous_flow_minutes
+                ),
+            }),
+            Update::Budget {
+                location,
+                name,
+                period,
+                used_ratio,
+                ..
+            } => conditions.push(Condition {
+                key: format!("budget/{}/{}/{}", device_id, period, name),
+                tripped: used_ratio * 100.0 >= configuration.budget_used_percent(),
+                alert: "budget",
+                location,
+                message: format!(
+                    "{} {} budget at {} is {:.0}% used",
+                    period,
+                    name,
+                    location,
+                    used_ratio * 100.0
+                ),
+            }),
+            _ => (),
+        }
+
+        let mut alerts = vec![];
+
+        for condition in conditions {
+            let status = if condition.tripped && self.active.insert(condition.key.clone()) {
+                Status::Firing
+            } else if !condition.tripped && self.active.remove(&condition.key) {
+                Status::Resolved
+            } else {
+                continue;
+            };
+
+            alerts.push(Alert {
+                alert: condition.alert,
+                status,
+                device_id: device_id.to_string(),
+                location: condition.location.clone(),
+                message: condition.message,
+            });
+        }
+
+        alerts
+    }
+
+    async fn send(&self, alert: &Alert) {
+        let body = match serde_json::to_string(alert) {
+            Ok(b) => b,
+            Err(e) => {
+                error!("Unable to serialize alert {}", e);
+                return;
+            }
+        };
+
+        for webhook in self.configuration.webhooks() {
+            let result = self
+                .client
+                .post(&webhook)
+                .header("Content-Type", "application/json")
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            if let Err(e) = result {
+                error!("Sending alert to webhook {} failed {}", webhook, e);
+            }
+        }
+
+        if let Some(ntfy) = self.configuration.ntfy() {
+            if let Err(e) = self.send_ntfy(&ntfy, alert).await {
+                error!("Sending alert to ntfy failed {}", e);
+            }
+        }
+
+        if let Some(pushover) = self.configuration.pushover() {
+            if let Err(e) = self.send_pushover(&pushover, alert).await {
+                error!("Sending alert to Pushover failed {}", e);
+            }
+        }
+    }
+
+    async fn send_ntfy(&self, ntfy: &NtfyConfiguration, alert: &Alert) -> Result<()> {
+        let (priority, tags) = match alert.status {
+            Status::Firing => ("high", "warning,droplet"),
+            Status::Resolved => ("default", "white_check_mark,droplet"),
+        };
+
+        let mut builder = self
+            .client
+            .post(ntfy.url())
+            .header("Title", title(alert))
+            .header("Priority", priority)
+            .header("Tags", tags)
+            .body(alert.message.clone());
+
+        if let Some(token) = ntfy.token() {
+            builder = builder.header("Authorization", format!("Bearer {}", token));
+        }
+
+        builder.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+
+    async fn send_pushover(&self, pushover: &PushoverConfiguration, alert: &Alert) -> Result<()> {
+        let priority = match alert.status {
+            Status::Firing => "1",
+            Status::Resolved => "0",
+        };
+
+        let form = [
+            ("token", pushover.token()),
+            ("user", pushover.user()),
+            ("title", title(alert)),
+            ("message", alert.message.clone()),
+            ("priority", priority.to_string()),
+        ];
+
+        self.client
+            .post(PUSHOVER_URL)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Notification title for `alert`
+fn title(alert: &Alert) -> String {
+    let condition = match alert.alert {
+        "budget" => "Water budget",
+        "continuous_flow" => "Possible leak",
+        "battery_low" => "Sensor battery low",
+        "disconnected" => "Device disconnected",
+        other => other,
+    };
+
+    match alert.status {
+        Status::Firing => condition.to_string(),
+        Status::Resolved => format!("Resolved: {}", condition),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+
+    /// Alerts with the default thresholds, budgets at 100% used and 120 minutes of flow
+    fn alerts() -> Alerts {
+        Alerts::new(toml::from_str("").unwrap())
+    }
+
+    fn budget(used_ratio: f64) -> Update {
+        Update::Budget {
+            device_id: "1".to_string(),
+            location_id: "5678".to_string(),
+            location: "Home".to_string(),
+            name: "Water".to_string(),
+            period: "monthly".to_string(),
+            used_ratio,
+        }
+    }
+
+    fn usage(continuous_flow_minutes: f64) -> Update {
+        let now = Utc::now().fixed_offset();
+
+        Update::Usage {
+            device_id: "1".to_string(),
+            location_id: "5678".to_string(),
+            location: "Home".to_string(),
+            since: now,
+            until: now,
+            liters: 1.0,
+            total_liters: 1.0,
+            flow_rate: Some(0.1),
+            continuous_flow_minutes,
+        }
+    }
+
+    fn statuses(alerts: Vec<Alert>) -> Vec<Status> {
+        alerts.iter().map(|alert| alert.status).collect()
+    }
+
+    #[test]
+    fn budget_alert() {
+        let mut alerts = alerts();
+
+        assert!(alerts.check(&budget(0.99)).is_empty());
+
+        let firing = alerts.check(&budget(1.0));
+        assert_eq!(vec![Status::Firing], statuses(firing.clone()));
+        assert_eq!("budget", firing[0].alert);
+        assert_eq!(
+            "monthly Water budget at Home is 100% used",
+            firing[0].message
+        );
+
+        // Fires once while over budget
+        assert!(alerts.check(&budget(1.2)).is_empty());
+
+        assert_eq!(vec![Status::Resolved], statuses(alerts.check(&budget(0.0))));
+    }
+
+    #[test]
+    fn continuous_flow_alert() {
+        let mut alerts = alerts();
+
+        assert!(alerts.check(&usage(119.0)).is_empty());
+
+        let firing = alerts.check(&usage(120.0));
+        assert_eq!(vec![Status::Firing], statuses(firing.clone()));
+        assert_eq!("continuous_flow", firing[0].alert);
+        assert_eq!(
+            "Water has been flowing at Home for 120 minutes",
+            firing[0].message
+        );
+
+        // Fires once while the water keeps flowing
+        assert!(alerts.check(&usage(125.0)).is_empty());
+
+        assert_eq!(vec![Status::Resolved], statuses(alerts.check(&usage(0.0))));
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::readings;
+use crate::readings::Reading;
+use crate::readings::Readings;
+use crate::update::Update;
+
+use chrono::Utc;
+
+use std::collections::HashMap;
+
+/// Usage range when `range` is not given
+const DEFAULT_RANGE: chrono::Duration = chrono::Duration::hours(1);
+
+/// `/api/v1/usage` JSON, the usage windows received in the `range` query parameter, one hour
+/// by default, for the location with the name or id in the `location` parameter, or for every
+/// location
+pub fn usage(readings: &Readings, query: &HashMap<String, String>) -> Result<String> {
+    let range = match query.get("range") {
+        Some(range) => parse_range(range)?,
+        None => DEFAULT_RANGE,
+    };
+
+    if range > readings::USAGE_RETENTION {
+        return Err(anyhow!(
+            "range {} is longer than the {} days of usage kept",
+            query["range"],
+            readings::USAGE_RETENTION.num_days()
+        ));
+    }
+
+    let location = query.get("location");
+
+    let usage: Vec<Reading> = readings
+        .usage_since(Utc::now() - range)
+        .into_iter()
+        .filter(|reading| match (location, &reading.update) {
+            (None, _) => true,
+            (
+                Some(wanted),
+                Update::Usage {
+                    location,
+                    location_id,
+                    ..
+                },
+            ) => wanted == location || wanted == location_id,
+            _ => false,
+        })
+        .collect();
+
+    Ok(serde_json::to_string(&usage)?)
+}
+
+/// `/api/v1/devices` JSON, the latest status of each bridge and sensor
+pub fn devices(readings: &Readings) -> Result<String> {
+    let devices: Vec<Reading> = readings
+        .latest()
+        .into_iter()
+        .filter(|reading| {
+            matches!(
+                reading.update,
+                Update::Bridge { .. } | Update::Sensor { .. }
+            )
+        })
+        .collect();
+
+    Ok(serde_json::to_string(&devices)?)
+}
+
+/// Parse a range like "90s", "30m", "24h", or "7d"
+fn parse_range(range: &str) -> Result<chrono::Duration> {
+    let invalid = || anyhow!("Invalid range {}, use a number with s, m, h, or d", range);
+
+    let unit = range.chars().last().ok_or_else(invalid)?;
+    let amount: i64 = range[..range.len() - unit.len_utf8()]
+        .parse()
+        .with_context(invalid)?;
+
+    if amount < 0 {
+        return Err(invalid());
+    }
+
+    match unit {
+        's' => Ok(chrono::Duration::seconds(amount)),
+        'm' => Ok(chrono::Duration::minutes(amount)),
+        'h' => Ok(chrono::Duration::hours(amount)),
+        'd' => Ok(chrono::Duration::days(amount)),
+        _ => Err(invalid()),
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::anyhow;
+use anyhow::Result;
+
+/// What to do after loading the configuration
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Command {
+    /// Run the exporter
+    #[default]
+    Run,
+    /// Check that a running exporter is healthy
+    Healthcheck,
+    /// Check the configured credentials authenticate
+    AuthTest,
+    /// Run one usage query and print the results
+    Query,
+    /// Write historical usage for each sensor to files
+    Export,
+    /// Print a Grafana dashboard for the exporter's metrics
+    GrafanaDashboard,
+    /// Turn away mode on or off for a location
+    AwayMode,
+    /// List, create, update, or delete budgets
+    Budget,
+}
+
+/// What the `budget` command does
+#[derive(Clone, Debug, PartialEq)]
+pub enum BudgetAction {
+    List,
+    Create,
+    Update,
+    Delete,
+}
+
+/// Options for the `budget` command
+#[derive(Clone, Debug, Default)]
+pub struct BudgetArguments {
+    pub action: Option<BudgetAction>,
+    /// Sensor the budget belongs to, `list` lists every sensor's budgets without one
+    pub device_id: Option<String>,
+    /// Budget to update or delete
+    pub budget_id: Option<String>,
+    pub name: Option<String>,
+    /// daily, weekly, or monthly
+    pub period: Option<String>,
+    /// Budget in gallons
+    pub value: Option<String>,
+    /// Comma separated thresholds in gallons
+    pub thresholds: Option<String>,
+}
+
+/// Options for the `query` and `export` commands
+#[derive(Clone, Debug, Default)]
+pub struct QueryArguments {
+    pub device_id: Option<String>,
+    /// Start of the query in the device's local time
+    pub since: Option<String>,
+    /// End of the query in the device's local time, defaults to now
+    pub until: Option<String>,
+    /// Bucket name, MIN, HR, DAY, MON, or YR
+    pub bucket: Option<String>,
+    /// Output format, json or csv for `query`, csv or parquet for `export`
+    pub format: Option<String>,
+    /// Directory `export` writes files to
+    pub output: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Arguments {
+    pub command: Command,
+    /// Path to the configuration file
+    pub configuration: Option<String>,
+    /// Path to a state snapshot to resume from
+    pub restore_state: Option<String>,
+    pub query: QueryArguments,
+    /// Volume units for `grafana-dashboard`, liters or gallons
+    pub units: Option<String>,
+    /// Location id or name for `away-mode`
+    pub location: Option<String>,
+    /// `on` or `off` for `away-mode`
+    pub away_mode: Option<String>,
+    pub budget: BudgetArguments,
+}
+
+impl Arguments {
+    /// Parse arguments from the environment.
+    pub fn parse() -> Result<Self> {
+        Arguments::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut arguments = Arguments::default();
+        let mut args = args.into_iter().peekable();
+
+        if let Some(command) = args.peek().and_then(|arg| command(arg)) {
+            arguments.command = command;
+            args.next();
+        }
+
+        if arguments.command == Command::Budget {
+            let action = args
+                .next()
+                .ok_or_else(|| anyhow!("budget requires list, create, update, or delete"))?;
+
+            arguments.budget.action = Some(budget_action(&action)?);
+        }
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--restore-state" => {
+                    let file = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--restore-state requires a file"))?;
+
+                    arguments.restore_state = Some(file);
+                }
+                "--since" | "--until" | "--bucket" | "--format" | "--output"
+                    if matches!(arguments.command, Command::Query | Command::Export) =>
+                {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("{} requires a value", arg))?;
+
+                    let option = match arg.as_str() {
+                        "--since" => &mut arguments.query.since,
+                        "--until" => &mut arguments.query.until,
+                        "--bucket" => &mut arguments.query.bucket,
+                        "--format" => &mut arguments.query.format,
+                        _ => &mut arguments.query.output,
+                    };
+
+                    *option = Some(value);
+                }
+                "--name" | "--period" | "--value" | "--thresholds"
+                    if arguments.command == Command::Budget =>
+                {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("{} requires a value", arg))?;
+
+                    let option = match arg.as_str() {
+                        "--name" => &mut arguments.budget.name,
+                        "--period" => &mut arguments.budget.period,
+                        "--value" => &mut arguments.budget.value,
+                        _ => &mut arguments.budget.thresholds,
+                    };
+
+                    *option = Some(value);
+                }
+                "--units" if arguments.command == Command::GrafanaDashboard => {
+                    let units = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--units requires liters or gallons"))?;
+
+                    arguments.units = Some(units);
+                }
+                _ if arg.starts_with("--") => {
+                    return Err(anyhow!("Unknown option {}", arg));
+                }
+                _ => {
+                    if arguments.configuration.is_none() {
+                        arguments.configuration = Some(arg);
+                    } else if arguments.command == Command::Query
+                        && arguments.query.device_id.is_none()
+                    {
+                        arguments.query.device_id = Some(arg);
+                    } else if arguments.command == Command::AwayMode && arguments.location.is_none()
+                    {
+                        arguments.location = Some(arg);
+                    } else if arguments.command == Command::AwayMode
+                        && arguments.away_mode.is_none()
+                    {
+                        arguments.away_mode = Some(arg);
+                    } else if arguments.command == Command::Budget
+                        && arguments.budget.device_id.is_none()
+                    {
+                        arguments.budget.device_id = Some(arg);
+                    } else if arguments.command == Command::Budget
+                        && arguments.budget.budget_id.is_none()
+                    {
+                        arguments.budget.budget_id = Some(arg);
+                    } else {
+                        return Err(anyhow!("Unexpected argument {}", arg));
+                    }
+                }
+            }
+        }
+
+        Ok(arguments)
+    }
+}
+
+fn command(arg: &str) -> Option<Command> {
+    match arg {
+        "healthcheck" => Some(Command::Healthcheck),
+        "auth-test" => Some(Command::AuthTest),
+        "query" => Some(Command::Query),
+        "export" => Some(Command::Export),
+        "grafana-dashboard" => Some(Command::GrafanaDashboard),
+        "away-mode" => Some(Command::AwayMode),
+        "budget" => Some(Command::Budget),
+        _ => None,
+    }
+}
+
+fn budget_action(arg: &str) -> Result<BudgetAction> {
+    match arg {
+        "list" => Ok(BudgetAction::List),
+        "create" => Ok(BudgetAction::Create),
+        "update" => Ok(BudgetAction::Update),
+        "delete" => Ok(BudgetAction::Delete),
+        _ => Err(anyhow!(
+            "Unknown budget action {}, use list, create, update, or delete",
+            arg
+        )),
+    }
+}
@@ -0,0 +1,32 @@
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::client::Client;
+use crate::configuration::Configuration;
+
+/// Authenticate with the configured credentials and look up the user unless `user_id` is
+/// configured, without touching any saved tokens, so the credentials can be checked before
+/// deploying.
+pub async fn run(configuration: &Configuration) -> Result<()> {
+    let client = Client::new(configuration);
+
+    let (token, _) = client
+        .access_token(&configuration.username(), &configuration.password())
+        .await
+        .context("Check client_id, secret_id, username, and password")?;
+
+    match configuration.user_id() {
+        Some(user_id) => println!("Authenticated, polling configured user {}", user_id),
+        None => {
+            let user_id = client
+                .user_id(&token.access_token)
+                .await
+                .context("Authenticated but unable to look up the user, set user_id")?;
+
+            println!("Authenticated as user {}", user_id);
+        }
+    }
+    println!("Access token expires in {}s", token.expires_in);
+
+    Ok(())
+}
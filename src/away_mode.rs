@@ -0,0 +1,53 @@
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::arguments::Arguments;
+use crate::configuration::Configuration;
+use crate::device::Device;
+use crate::flume_builder::FlumeBuilder;
+
+/// Turn away mode on or off for a location given by id or name, so it can be switched from cron
+/// or shell automations
+pub async fn run(configuration: &Configuration, arguments: &Arguments) -> Result<()> {
+    let location = arguments
+        .location
+        .as_ref()
+        .ok_or_else(|| anyhow!("away-mode requires a location id or name"))?;
+    let away_mode = match arguments.away_mode.as_deref() {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return Err(anyhow!("away-mode requires on or off")),
+    };
+
+    let mut flume = FlumeBuilder::from_configuration(configuration.clone())
+        .build()
+        .await?;
+
+    let user_id = flume.user_id().await?;
+
+    let location_id = flume
+        .devices(user_id)
+        .await?
+        .into_iter()
+        .find_map(|device| {
+            let (name, id) = match device {
+                Device::Bridge(b) => (b.location, b.location_id),
+                Device::Sensor(s) => (s.location, s.location_id),
+            };
+
+            (!id.is_empty() && (id == *location || name == *location)).then_some(id)
+        })
+        .ok_or_else(|| anyhow!("No devices found at location {}", location))?;
+
+    flume
+        .set_away_mode(user_id, &location_id, away_mode)
+        .await?;
+
+    println!(
+        "Turned away mode {} for location {}",
+        if away_mode { "on" } else { "off" },
+        location_id
+    );
+
+    Ok(())
+}
@@ -1,28 +1,57 @@
-use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
 
+use chrono::DateTime;
+use chrono::FixedOffset;
+
 use crate::client;
 
+use tracing::warn;
+
 use std::convert::TryFrom;
 
+/// A bridge with its location and last seen time parsed
 pub struct Bridge {
+    pub id: String,
     pub location: String,
+    pub location_id: String,
     pub connected: bool,
+    pub last_seen: DateTime<FixedOffset>,
     pub product: String,
+    pub firmware_version: Option<String>,
+    pub hardware_revision: Option<String>,
 }
 
 impl TryFrom<client::Bridge> for Bridge {
     type Error = anyhow::Error;
 
     fn try_from(bridge: client::Bridge) -> Result<Self> {
-        let location = bridge
-            .location
-            .ok_or_else(|| anyhow!("Fetch devices with location"))?;
+        // Without a location the bridge is labeled by its id
+        let (location, location_id) = match bridge.location {
+            Some(location) => (location.name, location.id.to_string()),
+            None => {
+                warn!(
+                    device_id = bridge.id,
+                    "Bridge has no location, labeling it by device id"
+                );
+
+                (bridge.id.clone(), String::new())
+            }
+        };
+
+        let last_seen = DateTime::parse_from_rfc3339(&bridge.last_seen).with_context(|| {
+            format!("Unable to parse bridge last seen time {}", bridge.last_seen)
+        })?;
 
         Ok(Bridge {
-            location: location.name,
+            id: bridge.id,
+            location,
+            location_id,
             connected: bridge.connected,
+            last_seen,
             product: bridge.product,
+            firmware_version: bridge.firmware_version,
+            hardware_revision: bridge.hardware_revision,
         })
     }
 }
@@ -0,0 +1,179 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::arguments::BudgetAction;
+use crate::arguments::BudgetArguments;
+use crate::client::Budget;
+use crate::client::BudgetChange;
+use crate::client::BudgetPeriod;
+use crate::configuration::Configuration;
+use crate::device::Device;
+use crate::flume::Flume;
+use crate::flume_builder::FlumeBuilder;
+use crate::sensor::Sensor;
+
+use serde::Serialize;
+
+/// One budget printed by `budget list`
+#[derive(Serialize)]
+struct Row {
+    device_id: String,
+    location: String,
+    #[serde(flatten)]
+    budget: Budget,
+}
+
+/// List, create, update, or delete budgets so they can be managed from scripts instead of only
+/// in the Flume app
+pub async fn run(configuration: &Configuration, arguments: &BudgetArguments) -> Result<()> {
+    let action = arguments
+        .action
+        .as_ref()
+        .ok_or_else(|| anyhow!("budget requires list, create, update, or delete"))?;
+
+    let mut flume = FlumeBuilder::from_configuration(configuration.clone())
+        .build()
+        .await?;
+
+    let user_id = flume.user_id().await?;
+
+    match action {
+        BudgetAction::List => {
+            let rows = list(&mut flume, user_id, arguments.device_id.as_deref()).await?;
+
+            println!("{}", serde_json::to_string_pretty(&rows)?);
+        }
+        BudgetAction::Create => {
+            let device_id = device_id(arguments)?;
+            let budget = change(arguments)?;
+
+            if budget.name.is_none() || budget.period.is_none() || budget.value.is_none() {
+                return Err(anyhow!(
+                    "budget create requires --name, --period, and --value"
+                ));
+            }
+
+            flume.create_budget(user_id, device_id, &budget).await?;
+
+            println!("Created budget for {}", device_id);
+        }
+        BudgetAction::Update => {
+            let device_id = device_id(arguments)?;
+            let budget_id = budget_id(arguments)?;
+            let budget = change(arguments)?;
+
+            flume
+                .update_budget(user_id, device_id, budget_id, &budget)
+                .await?;
+
+            println!("Updated budget {} for {}", budget_id, device_id);
+        }
+        BudgetAction::Delete => {
+            let device_id = device_id(arguments)?;
+            let budget_id = budget_id(arguments)?;
+
+            flume.delete_budget(user_id, device_id, budget_id).await?;
+
+            println!("Deleted budget {} for {}", budget_id, device_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Budgets of each sensor, or only of `device_id`
+async fn list(flume: &mut Flume, user_id: i64, device_id: Option<&str>) -> Result<Vec<Row>> {
+    let sensors: Vec<Sensor> = flume
+        .devices(user_id)
+        .await?
+        .into_iter()
+        .filter_map(|device| match device {
+            Device::Sensor(s) => Some(s),
+            Device::Bridge(_) => None,
+        })
+        .filter(|s| device_id.is_none_or(|id| id == s.sensor.id))
+        .collect();
+
+    let mut rows = Vec::new();
+
+    for sensor in sensors {
+        for budget in flume.budgets(user_id, &sensor).await? {
+            rows.push(Row {
+                device_id: sensor.sensor.id.clone(),
+                location: sensor.location.clone(),
+                budget,
+            });
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Budget fields given as options
+fn change(arguments: &BudgetArguments) -> Result<BudgetChange> {
+    let value = arguments
+        .value
+        .as_deref()
+        .map(|value| {
+            value
+                .parse()
+                .with_context(|| format!("Invalid --value {}", value))
+        })
+        .transpose()?;
+
+    Ok(BudgetChange {
+        name: arguments.name.clone(),
+        period: arguments.period.as_deref().map(period).transpose()?,
+        value,
+        thresholds: arguments
+            .thresholds
+            .as_deref()
+            .map(thresholds)
+            .transpose()?,
+    })
+}
+
+fn device_id(arguments: &BudgetArguments) -> Result<&str> {
+    arguments
+        .device_id
+        .as_deref()
+        .ok_or_else(|| anyhow!("budget requires a device id"))
+}
+
+fn budget_id(arguments: &BudgetArguments) -> Result<u64> {
+    let budget_id = arguments
+        .budget_id
+        .as_ref()
+        .ok_or_else(|| anyhow!("budget requires a budget id"))?;
+
+    budget_id
+        .parse()
+        .with_context(|| format!("Invalid budget id {}", budget_id))
+}
+
+/// Parse a budget period name
+fn period(name: &str) -> Result<BudgetPeriod> {
+    match name.to_lowercase().as_str() {
+        "daily" => Ok(BudgetPeriod::DAILY),
+        "weekly" => Ok(BudgetPeriod::WEEKLY),
+        "monthly" => Ok(BudgetPeriod::MONTHLY),
+        _ => Err(anyhow!(
+            "Unknown period {}, use daily, weekly, or monthly",
+            name
+        )),
+    }
+}
+
+/// Parse comma separated thresholds in gallons
+fn thresholds(thresholds: &str) -> Result<Vec<u64>> {
+    thresholds
+        .split(',')
+        .map(|threshold| {
+            threshold
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid threshold {}", threshold))
+        })
+        .collect()
+}
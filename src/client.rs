@@ -3,58 +3,422 @@ use anyhow::Context;
 use anyhow::Result;
 
 use crate::configuration::Configuration;
+use crate::marked;
+use crate::openmetrics::Exemplar;
+use crate::rate_limiter::RateLimiter;
+use crate::request_window;
+use crate::request_window::RequestWindow;
+use crate::response_spool;
 
 use lazy_static::lazy_static;
 
-use log::debug;
+use tracing::debug;
+use tracing::field;
+use tracing::instrument;
+use tracing::warn;
+use tracing::Span;
 
-use prometheus::register_histogram_vec;
-use prometheus::register_int_counter_vec;
+use reqwest::Method;
+
+use prometheus::proto::Metric;
+use prometheus::proto::MetricFamily;
+use prometheus::HistogramOpts;
+use prometheus::HistogramTimer;
 use prometheus::HistogramVec;
 use prometheus::IntCounterVec;
+use prometheus::IntGaugeVec;
+use prometheus::Opts;
+use prometheus::Registry;
 
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 
 use std::collections::HashMap;
-use std::time::Instant;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use uuid::Uuid;
 
 lazy_static! {
-    static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
-        "flume_water_http_requests_total",
-        "Number of HTTP requests made to the Flume API",
+    static ref REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "flume_water_http_requests_total",
+            "Number of HTTP requests made to the Flume API",
+        ),
         &["request_name"],
     )
     .unwrap();
-    static ref ERRORS: IntCounterVec = register_int_counter_vec!(
-        "flume_water_http_request_errors_total",
-        "Number of HTTP request errors returned by the Flume API",
+    static ref ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "flume_water_http_request_errors_total",
+            "Number of HTTP request errors returned by the Flume API",
+        ),
         &["request_name", "error_type"],
     )
     .unwrap();
-    static ref DURATIONS: HistogramVec = register_histogram_vec!(
-        "flume_water_http_request_duration_seconds",
-        "Flume API request durations",
+    static ref ERROR_STATUSES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "flume_water_http_request_error_statuses_total",
+            "Number of failed Flume API requests by HTTP status",
+        ),
+        &["request_name", "status"],
+    )
+    .unwrap();
+    static ref UP: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("flume_water_api_up", "Last Flume API request succeeded"),
+        &["request_name"],
+    )
+    .unwrap();
+    static ref DURATIONS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            DURATIONS_NAME,
+            "Flume API request durations",
+        )
+        .buckets(duration_buckets()),
         &["request_name"],
     )
     .unwrap();
+    /// Latest exemplar in each request duration bucket by request name, the +Inf bucket is last
+    static ref DURATION_EXEMPLARS: Mutex<HashMap<String, Vec<Option<Exemplar>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Add the Flume API request metrics to `registry`
+pub(crate) fn register(registry: &Registry) -> Result<()> {
+    registry.register(Box::new(REQUESTS.clone()))?;
+    registry.register(Box::new(ERRORS.clone()))?;
+    registry.register(Box::new(ERROR_STATUSES.clone()))?;
+    registry.register(Box::new(UP.clone()))?;
+    registry.register(Box::new(DURATIONS.clone()))?;
+
+    Ok(())
+}
+
+/// Exemplar for `bucket` of the request duration histogram `metric`, linking it to the trace of
+/// the latest request it counted
+pub(crate) fn duration_exemplar(
+    family: &MetricFamily,
+    metric: &Metric,
+    bucket: usize,
+) -> Option<Exemplar> {
+    if family.get_name() != DURATIONS_NAME {
+        return None;
+    }
+
+    let request_name = metric
+        .get_label()
+        .iter()
+        .find(|label| label.get_name() == "request_name")?
+        .get_value();
+
+    DURATION_EXEMPLARS
+        .lock()
+        .unwrap()
+        .get(request_name)?
+        .get(bucket)?
+        .clone()
+}
+
+/// Identifies the exporter to Flume support when diagnosing API problems
+const USER_AGENT: &str = concat!(
+    "flume_water_exporter/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/drbrain/flume_water_exporter)"
+);
+
+const DURATIONS_NAME: &str = "flume_water_http_request_duration_seconds";
+
+/// Buckets for the request duration histogram, set before the first request
+static DURATION_BUCKETS: OnceLock<Vec<f64>> = OnceLock::new();
+
+fn duration_buckets() -> Vec<f64> {
+    DURATION_BUCKETS
+        .get()
+        .cloned()
+        .unwrap_or_else(|| prometheus::DEFAULT_BUCKETS.to_vec())
+}
+
+/// Record the duration of a `request_name` request timed by `timer`.  When spans are exported
+/// the request's trace is kept as the exemplar of the bucket it landed in.
+fn observe_duration(request_name: &str, timer: HistogramTimer) -> f64 {
+    let duration = timer.stop_and_record();
+
+    #[cfg(feature = "otlp")]
+    let trace_context = crate::otlp_traces::current_trace_context();
+    #[cfg(not(feature = "otlp"))]
+    let trace_context: Option<(String, String)> = None;
+
+    if let Some((trace_id, span_id)) = trace_context {
+        let buckets = duration_buckets();
+        let bucket = buckets
+            .iter()
+            .position(|upper_bound| duration <= *upper_bound)
+            .unwrap_or(buckets.len());
+        let timestamp = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        DURATION_EXEMPLARS
+            .lock()
+            .unwrap()
+            .entry(request_name.to_string())
+            .or_insert_with(|| vec![None; buckets.len() + 1])[bucket] = Some(Exemplar {
+            labels: vec![("trace_id", trace_id), ("span_id", span_id)],
+            value: duration,
+            timestamp,
+        });
+    }
+
+    duration
+}
+
+/// Use `buckets` for the request duration histogram instead of the default Prometheus buckets.
+///
+/// Must be called before the first request.  The histogram is shared by the whole process, so
+/// calling this again with different buckets is an error.
+pub fn set_duration_buckets(buckets: Vec<f64>) -> Result<()> {
+    if buckets.is_empty() {
+        return Err(anyhow!("duration_buckets must not be empty"));
+    }
+
+    if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(anyhow!(
+            "duration_buckets must be in increasing order, got {:?}",
+            buckets
+        ));
+    }
+
+    let set = DURATION_BUCKETS.get_or_init(|| buckets.clone());
+
+    if *set != buckets {
+        return Err(anyhow!(
+            "duration_buckets are already set to {:?} for this process, got {:?}",
+            set,
+            buckets
+        ));
+    }
+
+    Ok(())
+}
+
+/// The Flume API rejected a request because the rate limit was exceeded
+#[derive(Debug)]
+pub struct RateLimited;
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rate limited by the Flume API")
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// The Flume API returned an unsuccessful response
+#[derive(Debug)]
+pub struct RequestFailed {
+    /// HTTP status from the response
+    pub http_code: u64,
+    pub message: String,
+    /// Further details about the failure, when Flume gives any
+    pub detailed: serde_json::Value,
+}
+
+impl std::fmt::Display for RequestFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request error {}", self.message)
+    }
+}
+
+impl std::error::Error for RequestFailed {}
+
+/// Why Flume rejected a login, attached as context so the exporter can exit with a distinct code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CredentialError {
+    /// The client_id or secret_id is wrong
+    InvalidClient,
+    /// The username or password is wrong
+    InvalidUser,
+    /// Flume locked the account after too many failed logins
+    AccountLocked,
+}
+
+impl CredentialError {
+    /// The credential problem a failed `/oauth/token` response describes by the OAuth `error`
+    /// code in its details, if Flume gives one
+    fn from_failure(failure: &RequestFailed) -> Option<Self> {
+        let details = match &failure.detailed {
+            serde_json::Value::Array(details) => details.iter().collect(),
+            detail => vec![detail],
+        };
+
+        details
+            .into_iter()
+            .filter_map(|detail| detail.get("error")?.as_str())
+            .find_map(|code| match code {
+                "invalid_client" => Some(CredentialError::InvalidClient),
+                "invalid_grant" => Some(CredentialError::InvalidUser),
+                "account_locked" => Some(CredentialError::AccountLocked),
+                _ => None,
+            })
+    }
+
+    /// Process exit code for this problem: 3 for the client, 4 for the user, 5 for a locked
+    /// account
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CredentialError::InvalidClient => 3,
+            CredentialError::InvalidUser => 4,
+            CredentialError::AccountLocked => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            CredentialError::InvalidClient => "Flume rejected the client_id or secret_id",
+            CredentialError::InvalidUser => "Flume rejected the username or password",
+            CredentialError::AccountLocked => {
+                "Flume locked the account after too many failed logins"
+            }
+        };
+
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for CredentialError {}
+
+/// Attach the credential problem to a failed login, if the Flume error payload identifies one
+fn credential_error(error: anyhow::Error) -> anyhow::Error {
+    let credential = error
+        .downcast_ref::<RequestFailed>()
+        .and_then(CredentialError::from_failure);
+
+    match credential {
+        Some(credential) => marked::mark(error, credential),
+        None => error,
+    }
+}
+
+/// Mark a failed token request as an authentication failure when Flume rejected it.  Timeouts,
+/// connection errors, and server errors are left unmarked so they are retried like any other
+/// request.
+fn authentication_error(error: anyhow::Error) -> anyhow::Error {
+    let rejected = error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<RequestFailed>(),
+            Some(e) if e.http_code == 400 || e.http_code == 401
+        )
+    });
+
+    if rejected {
+        marked::mark(error, ErrorKind::Authentication)
+    } else {
+        error
+    }
+}
+
+/// Kinds of errors the exporter may be configured to exit on, attached with `marked::mark`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// Flume rejected a login or token refresh
+    Authentication,
+    /// A response could not be deserialized
+    Deserialize,
+    /// Fetching budgets failed
+    Budgets,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            ErrorKind::Authentication => "Authentication failed",
+            ErrorKind::Deserialize => "Invalid response",
+            ErrorKind::Budgets => "Fetching budgets failed",
+        };
+
+        write!(f, "{}", message)
+    }
 }
 
-const API_URI: &str = "https://api.flumewater.com";
+impl std::error::Error for ErrorKind {}
 
+/// Envelope around every Flume API response with the endpoint's `data` items
 #[derive(Clone, Deserialize, Serialize)]
-pub struct Response {
+pub struct Response<T> {
     pub success: bool,
     pub code: u64,
     pub message: String,
     pub http_code: u64,
     pub http_message: String,
     pub detailed: serde_json::Value,
-    pub data: Vec<Data>,
+    pub data: Vec<T>,
     pub count: u64,
     pub pagination: Option<bool>,
 }
 
+impl Response<serde_json::Value> {
+    /// Deserialize the data items as `T`, only once the response is known to be successful since
+    /// failed responses don't carry the endpoint's data
+    fn typed<T: DeserializeOwned>(self) -> serde_json::Result<Response<T>> {
+        let data = self
+            .data
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<serde_json::Result<_>>()?;
+
+        Ok(Response {
+            success: self.success,
+            code: self.code,
+            message: self.message,
+            http_code: self.http_code,
+            http_message: self.http_message,
+            detailed: self.detailed,
+            data,
+            count: self.count,
+            pagination: self.pagination,
+        })
+    }
+}
+
+/// Response from `/oauth/token`
+pub type TokenResponse = Response<Token>;
+
+/// Response from `/me`
+pub type UserResponse = Response<User>;
+
+/// Response from `/users/{user_id}/devices`
+pub type DevicesResponse = Response<Item<Device>>;
+
+/// Response from `/users/{user_id}/devices/{device_id}/budgets`
+pub type BudgetsResponse = Response<Item<Budget>>;
+
+/// Response from `PATCH /users/{user_id}/locations/{location_id}`
+pub type LocationResponse = Response<serde_json::Value>;
+
+/// Response from creating, updating, or deleting a budget
+pub type BudgetChangeResponse = Response<serde_json::Value>;
+
+/// Response from `/users/{user_id}/devices/{device_id}/rules/usage-alerts`
+pub type NotificationRulesResponse = Response<Item<NotificationRule>>;
+
+/// Response from `/users/{user_id}/devices/{device_id}/query`, results by query request id
+pub type QueryResponse = Response<HashMap<String, Vec<QueryResult>>>;
+
+/// Body of a location update, only the away mode can be changed
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LocationUpdate {
+    pub away_mode: bool,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AccessToken {
     pub grant_type: String,
@@ -64,32 +428,114 @@ pub struct AccessToken {
     pub password: String,
 }
 
+/// One item of a list response, `Unknown` when it can't be parsed, such as a device missing an
+/// essential field, so the other items are still usable
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
-pub enum Data {
-    Bridge(Bridge),
-    Budget(Budget),
-    Sensor(Sensor),
-    Token(Token),
-    User(User),
-    QueryResults(HashMap<String, Vec<QueryResult>>),
+pub enum Item<T> {
+    Known(T),
+    Unknown(serde_json::Value),
 }
 
-#[derive(Clone, Debug)]
+/// A device as returned by the API, see `crate::device::Device` for a parsed device.
+///
+/// Devices are told apart by their `type`, 1 for bridges and 2 for sensors, or by their `product`
+/// when there is no `type`.  Devices of other types or products are `Unknown` so new Flume
+/// products don't prevent known devices from being parsed.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
 pub enum Device {
     Bridge(Bridge),
     Sensor(Sensor),
+    Unknown {
+        id: String,
+        product: String,
+        #[serde(rename = "type")]
+        device_type: Option<u64>,
+    },
 }
 
+impl Device {
+    pub fn id(&self) -> &str {
+        match self {
+            Device::Bridge(b) => &b.id,
+            Device::Sensor(s) => &s.id,
+            Device::Unknown { id, .. } => id,
+        }
+    }
+}
+
+/// Device type numbers from the API
+const BRIDGE_TYPE: u64 = 1;
+const SENSOR_TYPE: u64 = 2;
+
+impl<'de> Deserialize<'de> for Device {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+
+        // Budgets also have a type, but it is not a number
+        let device_type = value.get("type").and_then(serde_json::Value::as_u64);
+        let product = value.get("product").and_then(serde_json::Value::as_str);
+
+        if device_type.is_none() && product.is_none() {
+            return Err(D::Error::custom("not a device, missing type and product"));
+        }
+
+        let product = product.unwrap_or_default().to_string();
+
+        let is_bridge = match device_type {
+            Some(t) => t == BRIDGE_TYPE,
+            None => product.contains("bridge"),
+        };
+        let is_sensor = match device_type {
+            Some(t) => t == SENSOR_TYPE,
+            None => product.contains("sensor"),
+        };
+
+        if is_bridge {
+            Bridge::deserialize(value)
+                .map(Device::Bridge)
+                .map_err(D::Error::custom)
+        } else if is_sensor {
+            Sensor::deserialize(value)
+                .map(Device::Sensor)
+                .map_err(D::Error::custom)
+        } else {
+            let id = match value.get("id") {
+                Some(serde_json::Value::String(id)) => id.clone(),
+                Some(id) => id.to_string(),
+                None => String::new(),
+            };
+
+            Ok(Device::Unknown {
+                id,
+                product,
+                device_type,
+            })
+        }
+    }
+}
+
+/// Fields the exporter doesn't use are optional so Flume adding or removing them doesn't break
+/// deserialization
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Bridge {
     pub id: String,
     pub last_seen: String,
     pub connected: bool,
-    pub supports_ap: bool,
+    #[serde(default)]
+    pub supports_ap: Option<bool>,
     pub product: String,
     pub user: Option<User>,
     pub location: Option<Location>,
+    /// Firmware version, only present if the devices endpoint returns it
+    #[serde(default, alias = "firmware", alias = "fw_version")]
+    pub firmware_version: Option<String>,
+    /// Hardware revision, only present if the devices endpoint returns it
+    #[serde(default, alias = "hardware_version", alias = "hw_version")]
+    pub hardware_revision: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -107,6 +553,22 @@ pub struct Budget {
     pub actual: f64,
 }
 
+/// Body of a budget creation or update, fields left out of an update are unchanged
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BudgetChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub period: Option<BudgetPeriod>,
+    /// Always in gallons
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+    /// Always in gallons
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<Vec<u64>>,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum BudgetPeriod {
     DAILY,
@@ -114,36 +576,67 @@ pub enum BudgetPeriod {
     MONTHLY,
 }
 
-impl ToString for BudgetPeriod {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for BudgetPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            BudgetPeriod::DAILY => "daily".to_string(),
-            BudgetPeriod::WEEKLY => "weekly".to_string(),
-            BudgetPeriod::MONTHLY => "monthly".to_string(),
+            BudgetPeriod::DAILY => write!(f, "daily"),
+            BudgetPeriod::WEEKLY => write!(f, "weekly"),
+            BudgetPeriod::MONTHLY => write!(f, "monthly"),
         }
     }
 }
 
+/// A usage alert rule set up in the Flume app.  Only the id is required, the rest default when
+/// missing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NotificationRule {
+    pub id: u64,
+    #[serde(default)]
+    pub name: String,
+    /// Kind of usage the rule alerts on, such as high flow
+    #[serde(default, rename = "type", alias = "alert_type")]
+    pub rule_type: String,
+    /// Flow rate that trips the rule in the user's preferred units
+    #[serde(default, alias = "flow_rate")]
+    pub threshold: Option<f64>,
+    #[serde(default, alias = "active")]
+    pub enabled: bool,
+}
+
+/// Only the id, name, and timezone are required, the rest default when missing
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Location {
     pub id: u64,
     pub name: String,
+    #[serde(default)]
     pub primary_location: bool,
+    #[serde(default)]
     pub address: String,
+    #[serde(default)]
     pub address_2: String,
+    #[serde(default)]
     pub city: String,
+    #[serde(default)]
     pub state: String,
+    #[serde(default)]
     pub postal_code: String,
+    #[serde(default)]
     pub country: String,
     pub tz: String,
+    #[serde(default)]
     pub installation: String,
+    #[serde(default)]
     pub away_mode: bool,
-    pub usage_profile: UsageProfile,
+    #[serde(default)]
+    pub usage_profile: Option<UsageProfile>,
     pub user: Option<User>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct QueryResult {
+    /// Start of the bucket in the device's local time
+    #[serde(default)]
+    pub datetime: Option<String>,
     pub value: f64,
 }
 
@@ -152,6 +645,8 @@ pub struct Queries {
     queries: Vec<Query>,
 }
 
+/// A usage query for one device, see the [query
+/// documentation](https://flumetech.readme.io/reference/query-a-user-device)
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Query {
     pub request_id: String,
@@ -184,6 +679,7 @@ impl Default for Query {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum QueryBucket {
     MIN = 3,
@@ -193,6 +689,7 @@ pub enum QueryBucket {
     YR = 8,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum QueryOperation {
     SUM = 1,
@@ -202,12 +699,14 @@ pub enum QueryOperation {
     CNT = 5,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum QuerySortDirection {
     ASC,
     DESC,
 }
-#[allow(non_camel_case_types)]
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum QueryUnits {
     GALLONS,
@@ -224,17 +723,27 @@ pub struct RefreshToken {
     pub client_secret: String,
 }
 
+/// Fields the exporter doesn't need to label a sensor default when missing
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Sensor {
     pub id: String,
     pub bridge_id: String,
+    #[serde(default)]
     pub oriented: bool,
     pub last_seen: String,
+    #[serde(default)]
     pub connected: bool,
+    #[serde(default)]
     pub battery_level: String,
     pub product: String,
     pub user: Option<User>,
     pub location: Option<Location>,
+    /// Firmware version, only present if the devices endpoint returns it
+    #[serde(default, alias = "firmware", alias = "fw_version")]
+    pub firmware_version: Option<String>,
+    /// Hardware revision, only present if the devices endpoint returns it
+    #[serde(default, alias = "hardware_version", alias = "hw_version")]
+    pub hardware_revision: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -245,7 +754,9 @@ pub struct Token {
     pub refresh_token: String,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Unused by the exporter, every field defaults when missing
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct UsageProfile {
     id: u64,
     score: u64,
@@ -268,28 +779,42 @@ pub struct User {
     user_type: String,
 }
 
+/// Makes requests to the Flume API and records request metrics.
+///
+/// The client does not manage tokens, use `crate::flume::Flume` for that.
 #[derive(Clone)]
 pub struct Client {
     client: reqwest::Client,
+    api_url: String,
+    oauth_url: String,
+    request_window: RequestWindow,
+    rate_limiter: RateLimiter,
+    response_spool: Option<PathBuf>,
+    send_request_id: bool,
 
     client_id: String,
-    client_secret: String,
+    /// Shared between clones so a rotated secret is used by every `Flume`
+    client_secret: Arc<RwLock<String>>,
 }
 
 impl Client {
+    /// Client using the API key and timeouts from `configuration`
     pub fn new(configuration: &Configuration) -> Self {
-        let timeout = configuration.flume_timeout();
+        let connect_timeout = configuration.connect_timeout();
+        let request_timeout = configuration.request_timeout();
 
-        let mut default_headers = reqwest::header::HeaderMap::new();
-        default_headers.insert(
-            "Accept-Encoding",
-            reqwest::header::HeaderValue::from_static("application/json"),
-        );
+        let user_agent = match configuration.user_agent_suffix() {
+            Some(suffix) => format!("{} {}", USER_AGENT, suffix),
+            None => USER_AGENT.to_string(),
+        };
 
+        // Advertises and decodes gzip and brotli responses
         let client = reqwest::Client::builder()
-            .connect_timeout(timeout)
-            .timeout(timeout)
-            .default_headers(default_headers)
+            .user_agent(user_agent)
+            .connect_timeout(connect_timeout)
+            .timeout(request_timeout)
+            .gzip(true)
+            .brotli(true)
             .build()
             .expect("Could not build HTTP client");
 
@@ -298,46 +823,55 @@ impl Client {
 
         Client {
             client,
+            api_url: configuration.api_url(),
+            oauth_url: configuration.oauth_url(),
+            request_window: RequestWindow::default(),
+            rate_limiter: RateLimiter::new(configuration.requests_per_hour()),
+            response_spool: configuration.response_spool(),
+            send_request_id: configuration.send_request_id(),
 
             client_id,
-            client_secret,
+            client_secret: Arc::new(RwLock::new(client_secret)),
         }
     }
 
+    /// Use a rotated client secret for the token requests of this client and its clones
+    pub fn set_client_secret(&self, client_secret: String) {
+        *self.client_secret.write().unwrap() = client_secret;
+    }
+
+    /// Authenticate with a username and password
     pub async fn access_token(
-        &mut self,
+        &self,
         username: &str,
         password: &str,
-    ) -> Result<(Token, Instant)> {
-        let token_fetch_time = Instant::now();
+    ) -> Result<(Token, SystemTime)> {
+        let token_fetch_time = SystemTime::now();
 
         let request = AccessToken {
             grant_type: "password".to_string(),
             client_id: self.client_id.to_string(),
-            client_secret: self.client_secret.to_string(),
+            client_secret: self.client_secret.read().unwrap().clone(),
             username: username.to_string(),
             password: password.to_string(),
         };
 
         let body = serde_json::to_string(&request)?;
 
-        let response = self
+        let response: TokenResponse = self
             .post("/oauth/token", None, body, "authenticate")
             .await
-            .context("Authentication failed")?;
-
-        let token = match &response.data[0] {
-            Data::Token(t) => t.clone(),
-            _ => {
-                return Err(anyhow!(
-                    "Unexpected response type while requesting access token"
-                ));
-            }
-        };
+            .map_err(credential_error)
+            .map_err(authentication_error)?;
+
+        let token = first(response.data)
+            .ok_or_else(|| anyhow!("Missing token in response while requesting access token"))?;
 
         Ok((token, token_fetch_time))
     }
 
+    /// Budgets for a sensor
+    #[instrument(skip_all, fields(device_id = sensor_id))]
     pub async fn budgets(
         &self,
         access_token: &str,
@@ -346,30 +880,130 @@ impl Client {
     ) -> Result<Vec<Budget>> {
         let path = format!("/users/{}/devices/{}/budgets", user_id, sensor_id);
 
-        let response = self.get(&path, Some(access_token), "budgets").await?;
+        let response: BudgetsResponse = self.get(&path, Some(access_token), "budgets").await?;
+
+        Ok(known(response.data, "budget"))
+    }
+
+    /// Create a budget for a sensor
+    #[instrument(skip_all, fields(device_id = sensor_id))]
+    pub async fn create_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget: &BudgetChange,
+    ) -> Result<()> {
+        let path = format!("/users/{}/devices/{}/budgets", user_id, sensor_id);
+        let body = serde_json::to_string(budget)?;
+
+        let _: BudgetChangeResponse = self
+            .post(&path, Some(access_token), body, "create budget")
+            .await?;
+
+        Ok(())
+    }
+
+    /// Change the fields set in `budget` for budget `budget_id` of a sensor
+    #[instrument(skip_all, fields(device_id = sensor_id))]
+    pub async fn update_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget_id: u64,
+        budget: &BudgetChange,
+    ) -> Result<()> {
+        let path = format!(
+            "/users/{}/devices/{}/budgets/{}",
+            user_id, sensor_id, budget_id
+        );
+        let body = serde_json::to_string(budget)?;
+
+        let _: BudgetChangeResponse = self
+            .patch(&path, Some(access_token), body, "update budget")
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delete budget `budget_id` of a sensor
+    #[instrument(skip_all, fields(device_id = sensor_id))]
+    pub async fn delete_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget_id: u64,
+    ) -> Result<()> {
+        let path = format!(
+            "/users/{}/devices/{}/budgets/{}",
+            user_id, sensor_id, budget_id
+        );
+
+        let _: BudgetChangeResponse = self
+            .delete(&path, Some(access_token), "delete budget")
+            .await?;
 
-        response.data.iter().map(budget).collect()
+        Ok(())
     }
 
-    pub async fn devices(&mut self, access_token: &str, user_id: i64) -> Result<Vec<Device>> {
+    /// Usage alert rules for a sensor
+    #[instrument(skip_all, fields(device_id = sensor_id))]
+    pub async fn notification_rules(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+    ) -> Result<Vec<NotificationRule>> {
+        let path = format!(
+            "/users/{}/devices/{}/rules/usage-alerts",
+            user_id, sensor_id
+        );
+
+        let response: NotificationRulesResponse = self
+            .get(&path, Some(access_token), "notification rules")
+            .await?;
+
+        Ok(known(response.data, "notification rule"))
+    }
+
+    /// Bridges and sensors for a user, including their locations
+    pub async fn devices(&self, access_token: &str, user_id: i64) -> Result<Vec<Device>> {
         let path = format!("/users/{}/devices?location=true", user_id);
-        let response = self.get(&path, Some(access_token), "devices").await?;
+        let response: DevicesResponse = self.get(&path, Some(access_token), "devices").await?;
 
-        response.data.iter().map(device).collect()
+        Ok(known(response.data, "device"))
     }
 
-    pub async fn query_samples(
+    /// All results for one query, one per bucket unless the query has an operation
+    pub async fn query_results(
         &self,
         access_token: &str,
         user_id: i64,
         sensor_id: &str,
         query: Query,
-    ) -> Result<f64> {
-        let request_id = query.request_id.clone();
+    ) -> Result<Vec<QueryResult>> {
+        let results = self
+            .query_batch(access_token, user_id, sensor_id, vec![query])
+            .await?;
 
-        let queries = Queries {
-            queries: vec![query],
-        };
+        Ok(results.into_iter().next().unwrap_or_default())
+    }
+
+    /// All results for each of `queries` from one request, in the order of `queries`.  Each
+    /// query needs its own `request_id`.
+    #[instrument(skip_all, fields(device_id = sensor_id))]
+    pub async fn query_batch(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        queries: Vec<Query>,
+    ) -> Result<Vec<Vec<QueryResult>>> {
+        let request_ids: Vec<String> = queries.iter().map(|q| q.request_id.clone()).collect();
+
+        let queries = Queries { queries };
 
         let body = serde_json::to_string(&queries)?;
 
@@ -377,112 +1011,147 @@ impl Client {
 
         let path = format!("/users/{}/devices/{}/query", user_id, sensor_id);
 
-        let response = self.post(&path, Some(access_token), body, "query").await?;
-        let query_results = &response.data[0];
+        let response: QueryResponse = self.post(&path, Some(access_token), body, "query").await?;
 
-        let query_result = match query_results {
-            Data::QueryResults(q) => q,
-            _ => {
-                return Err(anyhow!("Unexpected response type querying sensor"));
-            }
-        };
+        let mut query_results = first(response.data).unwrap_or_default();
 
-        if let Some(results) = query_result.get(&request_id) {
-            if let Some(result) = results.get(0) {
-                Ok(result.value)
-            } else {
-                Ok(0.0)
-            }
-        } else {
-            Err(anyhow!("Missing query result {}", request_id))
-        }
+        request_ids
+            .iter()
+            .map(|request_id| {
+                query_results
+                    .remove(request_id)
+                    .ok_or_else(|| anyhow!("Missing query result {}", request_id))
+            })
+            .collect()
+    }
+
+    /// Turn away mode on or off for a location
+    #[instrument(skip_all, fields(location_id = location_id))]
+    pub async fn set_away_mode(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        location_id: &str,
+        away_mode: bool,
+    ) -> Result<()> {
+        let path = format!("/users/{}/locations/{}", user_id, location_id);
+        let body = serde_json::to_string(&LocationUpdate { away_mode })?;
+
+        let _: LocationResponse = self
+            .patch(&path, Some(access_token), body, "away mode")
+            .await?;
+
+        Ok(())
     }
 
-    pub async fn refresh_token(&self, refresh_token: &str) -> Result<(Token, Instant)> {
-        let token_fetch_time = Instant::now();
+    /// Exchange a refresh token for a new access token
+    pub async fn refresh_token(&self, refresh_token: &str) -> Result<(Token, SystemTime)> {
+        let token_fetch_time = SystemTime::now();
 
         let refresh_token = RefreshToken {
             grant_type: "refresh_token".to_string(),
             refresh_token: refresh_token.to_string(),
             client_id: self.client_id.clone(),
-            client_secret: self.client_secret.clone(),
+            client_secret: self.client_secret.read().unwrap().clone(),
         };
 
         let body = serde_json::to_string(&refresh_token)?;
 
-        let response = self
+        let response: TokenResponse = self
             .post("/oauth/token", None, body, "refresh token")
-            .await?;
+            .await
+            .map_err(authentication_error)?;
 
-        let token = match &response.data[0] {
-            Data::Token(t) => t.clone(),
-            _ => {
-                return Err(anyhow!("Unexpected response type while refreshing token"));
-            }
-        };
+        let token = first(response.data)
+            .ok_or_else(|| anyhow!("Missing token in response while refreshing token"))?;
 
         Ok((token, token_fetch_time))
     }
 
+    /// Id of the user the access token belongs to
     pub async fn user_id(&self, access_token: &str) -> Result<i64> {
-        let response = self.get("/me", Some(access_token), "user id").await?;
+        let response: UserResponse = self.get("/me", Some(access_token), "user id").await?;
 
-        match &response.data[0] {
-            Data::User(u) => Ok(u.id),
-            _ => Err(anyhow!("Could not find user in response")),
-        }
+        first(response.data)
+            .map(|user| user.id)
+            .ok_or_else(|| anyhow!("Could not find user in response"))
     }
 
-    async fn get(
+    async fn get<T: DeserializeOwned>(
         &self,
         path: &str,
         access_token: Option<&str>,
         request_name: &str,
-    ) -> Result<Response> {
-        let uri = format!("{}{}", API_URI, path);
-
-        debug!("GET {}", uri);
-        REQUESTS.with_label_values(&[request_name]).inc();
-        let timer = DURATIONS.with_label_values(&[request_name]).start_timer();
-
-        let builder = self.client.get(&uri).header("Accept", "application/json");
-
-        let builder = if let Some(access_token) = access_token {
-            builder.header("Authorization", format!("Bearer {}", access_token))
-        } else {
-            builder
-        };
+    ) -> Result<Response<T>> {
+        self.send(Method::GET, path, access_token, None, request_name)
+            .await
+    }
 
-        let response = builder
-            .send()
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        access_token: Option<&str>,
+        body: String,
+        request_name: &str,
+    ) -> Result<Response<T>> {
+        self.send(Method::POST, path, access_token, Some(body), request_name)
             .await
-            .with_context(|| format!("awaiting response from {}", uri));
+    }
 
-        timer.observe_duration();
+    async fn patch<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        access_token: Option<&str>,
+        body: String,
+        request_name: &str,
+    ) -> Result<Response<T>> {
+        self.send(Method::PATCH, path, access_token, Some(body), request_name)
+            .await
+    }
 
-        json_from(response, &uri, "GET", request_name).await
+    async fn delete<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        access_token: Option<&str>,
+        request_name: &str,
+    ) -> Result<Response<T>> {
+        self.send(Method::DELETE, path, access_token, None, request_name)
+            .await
     }
 
-    async fn post(
+    /// Send a request with `method` and `body` as JSON, if any
+    #[instrument(
+        skip(self, access_token, body),
+        fields(request_id = field::Empty, status = field::Empty, duration = field::Empty)
+    )]
+    async fn send<T: DeserializeOwned>(
         &self,
+        method: Method,
         path: &str,
         access_token: Option<&str>,
-        body: String,
+        body: Option<String>,
         request_name: &str,
-    ) -> Result<Response> {
-        let uri = format!("{}{}", API_URI, path);
+    ) -> Result<Response<T>> {
+        let uri = self.uri(path);
+        let request_id = new_request_id();
 
-        debug!("POST {}", uri);
+        self.rate_limiter.acquire().await;
 
-        REQUESTS.with_label_values(&[request_name]).inc();
+        debug!("{} {}", method, uri);
+        self.record_request(request_name);
         let timer = DURATIONS.with_label_values(&[request_name]).start_timer();
 
         let builder = self
             .client
-            .post(&uri)
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .body(body.to_string());
+            .request(method.clone(), &uri)
+            .header("Accept", "application/json");
+        let builder = match body {
+            Some(body) => builder
+                .header("Content-Type", "application/json")
+                .body(body),
+            None => builder,
+        };
+        let builder = self.request_id_header(builder, &request_id);
 
         let builder = if let Some(access_token) = access_token {
             builder.header("Authorization", format!("Bearer {}", access_token))
@@ -495,15 +1164,92 @@ impl Client {
             .await
             .with_context(|| format!("awaiting response from {}", uri));
 
-        timer.observe_duration();
+        Span::current().record("duration", observe_duration(request_name, timer));
+
+        record_up(
+            json_from(
+                response,
+                &uri,
+                method.as_str(),
+                request_name,
+                self.response_spool.as_deref(),
+            )
+            .await
+            .with_context(|| format!("{} request {}", request_name, request_id)),
+            request_name,
+        )
+    }
+
+    /// Send `request_id` to Flume in the `X-Request-Id` header when configured
+    fn request_id_header(
+        &self,
+        builder: reqwest::RequestBuilder,
+        request_id: &str,
+    ) -> reqwest::RequestBuilder {
+        if self.send_request_id {
+            builder.header("X-Request-Id", request_id)
+        } else {
+            builder
+        }
+    }
 
-        json_from(response, &uri, "POST", request_name).await
+    /// Token requests go to the OAuth URL, everything else to the API URL
+    fn uri(&self, path: &str) -> String {
+        if path.starts_with("/oauth/") {
+            format!("{}{}", self.oauth_url, path)
+        } else {
+            format!("{}{}", self.api_url, path)
+        }
     }
+
+    fn record_request(&self, request_name: &str) {
+        REQUESTS.with_label_values(&[request_name]).inc();
+
+        let last_hour = self.request_window.record();
+
+        if last_hour as f64 == request_window::HOURLY_LIMIT + 1.0 {
+            warn!(
+                "Made {} Flume API requests in the last hour, over the limit of {}, requests may be throttled",
+                last_hour,
+                request_window::HOURLY_LIMIT
+            );
+        }
+    }
+}
+
+/// A new id for one API request, recorded on the request's span so its logs and errors can be
+/// matched up
+fn new_request_id() -> String {
+    let request_id = Uuid::new_v4().to_string();
+
+    Span::current().record("request_id", request_id.as_str());
+
+    request_id
+}
+
+fn record_error_status(request_name: &str, status: u64) {
+    ERROR_STATUSES
+        .with_label_values(&[request_name, &status.to_string()])
+        .inc();
 }
 
-fn deserialize(body: &str, uri: &str, request_name: &str) -> Result<Response> {
-    let result =
-        serde_json::from_str(body).with_context(|| format!("deserialize response from {}", uri));
+fn record_up<T>(result: Result<Response<T>>, request_name: &str) -> Result<Response<T>> {
+    let up = if result.is_ok() { 1 } else { 0 };
+
+    UP.with_label_values(&[request_name]).set(up);
+
+    result
+}
+
+/// Parse `body` with `parse`, saving it to `spool` when it can't be parsed
+fn deserialize<T>(
+    body: &str,
+    uri: &str,
+    request_name: &str,
+    spool: Option<&Path>,
+    parse: impl FnOnce() -> serde_json::Result<T>,
+) -> Result<T> {
+    let result = parse().with_context(|| format!("deserialize response from {}", uri));
 
     match result {
         Ok(json) => Ok(json),
@@ -513,24 +1259,40 @@ fn deserialize(body: &str, uri: &str, request_name: &str) -> Result<Response> {
                 .with_label_values(&[request_name, "deserialize"])
                 .inc();
 
-            Err(e)
+            if let Some(spool) = spool {
+                response_spool::save(spool, request_name, body);
+            }
+
+            Err(marked::mark(e, ErrorKind::Deserialize))
         }
     }
 }
 
-fn budget(data: &Data) -> Result<Budget> {
-    match data {
-        Data::Budget(b) => Ok(b.clone()),
-        _ => Err(anyhow!("Unable to find budget in response")),
-    }
+/// The first data item, endpoints returning a single item return a one-item list
+fn first<T>(data: Vec<T>) -> Option<T> {
+    data.into_iter().next()
 }
 
-fn device(data: &Data) -> Result<Device> {
-    match data {
-        Data::Bridge(b) => Ok(Device::Bridge(b.clone())),
-        Data::Sensor(s) => Ok(Device::Sensor(s.clone())),
-        _ => Err(anyhow!("Unable to find device in response")),
-    }
+/// Known items, `Item::Unknown` items are skipped with a warning so one item Flume returns oddly
+/// doesn't fail the others
+fn known<T>(items: Vec<Item<T>>, item: &str) -> Vec<T> {
+    items
+        .into_iter()
+        .filter_map(|data| match data {
+            Item::Known(known) => Some(known),
+            Item::Unknown(value) => {
+                let id = value.get("id").map(|id| id.to_string()).unwrap_or_default();
+
+                warn!(
+                    id,
+                    "Skipping {} Flume returned that could not be parsed", item
+                );
+                debug!("Unparsed {}: {}", item, value);
+
+                None
+            }
+        })
+        .collect()
 }
 
 async fn extract_body(
@@ -538,7 +1300,7 @@ async fn extract_body(
     uri: &str,
     request_method: &str,
     request_name: &str,
-) -> Result<String> {
+) -> Result<(reqwest::StatusCode, String)> {
     let response = match response {
         Ok(r) => r,
         Err(e) => {
@@ -549,13 +1311,29 @@ async fn extract_body(
         }
     };
 
+    let status = response.status();
+    Span::current().record("status", status.as_u16());
+
+    if !status.is_success() {
+        record_error_status(request_name, status.as_u16().into());
+    }
+
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        debug!("{} rate limited", request_method);
+        ERRORS
+            .with_label_values(&[request_name, "rate_limited"])
+            .inc();
+
+        return Err(RateLimited.into());
+    }
+
     let result = response
         .text()
         .await
         .with_context(|| format!("fetching response body for {}", uri));
 
     match result {
-        Ok(text) => Ok(text),
+        Ok(text) => Ok((status, text)),
         Err(e) => {
             debug!("{} body fetch error {:?}", request_method, e);
             ERRORS.with_label_values(&[request_name, "body"]).inc();
@@ -565,19 +1343,126 @@ async fn extract_body(
     }
 }
 
-async fn json_from(
+async fn json_from<T: DeserializeOwned>(
     response: Result<reqwest::Response, anyhow::Error>,
     uri: &str,
     request_method: &str,
     request_name: &str,
-) -> Result<Response> {
-    let body = extract_body(response, uri, request_method, request_name).await?;
-
-    let result = deserialize(&body, uri, request_name)?;
+    spool: Option<&Path>,
+) -> Result<Response<T>> {
+    let (status, body) = extract_body(response, uri, request_method, request_name).await?;
+
+    // The envelope is checked for failures before the data is parsed as the endpoint's type
+    let result: Response<serde_json::Value> = deserialize(&body, uri, request_name, spool, || {
+        serde_json::from_str(&body)
+    })?;
+
+    // Failures returned with a successful HTTP status carry their status in the response
+    if status.is_success() && (!result.success || result.http_code == 429) {
+        record_error_status(request_name, result.http_code);
+    }
 
-    if !result.success {
-        Err(anyhow!("request error {}", result.message))
+    if result.http_code == 429 {
+        ERRORS
+            .with_label_values(&[request_name, "rate_limited"])
+            .inc();
+
+        Err(RateLimited.into())
+    } else if !result.success {
+        Err(RequestFailed {
+            http_code: result.http_code,
+            message: result.message,
+            detailed: result.detailed,
+        }
+        .into())
     } else {
-        Ok(result)
+        deserialize(&body, uri, request_name, spool, || result.typed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(http_code: u64) -> anyhow::Error {
+        anyhow::Error::from(RequestFailed {
+            http_code,
+            message: "failed".to_string(),
+            detailed: serde_json::Value::Null,
+        })
+        .context("refresh token request 1")
+    }
+
+    fn is_authentication(error: &anyhow::Error) -> bool {
+        marked::markers::<ErrorKind>(error).any(|kind| *kind == ErrorKind::Authentication)
+    }
+
+    /// `CredentialError` for a recorded `/oauth/token` failure
+    fn credential(fixture: &str) -> Option<CredentialError> {
+        let response: Response<serde_json::Value> = serde_json::from_str(fixture).unwrap();
+
+        CredentialError::from_failure(&RequestFailed {
+            http_code: response.http_code,
+            message: response.message,
+            detailed: response.detailed,
+        })
+    }
+
+    #[test]
+    fn credential_error_invalid_client() {
+        assert_eq!(
+            Some(CredentialError::InvalidClient),
+            credential(include_str!("../tests/fixtures/oauth/invalid_client.json"))
+        );
+    }
+
+    #[test]
+    fn credential_error_invalid_grant() {
+        assert_eq!(
+            Some(CredentialError::InvalidUser),
+            credential(include_str!("../tests/fixtures/oauth/invalid_grant.json"))
+        );
+    }
+
+    #[test]
+    fn credential_error_account_locked() {
+        assert_eq!(
+            Some(CredentialError::AccountLocked),
+            credential(include_str!("../tests/fixtures/oauth/account_locked.json"))
+        );
+    }
+
+    #[test]
+    fn credential_error_unknown() {
+        assert_eq!(
+            None,
+            credential(include_str!("../tests/fixtures/oauth/unknown.json"))
+        );
+    }
+
+    #[test]
+    fn credential_error_exit_code() {
+        let failure = RequestFailed {
+            http_code: 400,
+            message: "Invalid user credentials".to_string(),
+            detailed: serde_json::json!([{ "error": "invalid_grant" }]),
+        };
+        let error = authentication_error(credential_error(failure.into()));
+
+        assert_eq!(4, crate::exit_code(&error));
+    }
+
+    #[test]
+    fn authentication_error_rejected() {
+        assert!(is_authentication(&authentication_error(failure(400))));
+        assert!(is_authentication(&authentication_error(failure(401))));
+    }
+
+    #[test]
+    fn authentication_error_not_rejected() {
+        assert!(!is_authentication(&authentication_error(failure(503))));
+        assert!(!is_authentication(&authentication_error(anyhow!(
+            "awaiting response from https://api.flumewater.com/oauth/token"
+        ))));
     }
 }
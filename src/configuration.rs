@@ -1,22 +1,175 @@
+use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 
+use crate::arguments::Arguments;
+use crate::client::QueryBucket;
+use crate::client::QueryOperation;
+use crate::device_filter::DeviceFilter;
+use crate::device_labels::DeviceLabels;
+use crate::logging::LogFormat;
+use crate::query_settings::QuerySettings;
+use crate::query_settings::UsageQuery;
+use crate::relabel::Relabel;
+
 use serde::Deserialize;
 
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
 #[derive(Clone, Default, Deserialize)]
 pub struct Configuration {
-    bind_address: Option<String>,
+    environment: Option<Environment>,
+    api_url: Option<String>,
+    oauth_url: Option<String>,
+    bind_address: Option<BindAddress>,
     client_id: String,
+    #[serde(default)]
     secret_id: String,
+    secret_id_file: Option<String>,
     username: String,
+    #[serde(default)]
     password: String,
+    password_file: Option<String>,
+    user_id: Option<i64>,
     budget_interval: Option<u64>,
     device_interval: Option<u64>,
+    period_interval: Option<u64>,
     query_interval: Option<u64>,
+    query_jitter: Option<u64>,
+    query_lag: Option<u64>,
+    query_bucket: Option<QueryBucket>,
+    query_operation: Option<QueryOperation>,
+    query_overrides: Option<HashMap<String, QueryOverride>>,
+    flow_metrics: Option<bool>,
+    current_minute_interval: Option<u64>,
+    adaptive_polling: Option<bool>,
+    alerts: Option<AlertsConfiguration>,
+    error_policy: Option<ErrorPolicyConfiguration>,
+    poll_on_scrape: Option<bool>,
     flume_timeout: Option<u64>,
+    duration_buckets: Option<Vec<f64>>,
+    connect_timeout: Option<u64>,
+    request_timeout: Option<u64>,
+    user_agent_suffix: Option<String>,
+    requests_per_hour: Option<u32>,
+    send_request_id: Option<bool>,
+    control_token: Option<String>,
+    state_file: Option<String>,
+    response_spool: Option<String>,
+    device_ids: Option<Vec<String>>,
+    exclude_device_ids: Option<Vec<String>>,
+    device_labels: Option<HashMap<String, HashMap<String, String>>>,
+    relabel: Option<RelabelConfiguration>,
+    mqtt: Option<MqttConfiguration>,
+    remote_write: Option<RemoteWriteConfiguration>,
+    pushgateway: Option<PushgatewayConfiguration>,
+    history: Option<HistoryConfiguration>,
+    textfile: Option<TextfileConfiguration>,
+    otlp: Option<OtlpConfiguration>,
+    http_server: Option<bool>,
+    dashboard: Option<bool>,
+    log_format: Option<LogFormat>,
+    runtime: Option<Runtime>,
+    worker_threads: Option<usize>,
+}
+
+/// Flume account environment the credentials belong to
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    Production,
+    Sandbox,
+}
+
+/// Tokio runtime the exporter runs on
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Runtime {
+    CurrentThread,
+    MultiThread,
+}
+
+/// Whether an error exits the exporter or is logged and retried
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    Exit,
+    Retry,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct ErrorPolicyConfiguration {
+    authentication: Option<Policy>,
+    deserialize: Option<Policy>,
+    budgets: Option<Policy>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AlertsConfiguration {
+    webhooks: Option<Vec<String>>,
+    budget_used_percent: Option<f64>,
+    continuous_flow_minutes: Option<f64>,
+    battery_low: Option<bool>,
+    disconnected: Option<bool>,
+    timeout: Option<u64>,
+    ntfy: Option<NtfyConfiguration>,
+    pushover: Option<PushoverConfiguration>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct NtfyConfiguration {
+    topic: String,
+    server: Option<String>,
+    token: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PushoverConfiguration {
+    token: String,
+    user: String,
+}
+
+/// Label value renames and device drops applied before metrics are set
+#[derive(Clone, Default, Deserialize)]
+pub struct RelabelConfiguration {
+    location: Option<HashMap<String, String>>,
+    location_id: Option<HashMap<String, String>>,
+    product: Option<HashMap<String, String>>,
+    drop: Option<HashMap<String, Vec<String>>>,
+}
+
+/// Usage query settings for one sensor, unset settings use the global setting
+/// One bind address or a list of them
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum BindAddress {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Clone, Deserialize)]
+pub struct QueryOverride {
+    bucket: Option<QueryBucket>,
+    operation: Option<QueryOperation>,
+    query_interval: Option<u64>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MqttConfiguration {
+    host: String,
+    port: Option<u16>,
+    client_id: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    retain: Option<bool>,
+    usage_topic: Option<String>,
+    flow_rate_topic: Option<String>,
+    connected_topic: Option<String>,
+    battery_topic: Option<String>,
+    home_assistant_discovery: Option<bool>,
+    discovery_prefix: Option<String>,
 }
 
 impl Configuration {
@@ -24,29 +177,155 @@ impl Configuration {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
         let source = fs::read_to_string(path)?;
 
-        toml::from_str(&source).context("Invalid configuration file")
+        let mut configuration: Configuration =
+            toml::from_str(&source).context("Invalid configuration file")?;
+
+        configuration.reload_secrets()?;
+
+        Ok(configuration)
     }
 
-    /// Load configuration from the next argument in the environment.
-    pub fn load_from_next_arg() -> Result<Self> {
-        let file = match std::env::args().nth(1) {
+    /// Read the `secret_id_file` and `password_file`, if configured, replacing `secret_id` and
+    /// `password`.  Returns true when either changed so credentials rotated in the files can be
+    /// used without a restart.
+    pub fn reload_secrets(&mut self) -> Result<bool> {
+        let mut changed = false;
+
+        for (file, secret) in [
+            (&self.secret_id_file, &mut self.secret_id),
+            (&self.password_file, &mut self.password),
+        ] {
+            let file = match file {
+                Some(f) => f,
+                None => continue,
+            };
+
+            let contents = fs::read_to_string(file)
+                .with_context(|| format!("Unable to read secret file {}", file))?;
+            let contents = contents.trim_end_matches(['\r', '\n']);
+
+            if secret != contents {
+                *secret = contents.to_string();
+                changed = true;
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// With `password = "-"` read the password from stdin, prompting for it when stdin is a
+    /// terminal, so it never needs to be written to a file.
+    ///
+    /// Called once at startup since the configuration is loaded more than once.
+    pub fn read_password(&mut self) -> Result<()> {
+        if self.password_file.is_none() && self.password == "-" {
+            self.password = crate::password::read()?;
+        }
+
+        Ok(())
+    }
+
+    /// Load configuration from the file given in `arguments`.
+    pub fn load_from_arguments(arguments: &Arguments) -> Result<Self> {
+        let file = match &arguments.configuration {
             None => {
                 return Ok(Configuration::default());
             }
             Some(f) => f,
         };
 
-        Configuration::load(&file).with_context(|| format!("Unable to load {}", file))
+        Configuration::load(file).with_context(|| format!("Unable to load {}", file))
+    }
+
+    /// Fail when a sink is configured that the exporter was built without
+    pub fn check_features(&self) -> Result<()> {
+        let sinks = [
+            ("mqtt", "mqtt", self.mqtt.is_some(), cfg!(feature = "mqtt")),
+            ("otlp", "otlp", self.otlp.is_some(), cfg!(feature = "otlp")),
+            (
+                "pushgateway",
+                "pushgateway",
+                self.pushgateway.is_some(),
+                cfg!(feature = "pushgateway"),
+            ),
+            (
+                "remote_write",
+                "remote-write",
+                self.remote_write.is_some(),
+                cfg!(feature = "remote-write"),
+            ),
+            (
+                "textfile",
+                "textfile",
+                self.textfile.is_some(),
+                cfg!(feature = "textfile"),
+            ),
+            (
+                "history",
+                "sqlite",
+                self.history.is_some(),
+                cfg!(feature = "sqlite"),
+            ),
+        ];
+
+        for (section, feature, configured, enabled) in sinks {
+            if configured && !enabled {
+                return Err(anyhow!(
+                    "[{}] is configured but the exporter was built without the {} feature",
+                    section,
+                    feature
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fail when the sandbox environment is selected without its API URL, Flume doesn't publish
+    /// one so the exporter can't fall back to the production API
+    pub fn check_environment(&self) -> Result<()> {
+        if self.environment() == Environment::Sandbox && self.api_url.is_none() {
+            return Err(anyhow!(
+                "environment = \"sandbox\" requires the api_url of the sandbox Flume API"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Environment the credentials belong to.  Defaults to production.
+    pub fn environment(&self) -> Environment {
+        self.environment.unwrap_or(Environment::Production)
     }
 
-    /// Bind address for Prometheus metric server
-    pub fn bind_address(&self) -> String {
-        self.bind_address
-            .as_ref()
-            .unwrap_or(&"0.0.0.0:9160".to_string())
+    /// Base URL of the Flume API.  Defaults to https://api.flumewater.com in production, the
+    /// sandbox has no default.
+    pub fn api_url(&self) -> String {
+        self.api_url
+            .as_deref()
+            .unwrap_or("https://api.flumewater.com")
+            .trim_end_matches('/')
             .to_string()
     }
 
+    /// Base URL of the OAuth token endpoint.  Defaults to the `api_url`.
+    pub fn oauth_url(&self) -> String {
+        match &self.oauth_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => self.api_url(),
+        }
+    }
+
+    /// Bind addresses for Prometheus metric server, `bind_address` may be one address or a list.
+    /// Defaults to 0.0.0.0:9160.
+    pub fn bind_addresses(&self) -> Vec<String> {
+        match &self.bind_address {
+            Some(BindAddress::One(address)) => vec![address.clone()],
+            Some(BindAddress::Many(addresses)) => addresses.clone(),
+            None => vec!["0.0.0.0:9160".to_string()],
+        }
+    }
+
     pub fn client_id(&self) -> String {
         self.client_id.clone()
     }
@@ -63,6 +342,12 @@ impl Configuration {
         self.password.clone()
     }
 
+    /// Flume user to poll.  Defaults to the user the credentials authenticate as, looked up with
+    /// the `/me` API.
+    pub fn user_id(&self) -> Option<i64> {
+        self.user_id
+    }
+
     /// Interval between fetching budget data from Flume in seconds.
     ///
     /// Defaults to 60 minutes, the Flume Water API has a rate limit of 120 requests per hour.
@@ -82,6 +367,16 @@ impl Configuration {
         std::time::Duration::from_secs(interval)
     }
 
+    /// Interval between fetching usage for today, this week, and this month from Flume in
+    /// seconds.
+    ///
+    /// Defaults to 15 minutes, the Flume Water API has a rate limit of 120 requests per hour.
+    pub fn period_interval(&self) -> std::time::Duration {
+        let interval = self.period_interval.unwrap_or(900);
+
+        std::time::Duration::from_secs(interval)
+    }
+
     /// Interval between querying usage data from Flume in seconds.
     ///
     /// Defaults to 60 seconds, the Flume Water API has a rate limit of 120 requests per hour.
@@ -92,10 +387,560 @@ impl Configuration {
         std::time::Duration::from_secs(interval)
     }
 
-    /// Timeout to wait for the Flume API to respond in milliseconds.  Defaults to 1s.
-    pub fn flume_timeout(&self) -> std::time::Duration {
-        let timeout = self.flume_timeout.unwrap_or(1_000);
+    /// Maximum random delay added before querying each sensor in milliseconds.  Defaults to 0.
+    ///
+    /// Sensor queries are always spread evenly across the query interval, the jitter keeps
+    /// multiple exporters from querying in lockstep.
+    pub fn query_jitter(&self) -> std::time::Duration {
+        let jitter = self.query_jitter.unwrap_or(0);
+
+        std::time::Duration::from_millis(jitter)
+    }
+
+    /// How far behind now usage query windows end in seconds.  Defaults to 60.
+    ///
+    /// Usage for the most recent minutes may not be recorded by Flume yet, lagging keeps a
+    /// partial minute from being counted and then skipped by the next window.
+    pub fn query_lag(&self) -> std::time::Duration {
+        let lag = self.query_lag.unwrap_or(60);
+
+        std::time::Duration::from_secs(lag)
+    }
+
+    /// Bucket and operation for usage queries, `query_bucket` and `query_operation` with
+    /// per-sensor `query_overrides`.  Defaults to MIN buckets with SUM.
+    ///
+    /// Larger buckets query less often, a window ends only when a whole bucket has passed.  A
+    /// `query_interval` in seconds in an override queries that sensor less often than the other
+    /// sensors.
+    pub fn query_settings(&self) -> QuerySettings {
+        let default = UsageQuery::default();
+        let default = UsageQuery {
+            bucket: self.query_bucket.clone().unwrap_or(default.bucket),
+            operation: self.query_operation.clone().unwrap_or(default.operation),
+            interval: None,
+        };
+
+        let devices = self
+            .query_overrides
+            .iter()
+            .flatten()
+            .map(|(id, o)| {
+                let query = UsageQuery {
+                    bucket: o.bucket.clone().unwrap_or_else(|| default.bucket.clone()),
+                    operation: o
+                        .operation
+                        .clone()
+                        .unwrap_or_else(|| default.operation.clone()),
+                    interval: o.query_interval.map(std::time::Duration::from_secs),
+                };
+
+                (id.clone(), query)
+            })
+            .collect();
+
+        QuerySettings::new(default, devices)
+    }
+
+    /// Query the peak and average flow over each usage query window.  Defaults to false.
+    ///
+    /// Adds two requests per sensor to each query interval.
+    pub fn flow_metrics(&self) -> bool {
+        self.flow_metrics.unwrap_or(false)
+    }
+
+    /// Interval between querying each sensor's usage so far this minute in seconds.  Defaults to
+    /// not querying it.
+    ///
+    /// Adds one request per sensor to each interval.
+    pub fn current_minute_interval(&self) -> Option<std::time::Duration> {
+        self.current_minute_interval
+            .map(std::time::Duration::from_secs)
+    }
+
+    /// Lengthen the query interval to stay under the Flume API rate limit.  Defaults to false.
+    ///
+    /// The query interval is lengthened to fit the number of sensors and the other intervals, and
+    /// doubled each time the API rejects a request for exceeding the rate limit.  `query_interval`
+    /// is the shortest interval used.
+    pub fn adaptive_polling(&self) -> bool {
+        self.adaptive_polling.unwrap_or(false)
+    }
+
+    /// Query usage when metrics are scraped instead of on the query interval.  Defaults to false.
+    ///
+    /// Usage is still queried at most once per query interval.
+    pub fn poll_on_scrape(&self) -> bool {
+        self.poll_on_scrape.unwrap_or(false)
+    }
+
+    /// Timeout to connect to the Flume API in milliseconds.  Defaults to `flume_timeout`, or 1s.
+    pub fn connect_timeout(&self) -> std::time::Duration {
+        let timeout = self.connect_timeout.or(self.flume_timeout).unwrap_or(1_000);
+
+        std::time::Duration::from_millis(timeout)
+    }
+
+    /// Timeout to wait for the Flume API to finish responding in milliseconds, including
+    /// connecting.  Defaults to `flume_timeout`, or 10s.
+    ///
+    /// Usage queries over DAY or MON buckets can take several seconds.
+    pub fn request_timeout(&self) -> std::time::Duration {
+        let timeout = self
+            .request_timeout
+            .or(self.flume_timeout)
+            .unwrap_or(10_000);
+
+        std::time::Duration::from_millis(timeout)
+    }
+
+    /// Text appended to the `User-Agent` sent to the Flume API, such as contact details
+    pub fn user_agent_suffix(&self) -> Option<String> {
+        self.user_agent_suffix.clone()
+    }
+
+    /// Most Flume API requests to make in any hour, across all request types.  Defaults to 120,
+    /// Flume's rate limit.
+    pub fn requests_per_hour(&self) -> u32 {
+        self.requests_per_hour.unwrap_or(120)
+    }
+
+    /// Send each request's id to Flume in the `X-Request-Id` header.  Defaults to false.
+    pub fn send_request_id(&self) -> bool {
+        self.send_request_id.unwrap_or(false)
+    }
+
+    /// Buckets in seconds for the Flume API request duration histogram.  Defaults to the
+    /// Prometheus default buckets, 5ms to 10s.
+    pub fn duration_buckets(&self) -> Option<Vec<f64>> {
+        self.duration_buckets.clone()
+    }
+
+    /// What to do for each kind of error, from the `[error_policy]` section
+    pub fn error_policy(&self) -> ErrorPolicyConfiguration {
+        self.error_policy.clone().unwrap_or_default()
+    }
+
+    /// Bearer token required to access the `/control` endpoints.  The endpoints are disabled
+    /// when no token is configured.
+    pub fn control_token(&self) -> Option<String> {
+        self.control_token.clone()
+    }
+
+    /// File to save usage counter totals and sensor query windows to so they survive restarts.
+    pub fn state_file(&self) -> Option<std::path::PathBuf> {
+        self.state_file.as_ref().map(std::path::PathBuf::from)
+    }
+
+    /// Directory to save responses that could not be deserialized to, with credentials and
+    /// personal details redacted.  Responses are not saved by default.
+    pub fn response_spool(&self) -> Option<std::path::PathBuf> {
+        self.response_spool.as_ref().map(std::path::PathBuf::from)
+    }
+
+    /// Devices to poll.  When `device_ids` is set only those devices are polled, devices in
+    /// `exclude_device_ids` are never polled.
+    pub fn device_filter(&self) -> DeviceFilter {
+        DeviceFilter::new(
+            self.device_ids.clone(),
+            self.exclude_device_ids.clone().unwrap_or_default(),
+        )
+    }
+
+    /// Extra metric labels for each device id from the `[device_labels."<device_id>"]` tables
+    pub fn device_labels(&self) -> Result<DeviceLabels> {
+        DeviceLabels::new(self.device_labels.clone().unwrap_or_default())
+    }
+
+    /// Location and product renames and devices to drop, from the `[relabel]` section
+    pub fn relabel(&self) -> Result<Relabel> {
+        let relabel = self.relabel.clone().unwrap_or_default();
+
+        Relabel::new(
+            relabel.location.unwrap_or_default(),
+            relabel.location_id.unwrap_or_default(),
+            relabel.product.unwrap_or_default(),
+            relabel.drop.unwrap_or_default(),
+        )
+    }
+
+    /// Alert conditions and where to send alerts, if any
+    pub fn alerts(&self) -> Option<AlertsConfiguration> {
+        self.alerts.clone()
+    }
+
+    /// MQTT broker to publish updates to, if any
+    pub fn mqtt(&self) -> Option<MqttConfiguration> {
+        self.mqtt.clone()
+    }
+
+    /// Log output format, "text" or "json".  Defaults to text.
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format.unwrap_or(LogFormat::Text)
+    }
+
+    /// Tokio runtime to run on.  Defaults to the multi-threaded runtime, the current-thread
+    /// runtime uses less memory on small machines.
+    pub fn runtime(&self) -> Runtime {
+        self.runtime.unwrap_or(Runtime::MultiThread)
+    }
+
+    /// Worker threads for the multi-threaded runtime.  Defaults to one per CPU.
+    pub fn worker_threads(&self) -> Option<usize> {
+        self.worker_threads
+    }
+
+    /// File to write metrics to for the node_exporter textfile collector, if any
+    pub fn textfile(&self) -> Option<TextfileConfiguration> {
+        self.textfile.clone()
+    }
+
+    /// Prometheus Pushgateway to push metrics to, if any
+    pub fn pushgateway(&self) -> Option<PushgatewayConfiguration> {
+        self.pushgateway.clone()
+    }
+
+    /// Run the HTTP server for metrics and control endpoints.  Defaults to true.
+    ///
+    /// Disable when metrics are pushed and listening on a port isn't permitted.
+    pub fn http_server(&self) -> bool {
+        self.http_server.unwrap_or(true)
+    }
+
+    /// SQLite database to keep usage and device history in, if any
+    pub fn history(&self) -> Option<HistoryConfiguration> {
+        self.history.clone()
+    }
+
+    /// Serve the `/dashboard` page.  Defaults to true.
+    pub fn dashboard(&self) -> bool {
+        self.dashboard.unwrap_or(true)
+    }
+
+    /// OpenTelemetry collector to export metrics to, if any
+    pub fn otlp(&self) -> Option<OtlpConfiguration> {
+        self.otlp.clone()
+    }
+
+    /// Prometheus remote write endpoint to push metrics to, if any
+    pub fn remote_write(&self) -> Option<RemoteWriteConfiguration> {
+        self.remote_write.clone()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RemoteWriteConfiguration {
+    url: String,
+    job: Option<String>,
+    instance: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    bearer_token: Option<String>,
+    timeout: Option<u64>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PushgatewayConfiguration {
+    url: String,
+    job: Option<String>,
+    instance: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    timeout: Option<u64>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TextfileConfiguration {
+    path: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct HistoryConfiguration {
+    path: String,
+    retention_days: Option<u64>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct OtlpConfiguration {
+    endpoint: String,
+    traces_endpoint: Option<String>,
+    headers: Option<HashMap<String, String>>,
+    timeout: Option<u64>,
+}
+
+impl MqttConfiguration {
+    pub fn host(&self) -> String {
+        self.host.clone()
+    }
+
+    /// Defaults to 1883
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(1883)
+    }
+
+    /// Defaults to "flume_water_exporter"
+    pub fn client_id(&self) -> String {
+        self.client_id
+            .clone()
+            .unwrap_or_else(|| "flume_water_exporter".to_string())
+    }
+
+    /// Username and password for the broker, if both are configured
+    pub fn credentials(&self) -> Option<(String, String)> {
+        match (&self.username, &self.password) {
+            (Some(u), Some(p)) => Some((u.clone(), p.clone())),
+            _ => None,
+        }
+    }
+
+    /// Publish messages with the retain flag.  Defaults to true.
+    pub fn retain(&self) -> bool {
+        self.retain.unwrap_or(true)
+    }
+
+    /// Topic for total usage in liters.  `{device_id}` is replaced with the sensor id.
+    pub fn usage_topic(&self) -> String {
+        self.usage_topic
+            .clone()
+            .unwrap_or_else(|| "flume_water/{device_id}/usage".to_string())
+    }
+
+    /// Topic for flow rate in liters per minute.  `{device_id}` is replaced with the sensor id.
+    pub fn flow_rate_topic(&self) -> String {
+        self.flow_rate_topic
+            .clone()
+            .unwrap_or_else(|| "flume_water/{device_id}/flow_rate".to_string())
+    }
+
+    /// Topic for sensor and bridge connectivity.  `{device_id}` is replaced with the device id.
+    pub fn connected_topic(&self) -> String {
+        self.connected_topic
+            .clone()
+            .unwrap_or_else(|| "flume_water/{device_id}/connected".to_string())
+    }
+
+    /// Topic for the sensor battery level.  `{device_id}` is replaced with the sensor id.
+    pub fn battery_topic(&self) -> String {
+        self.battery_topic
+            .clone()
+            .unwrap_or_else(|| "flume_water/{device_id}/battery".to_string())
+    }
+
+    /// Publish Home Assistant MQTT discovery messages for each device.  Defaults to false.
+    pub fn home_assistant_discovery(&self) -> bool {
+        self.home_assistant_discovery.unwrap_or(false)
+    }
+
+    /// Home Assistant discovery topic prefix.  Defaults to "homeassistant".
+    pub fn discovery_prefix(&self) -> String {
+        self.discovery_prefix
+            .clone()
+            .unwrap_or_else(|| "homeassistant".to_string())
+    }
+}
+
+impl RemoteWriteConfiguration {
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Username and password for basic authentication, if both are configured
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        match (&self.username, &self.password) {
+            (Some(u), Some(p)) => Some((u.clone(), p.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn bearer_token(&self) -> Option<String> {
+        self.bearer_token.clone()
+    }
+
+    /// Value of the `job` label added to each series.  Defaults to "flume_water_exporter".
+    pub fn job(&self) -> String {
+        self.job
+            .clone()
+            .unwrap_or_else(|| "flume_water_exporter".to_string())
+    }
+
+    /// Value of the `instance` label added to each series, if any
+    pub fn instance(&self) -> Option<String> {
+        self.instance.clone()
+    }
+
+    /// Timeout to wait for the remote write endpoint to respond in milliseconds.  Defaults to
+    /// 10s.
+    pub fn timeout(&self) -> std::time::Duration {
+        let timeout = self.timeout.unwrap_or(10_000);
+
+        std::time::Duration::from_millis(timeout)
+    }
+}
+
+impl TextfileConfiguration {
+    pub fn path(&self) -> std::path::PathBuf {
+        std::path::PathBuf::from(&self.path)
+    }
+}
+
+impl HistoryConfiguration {
+    pub fn path(&self) -> std::path::PathBuf {
+        std::path::PathBuf::from(&self.path)
+    }
+
+    /// How long history is kept.  Defaults to 365 days.
+    pub fn retention(&self) -> std::time::Duration {
+        let days = self.retention_days.unwrap_or(365);
+
+        std::time::Duration::from_secs(days * 24 * 60 * 60)
+    }
+}
+
+impl PushgatewayConfiguration {
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Value of the `job` grouping label.  Defaults to "flume_water_exporter".
+    pub fn job(&self) -> String {
+        self.job
+            .clone()
+            .unwrap_or_else(|| "flume_water_exporter".to_string())
+    }
+
+    /// Value of the `instance` grouping label, if any
+    pub fn instance(&self) -> Option<String> {
+        self.instance.clone()
+    }
+
+    /// Username and password for basic authentication, if both are configured
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        match (&self.username, &self.password) {
+            (Some(u), Some(p)) => Some((u.clone(), p.clone())),
+            _ => None,
+        }
+    }
+
+    /// Timeout to wait for the Pushgateway to respond in milliseconds.  Defaults to 10s.
+    pub fn timeout(&self) -> std::time::Duration {
+        let timeout = self.timeout.unwrap_or(10_000);
 
         std::time::Duration::from_millis(timeout)
     }
 }
+
+impl OtlpConfiguration {
+    /// OTLP/HTTP metrics endpoint, usually ending in `/v1/metrics`
+    pub fn endpoint(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    /// OTLP/HTTP traces endpoint, usually ending in `/v1/traces`.  Spans are only exported when
+    /// it is set.
+    pub fn traces_endpoint(&self) -> Option<String> {
+        self.traces_endpoint.clone()
+    }
+
+    /// Extra headers sent with each export, such as an API key
+    pub fn headers(&self) -> HashMap<String, String> {
+        self.headers.clone().unwrap_or_default()
+    }
+
+    /// Timeout to wait for the collector to respond in milliseconds.  Defaults to 10s.
+    pub fn timeout(&self) -> std::time::Duration {
+        let timeout = self.timeout.unwrap_or(10_000);
+
+        std::time::Duration::from_millis(timeout)
+    }
+}
+
+impl AlertsConfiguration {
+    /// URLs alerts are POSTed to as JSON
+    pub fn webhooks(&self) -> Vec<String> {
+        self.webhooks.clone().unwrap_or_default()
+    }
+
+    /// Alert when a budget is at least this percent used.  Defaults to 100.
+    pub fn budget_used_percent(&self) -> f64 {
+        self.budget_used_percent.unwrap_or(100.0)
+    }
+
+    /// Alert when water has been flowing continuously for this many minutes.  Defaults to 120.
+    pub fn continuous_flow_minutes(&self) -> f64 {
+        self.continuous_flow_minutes.unwrap_or(120.0)
+    }
+
+    /// Alert when a sensor battery is low.  Defaults to true.
+    pub fn battery_low(&self) -> bool {
+        self.battery_low.unwrap_or(true)
+    }
+
+    /// Alert when a bridge or sensor is disconnected.  Defaults to true.
+    pub fn disconnected(&self) -> bool {
+        self.disconnected.unwrap_or(true)
+    }
+
+    /// Timeout to wait for a webhook or notification service to respond in milliseconds.
+    /// Defaults to 10s.
+    pub fn timeout(&self) -> std::time::Duration {
+        let timeout = self.timeout.unwrap_or(10_000);
+
+        std::time::Duration::from_millis(timeout)
+    }
+
+    /// ntfy topic to send push notifications to, if any
+    pub fn ntfy(&self) -> Option<NtfyConfiguration> {
+        self.ntfy.clone()
+    }
+
+    /// Pushover application and user to send push notifications to, if any
+    pub fn pushover(&self) -> Option<PushoverConfiguration> {
+        self.pushover.clone()
+    }
+}
+
+impl ErrorPolicyConfiguration {
+    /// Policy when Flume rejects the username and password or a token refresh.  Defaults to
+    /// retry, with a backoff starting at a minute so retries don't lock the account.
+    pub fn authentication(&self) -> Policy {
+        self.authentication.unwrap_or(Policy::Retry)
+    }
+
+    /// Policy for responses that can't be deserialized.  Defaults to retry.
+    ///
+    /// With exit the exporter exits after several deserialize errors in a row.
+    pub fn deserialize(&self) -> Policy {
+        self.deserialize.unwrap_or(Policy::Retry)
+    }
+
+    /// Policy when fetching budgets fails.  Defaults to retry.
+    pub fn budgets(&self) -> Policy {
+        self.budgets.unwrap_or(Policy::Retry)
+    }
+}
+
+impl NtfyConfiguration {
+    /// URL notifications are published to, the topic on the server
+    pub fn url(&self) -> String {
+        let server = self.server.as_deref().unwrap_or("https://ntfy.sh");
+
+        format!("{}/{}", server.trim_end_matches('/'), self.topic)
+    }
+
+    /// Access token for a protected topic, if any
+    pub fn token(&self) -> Option<String> {
+        self.token.clone()
+    }
+}
+
+impl PushoverConfiguration {
+    /// Pushover application API token
+    pub fn token(&self) -> String {
+        self.token.clone()
+    }
+
+    /// Pushover user or group key
+    pub fn user(&self) -> String {
+        self.user.clone()
+    }
+}
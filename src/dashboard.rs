@@ -0,0 +1,234 @@
+use crate::readings::Reading;
+use crate::update::Update;
+
+use std::fmt::Write;
+
+/// Seconds between dashboard reloads
+const REFRESH: u64 = 60;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:2em}\
+th,td{padding:.3em .8em;text-align:left;border-bottom:1px solid #ddd}\
+td.number{text-align:right}\
+progress{width:12em}\
+.bad{color:#b00}";
+
+/// Render the dashboard page from the latest `readings`
+pub fn render(readings: &[Reading]) -> String {
+    let mut page = String::new();
+
+    let _ = write!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"{}\">\
+         <title>Flume water</title><style>{}</style></head><body>\n\
+         <h1>Flume water</h1>\n",
+        REFRESH, STYLE
+    );
+
+    if readings.is_empty() {
+        page.push_str("<p>Waiting for the first update from Flume.</p>\n");
+    }
+
+    usage(&mut page, readings);
+    periods(&mut page, readings);
+    budgets(&mut page, readings);
+    devices(&mut page, readings);
+
+    page.push_str("</body></html>\n");
+
+    page
+}
+
+fn usage(page: &mut String, readings: &[Reading]) {
+    let rows: Vec<String> = readings
+        .iter()
+        .filter_map(|reading| match &reading.update {
+            Update::Usage {
+                device_id,
+                location,
+                flow_rate,
+                continuous_flow_minutes,
+                ..
+            } => Some(format!(
+                "<tr><td>{}</td><td>{}</td><td class=\"number\">{}</td>\
+                 <td class=\"number\">{:.0}</td><td>{}</td></tr>\n",
+                escape(location),
+                escape(device_id),
+                flow_rate.map_or_else(String::new, |rate| format!("{:.2}", rate)),
+                continuous_flow_minutes,
+                reading.received.format("%Y-%m-%d %H:%M:%S UTC"),
+            )),
+            _ => None,
+        })
+        .collect();
+
+    table(
+        page,
+        "Current flow",
+        &[
+            "Location",
+            "Sensor",
+            "Liters per minute",
+            "Continuous minutes",
+            "Updated",
+        ],
+        rows,
+    );
+}
+
+fn periods(page: &mut String, readings: &[Reading]) {
+    let rows: Vec<String> = readings
+        .iter()
+        .filter_map(|reading| match &reading.update {
+            Update::Periods {
+                device_id,
+                location,
+                today_liters,
+                this_week_liters,
+                this_month_liters,
+                ..
+            } => Some(format!(
+                "<tr><td>{}</td><td>{}</td><td class=\"number\">{:.1}</td>\
+                 <td class=\"number\">{:.1}</td><td class=\"number\">{:.1}</td></tr>\n",
+                escape(location),
+                escape(device_id),
+                today_liters,
+                this_week_liters,
+                this_month_liters,
+            )),
+            _ => None,
+        })
+        .collect();
+
+    table(
+        page,
+        "Usage in liters",
+        &["Location", "Sensor", "Today", "This week", "This month"],
+        rows,
+    );
+}
+
+fn budgets(page: &mut String, readings: &[Reading]) {
+    let rows: Vec<String> = readings
+        .iter()
+        .filter_map(|reading| match &reading.update {
+            Update::Budget {
+                location,
+                name,
+                period,
+                used_ratio,
+                ..
+            } => Some(format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td>\
+                 <td><progress value=\"{:.3}\" max=\"1\"></progress></td>\
+                 <td class=\"number{}\">{:.0}%</td></tr>\n",
+                escape(location),
+                escape(name),
+                escape(period),
+                used_ratio.min(1.0),
+                if *used_ratio >= 1.0 { " bad" } else { "" },
+                used_ratio * 100.0,
+            )),
+            _ => None,
+        })
+        .collect();
+
+    table(
+        page,
+        "Budgets",
+        &["Location", "Budget", "Period", "Progress", "Used"],
+        rows,
+    );
+}
+
+fn devices(page: &mut String, readings: &[Reading]) {
+    let rows: Vec<String> = readings
+        .iter()
+        .filter_map(|reading| match &reading.update {
+            Update::Bridge {
+                device_id,
+                location,
+                product,
+                connected,
+                ..
+            } => Some(format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td>{}<td></td><td></td></tr>\n",
+                escape(location),
+                escape(device_id),
+                escape(product),
+                status(*connected, "connected", "disconnected"),
+            )),
+            Update::Sensor {
+                device_id,
+                location,
+                product,
+                connected,
+                oriented,
+                battery_level,
+                ..
+            } => Some(format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td>{}{}<td{}>{}</td></tr>\n",
+                escape(location),
+                escape(device_id),
+                escape(product),
+                status(*connected, "connected", "disconnected"),
+                status(*oriented, "oriented", "not oriented"),
+                if matches!(battery_level.as_str(), "low" | "critical") {
+                    " class=\"bad\""
+                } else {
+                    ""
+                },
+                escape(battery_level),
+            )),
+            _ => None,
+        })
+        .collect();
+
+    table(
+        page,
+        "Devices",
+        &[
+            "Location",
+            "Device",
+            "Product",
+            "Connection",
+            "Orientation",
+            "Battery",
+        ],
+        rows,
+    );
+}
+
+fn status(ok: bool, good: &str, bad: &str) -> String {
+    if ok {
+        format!("<td>{}</td>", good)
+    } else {
+        format!("<td class=\"bad\">{}</td>", bad)
+    }
+}
+
+fn table(page: &mut String, title: &str, headings: &[&str], rows: Vec<String>) {
+    if rows.is_empty() {
+        return;
+    }
+
+    let _ = write!(page, "<h2>{}</h2>\n<table><tr>", title);
+
+    for heading in headings {
+        let _ = write!(page, "<th>{}</th>", heading);
+    }
+
+    page.push_str("</tr>\n");
+    rows.iter().for_each(|row| page.push_str(row));
+    page.push_str("</table>\n");
+}
+
+/// Escape text from Flume, such as location names, for HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,99 @@
+use chrono::DateTime;
+use chrono::FixedOffset;
+use chrono::Utc;
+
+use serde::Serialize;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use tokio::sync::watch;
+
+use tracing::error;
+use tracing::info;
+
+/// The downloader's scheduling and token status for debugging, updated as each polling cycle
+/// starts and finishes.  Contains no credentials.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DebugState {
+    pub user_id: Option<i64>,
+    pub token_expires_at: Option<DateTime<Utc>>,
+    pub cycle_started_at: Option<DateTime<Utc>>,
+    pub cycle_finished_at: Option<DateTime<Utc>>,
+    pub query_interval_seconds: u64,
+    pub throttle_factor: u32,
+    pub devices: Timer,
+    pub periods: Timer,
+    pub budgets: Timer,
+    pub sensors: Vec<SensorDebugState>,
+}
+
+/// When a type of data was last fetched and when it will be fetched next
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Timer {
+    pub last_update: Option<DateTime<Utc>>,
+    pub next_update: Option<DateTime<Utc>>,
+}
+
+impl Timer {
+    /// Timer for data last fetched at `last_update` and fetched every `interval`
+    pub fn new(last_update: Option<Instant>, interval: Duration) -> Self {
+        let last_update = last_update.map(wall_clock);
+
+        Timer {
+            last_update,
+            next_update: last_update
+                .and_then(|t| chrono::Duration::from_std(interval).ok().map(|i| t + i)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SensorDebugState {
+    pub id: String,
+    pub location_id: String,
+    pub location: String,
+    /// End of the last usage window queried
+    pub last_update: DateTime<FixedOffset>,
+    pub last_seen: DateTime<FixedOffset>,
+    pub continuous_flow_minutes: f64,
+}
+
+/// Wall clock time of `instant`
+pub fn wall_clock(instant: Instant) -> DateTime<Utc> {
+    let now = Utc::now();
+
+    chrono::Duration::from_std(instant.elapsed())
+        .map(|elapsed| now - elapsed)
+        .unwrap_or(now)
+}
+
+/// Log the latest debug state as JSON each time the exporter receives SIGUSR1
+#[cfg(unix)]
+pub fn dump_on_signal(debug_rx: watch::Receiver<DebugState>) {
+    use tokio::signal::unix::signal;
+    use tokio::signal::unix::SignalKind;
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Unable to listen for SIGUSR1 {:#}", e);
+
+            return;
+        }
+    };
+
+    crate::spawn_named(
+        async move {
+            while signals.recv().await.is_some() {
+                let state = debug_rx.borrow().clone();
+
+                match serde_json::to_string(&state) {
+                    Ok(json) => info!("Downloader state {}", json),
+                    Err(e) => error!("Unable to serialize downloader state {:#}", e),
+                }
+            }
+        },
+        "debug_state",
+    );
+}
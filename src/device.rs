@@ -1,3 +1,4 @@
+use anyhow::anyhow;
 use anyhow::Result;
 
 use crate::bridge::Bridge;
@@ -6,11 +7,22 @@ use crate::sensor::Sensor;
 
 use std::convert::TryFrom;
 
+/// A bridge or sensor parsed from an API response
+#[allow(clippy::large_enum_variant)]
 pub enum Device {
     Bridge(Bridge),
     Sensor(Sensor),
 }
 
+impl Device {
+    pub fn id(&self) -> &str {
+        match self {
+            Device::Bridge(b) => &b.id,
+            Device::Sensor(s) => &s.sensor.id,
+        }
+    }
+}
+
 impl TryFrom<client::Device> for Device {
     type Error = anyhow::Error;
 
@@ -18,6 +30,17 @@ impl TryFrom<client::Device> for Device {
         Ok(match device {
             client::Device::Bridge(b) => Device::Bridge(b.try_into()?),
             client::Device::Sensor(s) => Device::Sensor(s.try_into()?),
+            client::Device::Unknown {
+                product,
+                device_type,
+                ..
+            } => {
+                return Err(anyhow!(
+                    "Unsupported Flume product {:?} of type {:?}",
+                    product,
+                    device_type
+                ))
+            }
         })
     }
 }
@@ -0,0 +1,23 @@
+/// Selects which devices are polled by id
+#[derive(Clone, Debug, Default)]
+pub struct DeviceFilter {
+    include: Option<Vec<String>>,
+    exclude: Vec<String>,
+}
+
+impl DeviceFilter {
+    pub fn new(include: Option<Vec<String>>, exclude: Vec<String>) -> Self {
+        DeviceFilter { include, exclude }
+    }
+
+    /// A device is polled when it is in the include list (or there is no include list) and it is
+    /// not in the exclude list
+    pub fn includes(&self, id: &str) -> bool {
+        let included = match &self.include {
+            Some(include) => include.iter().any(|i| i == id),
+            None => true,
+        };
+
+        included && !self.exclude.iter().any(|e| e == id)
+    }
+}
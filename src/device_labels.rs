@@ -0,0 +1,88 @@
+use anyhow::anyhow;
+use anyhow::Result;
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+/// Labels the exporter sets itself, configured labels can't replace them
+const RESERVED: [&str; 6] = [
+    "location_id",
+    "location",
+    "device_id",
+    "bridge_id",
+    "product",
+    "level",
+];
+
+/// Extra metric labels for each device from the `[device_labels]` configuration
+#[derive(Clone, Debug, Default)]
+pub struct DeviceLabels {
+    names: Vec<String>,
+    values: HashMap<String, Vec<String>>,
+}
+
+impl DeviceLabels {
+    /// Labels for each device id.  Every device gets every label name used by any device, with
+    /// an empty value when it wasn't configured for that device.
+    pub fn new(labels: HashMap<String, HashMap<String, String>>) -> Result<Self> {
+        let names: Vec<String> = labels
+            .values()
+            .flat_map(|l| l.keys().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        for name in &names {
+            if !valid_name(name) {
+                return Err(anyhow!("device label {:?} is not a valid label name", name));
+            }
+
+            if RESERVED.contains(&name.as_str()) {
+                return Err(anyhow!(
+                    "device label {:?} is set by the exporter and can't be configured",
+                    name
+                ));
+            }
+        }
+
+        let values = labels
+            .into_iter()
+            .map(|(device_id, mut l)| {
+                let values = names
+                    .iter()
+                    .map(|name| l.remove(name).unwrap_or_default())
+                    .collect();
+
+                (device_id, values)
+            })
+            .collect();
+
+        Ok(DeviceLabels { names, values })
+    }
+
+    /// Label names in the order `values` returns their values
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Label values for `device_id`, empty for devices without configured labels
+    pub fn values(&self, device_id: &str) -> Vec<&str> {
+        match self.values.get(device_id) {
+            Some(values) => values.iter().map(String::as_str).collect(),
+            None => vec![""; self.names.len()],
+        }
+    }
+}
+
+/// Prometheus label names match `[a-zA-Z_][a-zA-Z0-9_]*` and names starting with `__` are
+/// reserved
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    let first = match chars.next() {
+        Some(c) => c.is_ascii_alphabetic() || c == '_',
+        None => false,
+    };
+
+    first && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !name.starts_with("__")
+}
@@ -3,302 +3,2715 @@ use anyhow::Error;
 use anyhow::Result;
 
 use crate::bridge::Bridge;
+use crate::client::BudgetPeriod;
+use crate::client::Client;
+use crate::client::ErrorKind;
+use crate::client::QueryBucket;
+use crate::client::RateLimited;
+use crate::client::RequestFailed;
+use crate::debug_state::DebugState;
+use crate::debug_state::SensorDebugState;
+use crate::debug_state::Timer;
 use crate::device::Device;
+use crate::device_filter::DeviceFilter;
+use crate::device_labels::DeviceLabels;
 use crate::flume::Flume;
+use crate::flume::PeriodUsage;
+use crate::flume_api::FlumeApi;
+use crate::marked;
+use crate::query_settings::QuerySettings;
+use crate::relabel::Relabel;
+use crate::request_window;
+use crate::sensor;
 use crate::sensor::Sensor;
+use crate::series::Metric;
+use crate::series::Series;
+use crate::series::SeriesTracker;
+use crate::state::SensorState;
+use crate::state::State;
+use crate::state::UsageState;
+use crate::supervisor;
+use crate::update::Update;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::FixedOffset;
+use chrono::NaiveDate;
+use chrono::Offset;
+use chrono::Utc;
+use chrono_tz::Tz;
 
 use lazy_static::lazy_static;
 
-use log::debug;
-use log::error;
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+use tracing::info_span;
+use tracing::warn;
+use tracing::Instrument;
 
-use prometheus::register_counter_vec;
-use prometheus::register_gauge_vec;
-use prometheus::register_int_gauge_vec;
 use prometheus::CounterVec;
+use prometheus::Gauge;
 use prometheus::GaugeVec;
+use prometheus::Histogram;
+use prometheus::HistogramOpts;
+use prometheus::IntCounter;
+use prometheus::IntGauge;
 use prometheus::IntGaugeVec;
+use prometheus::Opts;
+use prometheus::Registry;
 
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::OnceLock;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use rand::Rng;
 
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::sync::watch;
 use tokio::time::interval;
+use tokio::time::interval_at;
+use tokio::time::sleep_until;
+use tokio::time::Interval;
 use tokio::time::MissedTickBehavior;
 
 type Sender = mpsc::Sender<anyhow::Error>;
 
+/// Request for an update before metrics are scraped, replied to when the update finishes
+pub type ScrapeRequest = oneshot::Sender<()>;
+pub type ScrapeSender = mpsc::Sender<ScrapeRequest>;
+
+/// Request to turn away mode on or off for a location, given by id or name, replied to with the
+/// result
+pub struct AwayModeRequest {
+    pub location: String,
+    pub away_mode: bool,
+    pub reply: oneshot::Sender<Result<()>>,
+}
+pub type AwayModeSender = mpsc::Sender<AwayModeRequest>;
+
+/// No polled sensor is at the location given in an `AwayModeRequest`
+#[derive(Debug)]
+pub struct UnknownLocation(pub String);
+
+impl std::fmt::Display for UnknownLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no sensors found at location {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownLocation {}
+
+/// Liters in a US gallon, exactly
+pub(crate) const LITERS_PER_GALLON: f64 = 3.785411784;
+
 const BATTERY_HIGH: &str = "high";
 const BATTERY_MEDIUM: &str = "medium";
 const BATTERY_LOW: &str = "low";
 
 lazy_static! {
-    static ref BRIDGE_PRODUCT: GaugeVec = register_gauge_vec!(
-        "flume_water_bridge_product_info",
-        "Flume bridge product",
-        &["location", "product"],
+    static ref UP: IntGauge = IntGauge::new(
+        "flume_water_up",
+        "Last polling cycle fetched all data from Flume successfully",
+    )
+    .unwrap();
+    static ref CYCLE_DURATIONS: Histogram = Histogram::with_opts(
+        HistogramOpts::new(
+            "flume_water_cycle_duration_seconds",
+            "Time taken by each polling cycle",
+        )
+        .buckets(vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
+    )
+    .unwrap();
+    static ref SKIPPED_TICKS: IntCounter = IntCounter::new(
+        "flume_water_skipped_ticks_total",
+        "Query intervals skipped because the previous polling cycle was still running",
+    )
+    .unwrap();
+    static ref LAST_DEVICES_UPDATE: Gauge = Gauge::new(
+        "flume_water_last_successful_devices_update_timestamp_seconds",
+        "Time devices were last fetched from Flume",
+    )
+    .unwrap();
+    static ref QUERY_INTERVAL: Gauge = Gauge::new(
+        "flume_water_query_interval_seconds",
+        "Interval between usage queries",
+    )
+    .unwrap();
+    static ref LAST_BUDGETS_UPDATE: Gauge = Gauge::new(
+        "flume_water_last_successful_budgets_update_timestamp_seconds",
+        "Time budgets were last fetched from Flume",
+    )
+    .unwrap();
+    static ref LAST_PERIODS_UPDATE: Gauge = Gauge::new(
+        "flume_water_last_successful_periods_update_timestamp_seconds",
+        "Time usage for today, this week, and this month was last fetched from Flume",
+    )
+    .unwrap();
+    static ref LAST_QUERY_UPDATE: Gauge = Gauge::new(
+        "flume_water_last_successful_query_update_timestamp_seconds",
+        "Time usage was last queried from Flume",
+    )
+    .unwrap();
+    static ref DEVICE_FIRMWARE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_device_firmware_info",
+            "Flume device firmware version",
+        ),
+        &["device_id", "version"],
+    )
+    .unwrap();
+    static ref DEVICE_HARDWARE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_device_hardware_info",
+            "Flume device hardware revision",
+        ),
+        &["device_id", "revision"],
+    )
+    .unwrap();
+    static ref BRIDGE_PRODUCT: GaugeVec = GaugeVec::new(
+        Opts::new("flume_water_bridge_product_info", "Flume bridge product"),
+        &with_device_labels(&["location_id", "location", "device_id", "product"]),
+    )
+    .unwrap();
+    static ref BRIDGE_CONNECTED: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_bridge_connected",
+            "Flume bridge is connected to Flume",
+        ),
+        &with_device_labels(&["location_id", "location", "device_id"]),
+    )
+    .unwrap();
+    static ref BRIDGE_LAST_SEEN: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_bridge_last_seen_timestamp_seconds",
+            "Time the Flume bridge was last seen by Flume",
+        ),
+        &with_device_labels(&["location_id", "location", "device_id"]),
+    )
+    .unwrap();
+    static ref SENSOR_PRODUCT: GaugeVec = GaugeVec::new(
+        Opts::new("flume_water_sensor_product_info", "Flume sensor product"),
+        &with_device_labels(&[
+            "location_id",
+            "location",
+            "device_id",
+            "bridge_id",
+            "product",
+        ]),
+    )
+    .unwrap();
+    static ref SENSOR_BATTERY: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_sensor_battery_info",
+            "Flume sensor battery level",
+        ),
+        &with_device_labels(&["location_id", "location", "device_id", "bridge_id"]),
+    )
+    .unwrap();
+    static ref SENSOR_BATTERY_INFO: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_sensor_battery_level_info",
+            "Flume sensor battery level as reported by Flume",
+        ),
+        &with_device_labels(&["location_id", "location", "device_id", "bridge_id", "level"]),
+    )
+    .unwrap();
+    static ref SENSOR_CONNECTED: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_sensor_connected",
+            "Flume sensor is connected to Flume",
+        ),
+        &with_device_labels(&["location_id", "location", "device_id", "bridge_id"]),
+    )
+    .unwrap();
+    static ref SENSOR_ORIENTED: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_sensor_oriented",
+            "Flume sensor is oriented on the meter",
+        ),
+        &with_device_labels(&["location_id", "location", "device_id", "bridge_id"]),
+    )
+    .unwrap();
+    static ref SENSOR_LAST_SEEN: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_sensor_last_seen_timestamp_seconds",
+            "Time the Flume sensor was last seen by Flume",
+        ),
+        &with_device_labels(&["location_id", "location", "device_id", "bridge_id"]),
+    )
+    .unwrap();
+    static ref BUDGET: GaugeVec = GaugeVec::new(
+        Opts::new("flume_water_budget_liters", "Flume sensor budget"),
+        &["location_id", "location", "period", "name"],
+    )
+    .unwrap();
+    static ref BUDGET_GALLONS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "flume_water_budget_gallons",
+            "Flume sensor budget in gallons, as defined in the Flume app",
+        ),
+        &["location_id", "location", "period", "name"],
+    )
+    .unwrap();
+    static ref BUDGET_USED: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_budget_used_ratio",
+            "Fraction of the Flume sensor budget used this period",
+        ),
+        &["location_id", "location", "period", "name"],
+    )
+    .unwrap();
+    static ref BUDGET_REMAINING: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_budget_remaining_liters",
+            "Flume sensor budget remaining this period",
+        ),
+        &["location_id", "location", "period", "name"],
+    )
+    .unwrap();
+    static ref BUDGET_THRESHOLD_EXCEEDED: CounterVec = CounterVec::new(
+        Opts::new(
+            "flume_water_budget_threshold_exceeded_total",
+            "Number of times a budget's usage crossed one of its thresholds in gallons",
+        ),
+        &["location_id", "location", "period", "name", "threshold"],
+    )
+    .unwrap();
+    static ref NOTIFICATION_RULE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_notification_rule_info",
+            "Usage alert rule configured in the Flume app",
+        ),
+        &[
+            "location_id",
+            "location",
+            "device_id",
+            "rule_id",
+            "name",
+            "type",
+            "threshold",
+            "enabled",
+        ],
+    )
+    .unwrap();
+    static ref USAGE: CounterVec = CounterVec::new(
+        Opts::new("flume_water_usage_liters", "Water usage in liters"),
+        &with_device_labels(&["location_id", "location", "device_id"]),
+    )
+    .unwrap();
+    static ref USAGE_LIFETIME: CounterVec = CounterVec::new(
+        Opts::new(
+            "flume_water_usage_lifetime_liters",
+            "Water usage in liters since the sensor was installed",
+        ),
+        &["location_id", "location", "device_id"],
+    )
+    .unwrap();
+    static ref FLOW_MAX: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_flow_max_liters_per_minute",
+            "Highest flow in a minute during the last usage query window in liters per minute",
+        ),
+        &["location_id", "location", "device_id"],
+    )
+    .unwrap();
+    static ref FLOW_AVG: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_flow_avg_liters_per_minute",
+            "Average flow during the last usage query window in liters per minute",
+        ),
+        &["location_id", "location", "device_id"],
+    )
+    .unwrap();
+    static ref CURRENT_MINUTE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_current_minute_liters",
+            "Usage so far this minute in liters, the real-time usage the Flume app shows",
+        ),
+        &["location_id", "location", "device_id"],
+    )
+    .unwrap();
+    static ref CONTINUOUS_FLOW: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_continuous_flow_minutes",
+            "Minutes of consecutive usage query windows with water flowing, a possible leak",
+        ),
+        &["location_id", "location", "device_id"],
     )
     .unwrap();
-    static ref BRIDGE_CONNECTED: GaugeVec = register_gauge_vec!(
-        "flume_water_bridge_connected",
-        "Flume bridge is connected to Flume",
-        &["location"],
+    static ref SENSOR_LAST_QUERY: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_sensor_last_query_timestamp_seconds",
+            "Time usage was last queried from Flume successfully for the sensor",
+        ),
+        &["location_id", "location", "device_id"],
     )
     .unwrap();
-    static ref SENSOR_PRODUCT: GaugeVec = register_gauge_vec!(
-        "flume_water_sensor_product_info",
-        "Flume sensor product",
-        &["location", "product"],
+    static ref DATA_AGE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_data_age_seconds",
+            "Time since the end of the oldest usage query window of a location's sensors",
+        ),
+        &["location_id", "location"],
     )
     .unwrap();
-    static ref SENSOR_BATTERY: GaugeVec = register_gauge_vec!(
-        "flume_water_sensor_battery_info",
-        "Flume sensor battery level",
-        &["location"],
+    static ref DATA_STALE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_data_stale",
+            "Usage for a location has not been received for more than two query intervals",
+        ),
+        &["location_id", "location"],
     )
     .unwrap();
-    static ref SENSOR_CONNECTED: GaugeVec = register_gauge_vec!(
-        "flume_water_sensor_connected",
-        "Flume sensor is connected to Flume",
-        &["location"],
+    static ref USAGE_TODAY: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_usage_today_liters",
+            "Water usage since midnight in liters",
+        ),
+        &["location_id", "location", "device_id"],
     )
     .unwrap();
-    static ref BUDGET: IntGaugeVec = register_int_gauge_vec!(
-        "flume_water_budget_liters",
-        "Flume sensor budget",
-        &["location", "period", "name"],
+    static ref USAGE_THIS_WEEK: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_usage_this_week_liters",
+            "Water usage since the start of the week in liters",
+        ),
+        &["location_id", "location", "device_id"],
     )
     .unwrap();
-    static ref USAGE: CounterVec = register_counter_vec!(
-        "flume_water_usage_liters",
-        "Water usage in liters",
-        &["location"],
+    static ref USAGE_THIS_MONTH: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_usage_this_month_liters",
+            "Water usage since the start of the month in liters",
+        ),
+        &["location_id", "location", "device_id"],
     )
     .unwrap();
 }
 
-pub struct Downloader {
+/// Add the polling, device, budget, and usage metrics to `registry`
+pub(crate) fn register(registry: &Registry) -> Result<()> {
+    registry.register(Box::new(UP.clone()))?;
+    registry.register(Box::new(CYCLE_DURATIONS.clone()))?;
+    registry.register(Box::new(SKIPPED_TICKS.clone()))?;
+    registry.register(Box::new(LAST_DEVICES_UPDATE.clone()))?;
+    registry.register(Box::new(QUERY_INTERVAL.clone()))?;
+    registry.register(Box::new(LAST_BUDGETS_UPDATE.clone()))?;
+    registry.register(Box::new(LAST_PERIODS_UPDATE.clone()))?;
+    registry.register(Box::new(LAST_QUERY_UPDATE.clone()))?;
+    registry.register(Box::new(DEVICE_FIRMWARE.clone()))?;
+    registry.register(Box::new(DEVICE_HARDWARE.clone()))?;
+    registry.register(Box::new(BRIDGE_PRODUCT.clone()))?;
+    registry.register(Box::new(BRIDGE_CONNECTED.clone()))?;
+    registry.register(Box::new(BRIDGE_LAST_SEEN.clone()))?;
+    registry.register(Box::new(SENSOR_PRODUCT.clone()))?;
+    registry.register(Box::new(SENSOR_BATTERY.clone()))?;
+    registry.register(Box::new(SENSOR_BATTERY_INFO.clone()))?;
+    registry.register(Box::new(SENSOR_CONNECTED.clone()))?;
+    registry.register(Box::new(SENSOR_ORIENTED.clone()))?;
+    registry.register(Box::new(SENSOR_LAST_SEEN.clone()))?;
+    registry.register(Box::new(BUDGET.clone()))?;
+    registry.register(Box::new(BUDGET_GALLONS.clone()))?;
+    registry.register(Box::new(BUDGET_USED.clone()))?;
+    registry.register(Box::new(BUDGET_REMAINING.clone()))?;
+    registry.register(Box::new(BUDGET_THRESHOLD_EXCEEDED.clone()))?;
+    registry.register(Box::new(NOTIFICATION_RULE.clone()))?;
+    registry.register(Box::new(USAGE.clone()))?;
+    registry.register(Box::new(USAGE_LIFETIME.clone()))?;
+    registry.register(Box::new(FLOW_MAX.clone()))?;
+    registry.register(Box::new(FLOW_AVG.clone()))?;
+    registry.register(Box::new(CURRENT_MINUTE.clone()))?;
+    registry.register(Box::new(CONTINUOUS_FLOW.clone()))?;
+    registry.register(Box::new(SENSOR_LAST_QUERY.clone()))?;
+    registry.register(Box::new(DATA_AGE.clone()))?;
+    registry.register(Box::new(DATA_STALE.clone()))?;
+    registry.register(Box::new(USAGE_TODAY.clone()))?;
+    registry.register(Box::new(USAGE_THIS_WEEK.clone()))?;
+    registry.register(Box::new(USAGE_THIS_MONTH.clone()))?;
+
+    Ok(())
+}
+
+/// Names of the configured device labels, set before the device metrics are first used
+static DEVICE_LABEL_NAMES: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Add `names` to the labels of the device and usage metrics.
+///
+/// Must be called before the first metric update.  The metrics are shared by the whole process,
+/// so calling this again with different names is an error.
+pub fn set_device_label_names(names: &[String]) -> Result<()> {
+    let set = DEVICE_LABEL_NAMES.get_or_init(|| names.to_vec());
+
+    if set != names {
+        return Err(anyhow!(
+            "device labels are already set to {:?} for this process, got {:?}",
+            set,
+            names
+        ));
+    }
+
+    Ok(())
+}
+
+/// `base` followed by the configured device label names
+fn with_device_labels(base: &[&'static str]) -> Vec<&'static str> {
+    let mut names = base.to_vec();
+
+    if let Some(device_labels) = DEVICE_LABEL_NAMES.get() {
+        names.extend(device_labels.iter().map(String::as_str));
+    }
+
+    names
+}
+
+/// Requests per hour adaptive polling aims for, leaving room under the rate limit for token
+/// refreshes and retries
+const ADAPTIVE_LIMIT: f64 = request_window::HOURLY_LIMIT * 0.9;
+
+/// How often to check again for budgets on a sensor that had none
+const BUDGET_RECHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Year lifetime usage is summed from when Flume doesn't give a location's installation date
+const FLUME_FIRST_SENSORS: i32 = 2015;
+
+/// Most the adaptive query interval is multiplied by after being rate limited
+const MAX_THROTTLE_FACTOR: u32 = 16;
+
+pub struct Downloader<A = Client> {
     error_tx: Sender,
     budget_interval: Duration,
     device_interval: Duration,
+    period_interval: Duration,
     query_interval: Duration,
+    query_jitter: Duration,
+    query_lag: Duration,
+    query_settings: QuerySettings,
+    flow_metrics: bool,
+    current_minute_interval: Option<Duration>,
+    current_minute_last_update: Option<Instant>,
+    adaptive_polling: bool,
+    throttle_factor: u32,
+    throttled_at: Option<Instant>,
+    continuous_flow: HashMap<String, f64>,
+    lifetime_started: HashSet<String>,
+    flow_rate_started: HashSet<String>,
+    query_deadlines: HashMap<String, QueryDeadline>,
 
-    flume: Flume,
+    flume: Flume<A>,
 
     user_id: Option<i64>,
+    periods_last_update: Option<Instant>,
+    period_dates: HashMap<String, NaiveDate>,
     budgets_last_update: Option<Instant>,
+    budgetless: HashMap<String, Instant>,
+    budget_readings: HashMap<(String, String, String), BudgetReading>,
+    notification_rules_last_update: Option<Instant>,
     devices_last_update: Option<Instant>,
     sensors: Option<Vec<Sensor>>,
+
+    restored_last_updates: HashMap<String, DateTime<FixedOffset>>,
+    state_tx: watch::Sender<State>,
+    debug_tx: watch::Sender<DebugState>,
+    cycle_started_at: Option<DateTime<Utc>>,
+    cycle_finished_at: Option<DateTime<Utc>>,
+    state_file: Option<PathBuf>,
+    device_filter: DeviceFilter,
+    device_labels: DeviceLabels,
+    relabel: Relabel,
+    series: SeriesTracker,
+    scrape_driven: bool,
+    scrape_rx: Option<mpsc::Receiver<ScrapeRequest>>,
+    restart_rx: Option<mpsc::Receiver<Flume<A>>>,
+    away_mode_rx: Option<mpsc::Receiver<AwayModeRequest>>,
+    updates_tx: broadcast::Sender<Update>,
 }
 
-impl Downloader {
+impl<A: FlumeApi> Downloader<A> {
     pub fn new(
-        flume: Flume,
+        flume: Flume<A>,
         budget_interval: Duration,
         device_interval: Duration,
+        period_interval: Duration,
         query_interval: Duration,
+        query_jitter: Duration,
         error_tx: Sender,
     ) -> Self {
+        let (state_tx, _) = watch::channel(State::default());
+        let (debug_tx, _) = watch::channel(DebugState::default());
+        let (updates_tx, _) = broadcast::channel(64);
+
         Downloader {
             error_tx,
             budget_interval,
             device_interval,
+            period_interval,
             query_interval,
+            query_jitter,
+            query_lag: Duration::ZERO,
+            query_settings: QuerySettings::default(),
+            flow_metrics: false,
+            current_minute_interval: None,
+            current_minute_last_update: None,
+            adaptive_polling: false,
+            throttle_factor: 1,
+            throttled_at: None,
+            continuous_flow: HashMap::new(),
+            lifetime_started: HashSet::new(),
+            flow_rate_started: HashSet::new(),
+            query_deadlines: HashMap::new(),
 
             flume,
 
             user_id: None,
 
+            periods_last_update: None,
+            period_dates: HashMap::new(),
             budgets_last_update: None,
+            budgetless: HashMap::new(),
+            budget_readings: HashMap::new(),
+            notification_rules_last_update: None,
             devices_last_update: None,
             sensors: None,
+
+            restored_last_updates: HashMap::new(),
+            state_tx,
+            debug_tx,
+            cycle_started_at: None,
+            cycle_finished_at: None,
+            state_file: None,
+            device_filter: DeviceFilter::default(),
+            device_labels: DeviceLabels::default(),
+            relabel: Relabel::default(),
+            series: SeriesTracker::default(),
+            scrape_driven: false,
+            scrape_rx: None,
+            restart_rx: None,
+            away_mode_rx: None,
+            updates_tx,
         }
     }
 
-    pub async fn start(mut self) {
-        tokio::spawn(async move {
-            let mut interval = interval(self.query_interval);
-            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    /// Resume from a state snapshot taken by another exporter.
+    ///
+    /// Usage totals are added to the usage counters immediately, sensor query windows are resumed
+    /// when the sensors are first fetched.
+    pub fn restore(mut self, state: State) -> Self {
+        self.user_id = state.user_id.or(self.user_id);
 
-            loop {
-                match self.update().await {
-                    Ok(_) => (),
-                    Err(e) => self.handle_error(e).await,
-                };
+        for usage in state.usage {
+            let labels = usage_labels(
+                &self.device_labels,
+                &usage.location_id,
+                &usage.location,
+                &usage.device_id,
+            );
 
-                interval.tick().await;
-            }
-        });
+            USAGE.with_label_values(&labels).inc_by(usage.liters);
+        }
+
+        self.restored_last_updates = state
+            .sensors
+            .into_iter()
+            .map(|sensor| (sensor.id, sensor.last_update))
+            .collect();
+
+        self
     }
 
-    async fn handle_error(&mut self, error: Error) {
-        for cause in error.chain() {
-            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
-                if e.is_timeout() || e.is_request() || e.is_connect() {
-                    error!("Ignoring error {:?}", error);
+    /// End usage query windows `query_lag` before now so minutes the Flume API has not finished
+    /// recording are queried in the next window
+    pub fn query_lag(mut self, query_lag: Duration) -> Self {
+        self.query_lag = query_lag;
 
-                    return;
-                }
-            }
-        }
+        self
+    }
 
-        self.error_tx
-            .send(error)
-            .await
-            .expect("Error propagation failed");
+    /// Query usage with the bucket and operation in `query_settings` for each sensor
+    pub fn query_settings(mut self, query_settings: QuerySettings) -> Self {
+        self.query_settings = query_settings;
+
+        self
     }
 
-    async fn update(&mut self) -> Result<()> {
-        // refresh sensors first, then fetch extra data based on current sensors
-        self.devices().await?;
+    /// Also query the peak and average flow over each usage query window
+    pub fn flow_metrics(mut self, flow_metrics: bool) -> Self {
+        self.flow_metrics = flow_metrics;
 
-        self.query().await?;
+        self
+    }
 
-        self.budgets().await?;
+    /// Also query each sensor's usage so far this minute every `interval`, independently of the
+    /// usage queries
+    pub fn current_minute_interval(mut self, interval: Option<Duration>) -> Self {
+        self.current_minute_interval = interval;
 
-        Ok(())
+        self
     }
 
-    async fn user_id(&mut self) -> Result<i64> {
-        if let Some(user_id) = self.user_id {
-            return Ok(user_id);
-        }
+    /// Lengthen the query interval to stay under the Flume API rate limit as sensors are added and
+    /// when the API rejects requests for exceeding it
+    pub fn adaptive_polling(mut self, adaptive_polling: bool) -> Self {
+        self.adaptive_polling = adaptive_polling;
 
-        match self.flume.user_id().await {
-            Ok(user_id) => {
-                self.user_id = Some(user_id);
-                debug!("user_id: {:?}", self.user_id)
+        self
+    }
+
+    /// Only poll devices selected by `device_filter`
+    pub fn filter_devices(mut self, device_filter: DeviceFilter) -> Self {
+        self.device_filter = device_filter;
+
+        self
+    }
+
+    /// Add the configured `device_labels` to each device's metrics.  The names must have been
+    /// passed to `set_device_label_names` first.
+    pub fn device_labels(mut self, device_labels: DeviceLabels) -> Self {
+        self.device_labels = device_labels;
+
+        self
+    }
+
+    /// Rename locations and products and drop devices with `relabel` before setting metrics
+    pub fn relabel(mut self, relabel: Relabel) -> Self {
+        self.relabel = relabel;
+
+        self
+    }
+
+    /// Save a state snapshot to `state_file` after each polling cycle
+    pub fn persist_to(mut self, state_file: Option<PathBuf>) -> Self {
+        self.state_file = state_file;
+
+        self
+    }
+
+    /// Subscribe to state snapshots, updated after each polling cycle
+    pub fn state(&self) -> watch::Receiver<State> {
+        self.state_tx.subscribe()
+    }
+
+    /// Subscribe to the scheduling and token status, updated as each polling cycle starts and
+    /// finishes
+    pub fn debug_state(&self) -> watch::Receiver<DebugState> {
+        self.debug_tx.subscribe()
+    }
+
+    /// Subscribe to updates, sent as each device, budget, and usage query is refreshed
+    pub fn updates(&self) -> broadcast::Receiver<Update> {
+        self.updates_tx.subscribe()
+    }
+
+    /// Poll Flume when the metrics are scraped instead of on the query interval.
+    ///
+    /// Send a scrape request on the returned channel to trigger an update, the reply is sent when
+    /// the update finishes.  Updates are made at most once per query interval.
+    pub fn poll_on_scrape(&mut self) -> ScrapeSender {
+        let (scrape_tx, scrape_rx) = mpsc::channel(8);
+
+        self.scrape_driven = true;
+        self.scrape_rx = Some(scrape_rx);
+
+        scrape_tx
+    }
+
+    /// Restart with each `Flume` sent on the returned channel.
+    ///
+    /// The new `Flume` replaces the current one, the user, devices, periods, and budgets are
+    /// fetched again, and usage queries continue where they left off.
+    pub fn restart_on(&mut self) -> mpsc::Sender<Flume<A>> {
+        let (restart_tx, restart_rx) = mpsc::channel(1);
+
+        self.restart_rx = Some(restart_rx);
+
+        restart_tx
+    }
+
+    /// Change the away mode of locations as requested on the returned channel.
+    ///
+    /// Requests are made while waiting for the next polling cycle so they share the access token
+    /// and request limit with polling.
+    pub fn control_away_mode(&mut self) -> AwayModeSender {
+        let (away_mode_tx, away_mode_rx) = mpsc::channel(8);
+
+        self.away_mode_rx = Some(away_mode_rx);
+
+        away_mode_tx
+    }
+
+    pub async fn start(mut self) {
+        tokio::spawn(async move {
+            let restart_rx = self.restart_rx.take();
+
+            match self.scrape_rx.take() {
+                Some(scrape_rx) => self.run_on_scrape(scrape_rx, restart_rx).await,
+                None => self.run_on_interval(restart_rx).await,
             }
-            Err(e) => self.handle_error(e).await,
-        }
+        });
+    }
 
-        self.user_id
-            .ok_or_else(|| anyhow!("Missing user_id somehow"))
+    fn restart(&mut self, flume: Flume<A>) {
+        info!("Restarting downloader");
+
+        self.flume = flume;
+        self.user_id = None;
+        self.devices_last_update = None;
+        self.periods_last_update = None;
+        self.budgets_last_update = None;
+        self.notification_rules_last_update = None;
     }
 
-    async fn devices(&mut self) -> Result<bool> {
-        if let Some(last_update) = self.devices_last_update {
-            if Instant::now().duration_since(last_update) < self.device_interval {
-                return Ok(false);
+    async fn run_on_interval(&mut self, mut restart_rx: Option<mpsc::Receiver<Flume<A>>>) {
+        let mut period = self.query_interval;
+        let mut interval = interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        QUERY_INTERVAL.set(period.as_secs_f64());
+
+        loop {
+            let started = Instant::now();
+            self.poll().await;
+
+            // Ticks missed during a slow cycle are skipped, not run late
+            let skipped = started.elapsed().as_secs_f64() / period.as_secs_f64();
+            SKIPPED_TICKS.inc_by(skipped.floor() as u64);
+
+            let next_period = self.current_query_interval();
+
+            if next_period != period {
+                info!("Querying usage every {}s", next_period.as_secs());
+
+                period = next_period;
+                interval = interval_at(tokio::time::Instant::now() + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                QUERY_INTERVAL.set(period.as_secs_f64());
             }
-        }
 
-        let mut sensors = Vec::new();
+            let next_query = self.next_query_due();
 
-        let user_id = self.user_id().await?;
+            tokio::select! {
+                _ = self.wait_for(next_tick(&mut interval, next_query)) => (),
+                Some(flume) = restarted(&mut restart_rx) => self.restart(flume),
+            }
+        }
+    }
 
-        let devices = self.flume.devices(user_id).await?;
-        debug!("Found {} devices", devices.len());
+    async fn run_on_scrape(
+        &mut self,
+        mut scrape_rx: mpsc::Receiver<ScrapeRequest>,
+        mut restart_rx: Option<mpsc::Receiver<Flume<A>>>,
+    ) {
+        let mut last_poll: Option<Instant> = None;
+        QUERY_INTERVAL.set(self.query_interval.as_secs_f64());
 
-        for device in devices {
-            match device {
-                Device::Bridge(b) => update_bridge(&b),
-                Device::Sensor(s) => {
-                    update_sensor(&s);
+        loop {
+            let reply = tokio::select! {
+                reply = self.wait_for(scrape_rx.recv()) => match reply {
+                    Some(reply) => reply,
+                    None => break,
+                },
+                Some(flume) = restarted(&mut restart_rx) => {
+                    self.restart(flume);
+                    last_poll = None;
 
-                    sensors.push(s);
+                    continue;
                 }
             };
-        }
 
-        self.sensors = Some(sensors);
-        self.devices_last_update = Some(Instant::now());
+            let query_interval = self.current_query_interval();
+            QUERY_INTERVAL.set(query_interval.as_secs_f64());
 
-        Ok(true)
-    }
+            let due = match last_poll {
+                Some(last_poll) => last_poll.elapsed() >= query_interval,
+                None => true,
+            };
 
-    async fn budgets(&mut self) -> Result<bool> {
-        if let Some(last_update) = self.budgets_last_update {
-            if Instant::now().duration_since(last_update) < self.budget_interval {
-                return Ok(false);
+            if due {
+                self.poll().await;
+                last_poll = Some(Instant::now());
             }
-        }
 
-        let user_id = self.user_id().await?;
+            let _ = reply.send(());
 
-        if let Some(sensors) = &self.sensors {
-            for sensor in sensors {
-                let location = &sensor.sensor.location.as_ref().unwrap().name;
+            // Scrapes that arrived during the update get the same data
+            while let Ok(reply) = scrape_rx.try_recv() {
+                let _ = reply.send(());
+            }
+        }
+    }
 
-                let budgets = self.flume.budgets(user_id, sensor).await?;
+    /// Wait for `until`, querying the usage so far this minute each time it is due and starting
+    /// away mode changes requested meanwhile
+    async fn wait_for<F: Future>(&mut self, until: F) -> F::Output {
+        tokio::pin!(until);
 
-                budgets.iter().for_each(|budget| {
-                    let gallons = budget.value as f64;
-                    let liters = (gallons * 3.7854) as i64;
+        loop {
+            let due = self.current_minute_due();
 
-                    BUDGET
-                        .with_label_values(&[location, &budget.period.to_string(), &budget.name])
-                        .set(liters)
-                });
+            tokio::select! {
+                output = &mut until => return output,
+                _ = sleep_until(due.unwrap_or_else(tokio::time::Instant::now)), if due.is_some() => {
+                    self.current_minute().await
+                }
+                request = away_mode_requested(&mut self.away_mode_rx) => match request {
+                    Some(request) => self.away_mode(request),
+                    None => self.away_mode_rx = None,
+                },
             }
         }
+    }
 
-        self.budgets_last_update = Some(Instant::now());
+    /// Change away mode in its own task so a restart can't drop the request before it is
+    /// answered
+    fn away_mode(&self, request: AwayModeRequest) {
+        let location_id = self
+            .sensors
+            .iter()
+            .flatten()
+            .find(|s| s.location_id == request.location || s.location == request.location)
+            .map(|s| s.location_id.clone());
+        let flume = self.flume.clone();
+        let user_id = self.user_id;
 
-        Ok(true)
+        crate::spawn_named(
+            async move {
+                let result = match location_id {
+                    Some(location_id) => {
+                        set_away_mode(flume, user_id, &location_id, request.away_mode).await
+                    }
+                    None => Err(UnknownLocation(request.location.clone()).into()),
+                };
+
+                if let Err(e) = &result {
+                    warn!(
+                        "Unable to change away mode of {}: {:#}",
+                        request.location, e
+                    );
+                }
+
+                let _ = request.reply.send(result);
+            },
+            "away_mode",
+        );
     }
 
-    async fn query(&mut self) -> Result<()> {
-        let user_id = self.user_id().await?;
+    /// When the next sensor's usage query is due, `None` before sensors are first queried
+    fn next_query_due(&self) -> Option<tokio::time::Instant> {
+        self.query_deadlines.values().map(|d| d.due).min()
+    }
 
-        if let Some(sensors) = &self.sensors {
-            let mut updated_sensors = Vec::with_capacity(sensors.len());
+    /// When the usage so far this minute is next queried, `None` when it isn't
+    fn current_minute_due(&self) -> Option<tokio::time::Instant> {
+        let interval = self.current_minute_interval?;
 
-            for sensor in sensors {
-                let (new_usage, until_time) = self.flume.query_sensor(user_id, sensor).await?;
+        let due = match self.current_minute_last_update {
+            Some(last_update) => last_update + interval,
+            None => Instant::now(),
+        };
 
-                let id = &sensor.sensor.id;
-                let location = &sensor.sensor.location.as_ref().unwrap().name;
+        Some(tokio::time::Instant::from_std(due))
+    }
 
-                debug!("Sensor {} used {} liters", id, new_usage);
-                USAGE.with_label_values(&[location]).inc_by(new_usage);
+    async fn current_minute(&mut self) {
+        self.current_minute_last_update = Some(Instant::now());
 
-                updated_sensors.push(sensor.with_updated_timestamp(until_time));
-            }
+        if let Err(e) = self.update_current_minute().await {
+            self.handle_error(e).await;
+        }
+    }
+
+    /// Query each sensor's usage so far this minute.  Waits for the first polling cycle to find
+    /// the user and sensors.
+    async fn update_current_minute(&mut self) -> Result<()> {
+        let (Some(user_id), Some(sensors)) = (self.user_id, self.sensors.clone()) else {
+            return Ok(());
+        };
 
-            self.sensors = Some(updated_sensors);
+        for sensor in &sensors {
+            let liters = self.flume.query_current_minute(user_id, sensor).await?;
+
+            let labels = [
+                sensor.location_id.as_str(),
+                sensor.location.as_str(),
+                sensor.sensor.id.as_str(),
+            ];
+            CURRENT_MINUTE.with_label_values(&labels).set(liters);
+            self.series.replace(
+                &sensor.sensor.id,
+                vec![Series::gauge(&CURRENT_MINUTE, &labels)],
+            );
         }
 
         Ok(())
     }
-}
 
-fn update_bridge(bridge: &Bridge) {
-    let location = &bridge.location;
-    let product = &bridge.product;
-    let connected = if bridge.connected { 1.0 } else { 0.0 };
+    async fn poll(&mut self) {
+        let cycle = info_span!("cycle");
+        let timer = CYCLE_DURATIONS.start_timer();
 
-    BRIDGE_PRODUCT
-        .with_label_values(&[location, product])
-        .set(1.0);
-    BRIDGE_CONNECTED
-        .with_label_values(&[location])
-        .set(connected);
-}
+        self.cycle_started_at = Some(Utc::now());
+        self.publish_debug_state();
 
-fn update_sensor(sensor: &Sensor) {
-    let sensor = &sensor.sensor;
-    let location = &sensor.location.as_ref().unwrap().name;
-    let product = &sensor.product;
+        let result = self.update().instrument(cycle.clone()).await;
+        timer.observe_duration();
 
-    let connected = if sensor.connected { 1.0 } else { 0.0 };
-    let battery_level = if BATTERY_HIGH == sensor.battery_level {
-        1.0
-    } else if BATTERY_MEDIUM == sensor.battery_level {
-        0.5
-    } else if BATTERY_LOW == sensor.battery_level {
-        0.25
-    } else {
-        0.0
-    };
+        if result.is_ok() {
+            UP.set(1);
+            self.recover_from_throttling();
+        } else {
+            UP.set(0);
+        }
 
-    SENSOR_PRODUCT
-        .with_label_values(&[location, product])
-        .set(1.0);
-    SENSOR_BATTERY
-        .with_label_values(&[location])
-        .set(battery_level);
-    SENSOR_CONNECTED
-        .with_label_values(&[location])
-        .set(connected);
+        self.update_staleness();
+
+        // Published before the error is handled so a restart keeps the current token
+        cycle.in_scope(|| self.publish_state());
+
+        if let Err(e) = result {
+            self.handle_error(e).await;
+        }
+
+        self.cycle_finished_at = Some(Utc::now());
+        self.publish_debug_state();
+    }
+
+    /// Export the age of each location's usage data.  The last values of the other metrics keep
+    /// being served when Flume can't be reached, staleness tells no water used from no data.
+    fn update_staleness(&mut self) {
+        let query_interval = self.current_query_interval();
+        let sensors = self.sensors.as_deref().unwrap_or_default();
+
+        // The oldest sensor at a location sets its age, any stale sensor makes it stale
+        let mut locations: HashMap<(String, String), (Duration, bool)> = HashMap::new();
+
+        for sensor in sensors {
+            let usage_query = self.query_settings.for_device(&sensor.sensor.id);
+
+            // A window isn't queried until its sensor's interval and a whole bucket have passed
+            let interval = usage_query
+                .interval
+                .unwrap_or_default()
+                .max(query_interval)
+                .max(Duration::from_secs_f64(
+                    bucket_minutes(&usage_query.bucket) * 60.0,
+                ));
+
+            let age = (sensor.now() - sensor.last_update)
+                .to_std()
+                .unwrap_or_default();
+            let stale = age > interval * 2 + self.query_lag;
+
+            let location = locations
+                .entry((sensor.location_id.clone(), sensor.location.clone()))
+                .or_default();
+            location.0 = location.0.max(age);
+            location.1 |= stale;
+        }
+
+        let sensors: Vec<(String, String, String)> = sensors
+            .iter()
+            .map(|s| {
+                (
+                    s.sensor.id.clone(),
+                    s.location_id.clone(),
+                    s.location.clone(),
+                )
+            })
+            .collect();
+
+        for (device_id, location_id, location) in sensors {
+            let (age, stale) = locations[&(location_id.clone(), location.clone())];
+            let labels = [location_id.as_str(), location.as_str()];
+
+            DATA_AGE.with_label_values(&labels).set(age.as_secs_f64());
+            DATA_STALE
+                .with_label_values(&labels)
+                .set(if stale { 1.0 } else { 0.0 });
+
+            self.series.replace(
+                &device_id,
+                vec![
+                    Series::gauge(&DATA_AGE, &labels),
+                    Series::gauge(&DATA_STALE, &labels),
+                ],
+            );
+        }
+    }
+
+    fn publish_debug_state(&self) {
+        let sensors = self
+            .sensors
+            .iter()
+            .flatten()
+            .map(|sensor| SensorDebugState {
+                id: sensor.sensor.id.clone(),
+                location_id: sensor.location_id.clone(),
+                location: sensor.location.clone(),
+                last_update: sensor.last_update.fixed_offset(),
+                last_seen: sensor.last_seen.fixed_offset(),
+                continuous_flow_minutes: self
+                    .continuous_flow
+                    .get(&sensor.sensor.id)
+                    .copied()
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        let token_expires_at =
+            DateTime::from_timestamp(self.flume.token_state().expires_at as i64, 0);
+
+        self.debug_tx.send_replace(DebugState {
+            user_id: self.user_id,
+            token_expires_at,
+            cycle_started_at: self.cycle_started_at,
+            cycle_finished_at: self.cycle_finished_at,
+            query_interval_seconds: self.current_query_interval().as_secs(),
+            throttle_factor: self.throttle_factor,
+            devices: Timer::new(self.devices_last_update, self.device_interval),
+            periods: Timer::new(self.periods_last_update, self.period_interval),
+            budgets: Timer::new(self.budgets_last_update, self.budget_interval),
+            sensors,
+        });
+    }
+
+    fn publish_state(&self) {
+        let state = self.snapshot();
+
+        if let Some(state_file) = &self.state_file {
+            if let Err(e) = state.save(state_file) {
+                error!("{:#}", e);
+            }
+        }
+
+        self.state_tx.send_replace(state);
+    }
+
+    fn snapshot(&self) -> State {
+        let sensors = self.sensors.as_deref().unwrap_or_default();
+
+        let usage = sensors
+            .iter()
+            .map(|sensor| UsageState {
+                location_id: sensor.location_id.clone(),
+                location: sensor.location.clone(),
+                device_id: sensor.sensor.id.clone(),
+                liters: USAGE
+                    .with_label_values(&usage_labels(
+                        &self.device_labels,
+                        &sensor.location_id,
+                        &sensor.location,
+                        &sensor.sensor.id,
+                    ))
+                    .get(),
+            })
+            .collect();
+
+        let sensors = sensors
+            .iter()
+            .map(|sensor| SensorState {
+                id: sensor.sensor.id.clone(),
+                last_update: sensor
+                    .last_update
+                    .with_timezone(&sensor.last_update.offset().fix()),
+            })
+            .collect();
+
+        State {
+            user_id: self.user_id,
+            token: Some(self.flume.token_state()),
+            sensors,
+            usage,
+        }
+    }
+
+    async fn handle_error(&mut self, error: Error) {
+        let rate_limited = error.chain().any(|cause| cause.is::<RateLimited>());
+
+        if self.adaptive_polling && rate_limited {
+            self.throttle_factor = (self.throttle_factor * 2).min(MAX_THROTTLE_FACTOR);
+            self.throttled_at = Some(Instant::now());
+
+            warn!(
+                "Rate limited by the Flume API, slowing usage queries to every {}s",
+                self.current_query_interval().as_secs()
+            );
+
+            return;
+        }
+
+        if supervisor::is_transient(&error) {
+            error!("Ignoring error {:?}", error);
+
+            return;
+        }
+
+        self.error_tx
+            .send(error)
+            .await
+            .expect("Error propagation failed");
+    }
+
+    async fn update(&mut self) -> Result<()> {
+        // refresh sensors first, then fetch extra data based on current sensors
+        self.devices().await?;
+
+        // Period usage is queried along with each sensor's usage when both are due
+        let periods_due = self.periods_due();
+        let period_dates = self.query(periods_due).await?;
+
+        self.periods(periods_due, period_dates).await?;
+
+        self.budgets().await?;
+
+        self.notification_rules().await?;
+
+        Ok(())
+    }
+
+    async fn user_id(&mut self) -> Result<i64> {
+        if let Some(user_id) = self.user_id {
+            return Ok(user_id);
+        }
+
+        match self.flume.user_id().await {
+            Ok(user_id) => {
+                self.user_id = Some(user_id);
+                debug!("user_id: {:?}", self.user_id)
+            }
+            Err(e) => self.handle_error(e).await,
+        }
+
+        self.user_id
+            .ok_or_else(|| anyhow!("Missing user_id somehow"))
+    }
+
+    async fn devices(&mut self) -> Result<bool> {
+        if let Some(last_update) = self.devices_last_update {
+            if Instant::now().duration_since(last_update) < self.device_interval {
+                return Ok(false);
+            }
+        }
+
+        let mut sensors = Vec::new();
+        let mut device_ids = HashSet::new();
+
+        // Continue each sensor's query window, the last seen time from the API would restart it
+        let mut last_updates: HashMap<String, DateTime<Tz>> = self
+            .sensors
+            .iter()
+            .flatten()
+            .map(|s| (s.sensor.id.clone(), s.last_update))
+            .collect();
+
+        let user_id = self.user_id().await?;
+
+        let devices = self.flume.devices(user_id).await?;
+        debug!("Found {} devices", devices.len());
+
+        for device in devices {
+            if !self.device_filter.includes(device.id()) {
+                debug!(device_id = device.id(), "Skipping device");
+                continue;
+            }
+
+            let device_id = device.id().to_string();
+            let device = match self.relabel.apply(device) {
+                Some(device) => device,
+                None => {
+                    debug!(device_id, "Dropping device by relabel rule");
+                    continue;
+                }
+            };
+
+            device_ids.insert(device.id().to_string());
+
+            match device {
+                Device::Bridge(b) => {
+                    self.series
+                        .replace(&b.id, update_bridge(&b, &self.device_labels.values(&b.id)));
+
+                    let _ = self.updates_tx.send(Update::bridge(&b));
+                }
+                Device::Sensor(s) => {
+                    let timezone = s.last_update.timezone();
+                    let last_update = last_updates.remove(&s.sensor.id).or_else(|| {
+                        self.restored_last_updates
+                            .remove(&s.sensor.id)
+                            .map(|t| sensor::start_of_minute(t.with_timezone(&timezone)))
+                    });
+
+                    let s = match last_update {
+                        Some(last_update) => s.with_updated_timestamp(last_update),
+                        None => s,
+                    };
+
+                    self.series.replace(
+                        &s.sensor.id,
+                        update_sensor(&s, &self.device_labels.values(&s.sensor.id)),
+                    );
+
+                    let _ = self.updates_tx.send(Update::sensor(&s));
+
+                    sensors.push(s);
+                }
+            };
+        }
+
+        // Stop exporting devices that were removed from the account or are no longer polled
+        self.series.retain(&device_ids);
+        self.continuous_flow.retain(|id, _| device_ids.contains(id));
+        self.query_deadlines.retain(|id, _| device_ids.contains(id));
+        self.budgetless.retain(|id, _| device_ids.contains(id));
+        self.budget_readings
+            .retain(|(id, _, _), _| device_ids.contains(id));
+
+        let sensor_count = self.sensors.as_ref().map(Vec::len);
+
+        if sensor_count != Some(sensors.len()) {
+            self.check_request_rate(&sensors);
+        }
+
+        self.sensors = Some(sensors);
+        self.devices_last_update = Some(Instant::now());
+        LAST_DEVICES_UPDATE.set(unix_now());
+
+        Ok(true)
+    }
+
+    /// Warn when polling `sensors` with the configured intervals will exceed the Flume API rate
+    /// limit
+    fn check_request_rate(&self, sensors: &[Sensor]) {
+        if self.adaptive_polling {
+            let fixed = self.fixed_requests_per_hour(sensors);
+
+            if fixed > ADAPTIVE_LIMIT {
+                warn!(
+                    "Polling {} sensors will make about {:.0} Flume API requests per hour before usage queries, over the limit of {}, increase period_interval, budget_interval, device_interval, or sensor query_interval overrides",
+                    sensors.len(),
+                    fixed,
+                    request_window::HOURLY_LIMIT
+                );
+            }
+
+            return;
+        }
+
+        let per_hour = self.fixed_requests_per_hour(sensors)
+            + self.queries_per_poll(sensors) * request_window::per_hour(self.query_interval);
+
+        if per_hour > request_window::HOURLY_LIMIT {
+            warn!(
+                "Polling {} sensors will make about {:.0} Flume API requests per hour, over the limit of {}, increase query_interval, period_interval, budget_interval, or device_interval",
+                sensors.len(),
+                per_hour,
+                request_window::HOURLY_LIMIT
+            );
+        } else {
+            debug!(
+                "Polling {} sensors will make about {:.0} Flume API requests per hour",
+                sensors.len(),
+                per_hour
+            );
+        }
+    }
+
+    /// Requests per hour for devices, periods and notification rules of `sensors`, budgets of
+    /// `sensors` that have them, usage queries of sensors with their own query interval, and
+    /// current minute queries
+    fn fixed_requests_per_hour(&self, sensors: &[Sensor]) -> f64 {
+        let count = sensors.len() as f64;
+
+        let overridden: f64 = sensors
+            .iter()
+            .filter_map(|sensor| self.query_settings.for_device(&sensor.sensor.id).interval)
+            .map(|interval| {
+                self.queries_per_sensor()
+                    * request_window::per_hour(interval.max(self.query_interval))
+            })
+            .sum();
+
+        let with_budgets = sensors
+            .iter()
+            .filter(|sensor| !self.budgetless.contains_key(&sensor.sensor.id))
+            .count() as f64;
+
+        request_window::per_hour(self.device_interval)
+            + count * request_window::per_hour(self.period_interval)
+            + with_budgets * request_window::per_hour(self.budget_interval)
+            + count * request_window::per_hour(self.device_interval)
+            + overridden
+            + self
+                .current_minute_interval
+                .map_or(0.0, |interval| count * request_window::per_hour(interval))
+    }
+
+    /// Requests made by each usage query of `sensors` without their own query interval
+    fn queries_per_poll(&self, sensors: &[Sensor]) -> f64 {
+        let polled = sensors
+            .iter()
+            .filter(|sensor| {
+                self.query_settings
+                    .for_device(&sensor.sensor.id)
+                    .interval
+                    .is_none()
+            })
+            .count();
+
+        polled as f64 * self.queries_per_sensor()
+    }
+
+    /// Requests made by each usage query of one sensor
+    fn queries_per_sensor(&self) -> f64 {
+        if self.flow_metrics {
+            2.0
+        } else {
+            1.0
+        }
+    }
+
+    /// Interval between usage queries.
+    ///
+    /// With adaptive polling this is the shortest interval that keeps the requests for the
+    /// current sensors under the rate limit, but no shorter than `query_interval`, multiplied by
+    /// the throttle factor.
+    fn current_query_interval(&self) -> Duration {
+        if !self.adaptive_polling {
+            return self.query_interval;
+        }
+
+        let sensors = self.sensors.as_deref().unwrap_or_default();
+        let available = ADAPTIVE_LIMIT - self.fixed_requests_per_hour(sensors);
+        let queries = self.queries_per_poll(sensors);
+
+        let shortest = if available > 0.0 {
+            Duration::from_secs_f64(3600.0 * queries / available).max(Duration::from_secs(1))
+        } else {
+            Duration::from_secs(3600)
+        };
+
+        let interval = self.query_interval.max(shortest);
+
+        Duration::from_secs(interval.as_secs_f64().ceil() as u64) * self.throttle_factor
+    }
+
+    /// Return to the unthrottled query interval after an hour without being rate limited
+    fn recover_from_throttling(&mut self) {
+        if let Some(throttled_at) = self.throttled_at {
+            if throttled_at.elapsed() >= Duration::from_secs(3600) {
+                self.throttle_factor = 1;
+                self.throttled_at = None;
+
+                info!("No longer rate limited by the Flume API");
+            }
+        }
+    }
+
+    /// Whether a budget period ended at any sensor's location since its budgets were fetched
+    fn budget_period_ended(&self) -> bool {
+        self.sensors.iter().flatten().any(|sensor| {
+            let today = sensor.now().naive_local().date();
+
+            self.budget_readings
+                .iter()
+                .filter(|((id, _, _), _)| *id == sensor.sensor.id)
+                .any(|(_, reading)| budget_period_start(&reading.period, today) != reading.start)
+        })
+    }
+
+    async fn budgets(&mut self) -> Result<bool> {
+        // Budgets are fetched again as soon as a period ends so they roll over at local midnight
+        if let Some(last_update) = self.budgets_last_update {
+            if !self.budget_period_ended()
+                && Instant::now().duration_since(last_update) < self.budget_interval
+            {
+                return Ok(false);
+            }
+        }
+
+        let user_id = self.user_id().await?;
+
+        if let Some(sensors) = &self.sensors {
+            for sensor in sensors {
+                let location_id = &sensor.location_id;
+                let location = &sensor.location;
+
+                let id = &sensor.sensor.id;
+
+                if let Some(checked) = self.budgetless.get(id) {
+                    if checked.elapsed() < BUDGET_RECHECK_INTERVAL {
+                        continue;
+                    }
+                }
+
+                let budgets = match self.flume.budgets(user_id, sensor).await {
+                    Ok(budgets) => budgets,
+                    Err(e) if is_not_found(&e) => Vec::new(),
+                    Err(e) => return Err(marked::mark(e, ErrorKind::Budgets)),
+                };
+
+                // Sensors without budgets are checked again much less often
+                if budgets.is_empty() {
+                    if !self.budgetless.contains_key(id) {
+                        debug!(device_id = id, "No budgets, checking again in a day");
+                    }
+
+                    self.budgetless.insert(id.clone(), Instant::now());
+                } else {
+                    self.budgetless.remove(id);
+                }
+                let mut series = Vec::with_capacity(budgets.len() * 4);
+
+                let today = sensor.now().naive_local().date();
+                self.budget_readings.retain(|(sensor_id, name, period), _| {
+                    sensor_id != id
+                        || budgets
+                            .iter()
+                            .any(|b| b.name == *name && b.period.to_string() == *period)
+                });
+
+                budgets.iter().for_each(|budget| {
+                    let gallons = budget.value as f64;
+                    let liters = gallons * LITERS_PER_GALLON;
+                    let period = budget.period.to_string();
+                    let start = budget_period_start(&budget.period, today);
+                    let since_start = (sensor.now() - sensor.start_of_day(start))
+                        .to_std()
+                        .unwrap_or_default();
+                    let key = (id.clone(), budget.name.clone(), period.clone());
+
+                    // Flume can report the usage of the period that just ended for a while, the
+                    // new period starts with none used until Flume's usage drops
+                    let reading = self.budget_readings.get(&key);
+                    let stale = match reading {
+                        Some(r) if r.start != start && r.actual > 0.0 => Some(r.actual),
+                        Some(r) if r.start == start => r.stale,
+                        _ => None,
+                    }
+                    .filter(|stale| budget.actual >= *stale && since_start < self.budget_interval);
+
+                    let (actual, previous) = match stale {
+                        Some(stale) => {
+                            debug!(
+                                device_id = id,
+                                "Budget {} has not reset for the new {} period yet",
+                                budget.name,
+                                period
+                            );
+
+                            // Recording the new period stops the refetch on every cycle
+                            self.budget_readings.insert(
+                                key,
+                                BudgetReading {
+                                    period: budget.period.clone(),
+                                    start,
+                                    actual: 0.0,
+                                    stale: Some(stale),
+                                },
+                            );
+
+                            (0.0, None)
+                        }
+                        None => {
+                            // Usage carried over from the last period was already counted
+                            let previous =
+                                reading.map(|r| if r.start == start { r.actual } else { 0.0 });
+
+                            self.budget_readings.insert(
+                                key,
+                                BudgetReading {
+                                    period: budget.period.clone(),
+                                    start,
+                                    actual: budget.actual,
+                                    stale: None,
+                                },
+                            );
+
+                            (budget.actual, previous)
+                        }
+                    };
+
+                    for threshold in &budget.thresholds {
+                        let threshold_labels = [
+                            location_id.as_str(),
+                            location.as_str(),
+                            &period,
+                            &budget.name,
+                            &threshold.to_string(),
+                        ];
+                        let exceeded =
+                            BUDGET_THRESHOLD_EXCEEDED.with_label_values(&threshold_labels);
+                        series.push(Series::counter(
+                            &BUDGET_THRESHOLD_EXCEEDED,
+                            &threshold_labels,
+                        ));
+
+                        // Thresholds already exceeded at startup aren't counted
+                        let threshold = *threshold as f64;
+                        if previous.is_some_and(|previous| previous < threshold)
+                            && budget.actual >= threshold
+                        {
+                            exceeded.inc();
+                        }
+                    }
+
+                    let labels = [
+                        location_id.as_str(),
+                        location.as_str(),
+                        &period,
+                        &budget.name,
+                    ];
+
+                    BUDGET.with_label_values(&labels).set(liters);
+                    series.push(Series::gauge(&BUDGET, &labels));
+
+                    BUDGET_GALLONS
+                        .with_label_values(&labels)
+                        .set(budget.value as i64);
+                    series.push(Series::int_gauge(&BUDGET_GALLONS, &labels));
+
+                    let remaining = (gallons - actual).max(0.0) * LITERS_PER_GALLON;
+                    BUDGET_REMAINING.with_label_values(&labels).set(remaining);
+                    series.push(Series::gauge(&BUDGET_REMAINING, &labels));
+
+                    if budget.value > 0 {
+                        BUDGET_USED.with_label_values(&labels).set(actual / gallons);
+                        series.push(Series::gauge(&BUDGET_USED, &labels));
+
+                        let _ = self.updates_tx.send(Update::Budget {
+                            device_id: sensor.sensor.id.clone(),
+                            location_id: location_id.clone(),
+                            location: location.clone(),
+                            name: budget.name.clone(),
+                            period: period.clone(),
+                            used_ratio: actual / gallons,
+                        });
+                    }
+                });
+
+                // Budgets deleted in the Flume app are no longer returned
+                self.series.replace_metrics(
+                    &sensor.sensor.id,
+                    &[
+                        Metric::Gauge(&BUDGET),
+                        Metric::IntGauge(&BUDGET_GALLONS),
+                        Metric::Gauge(&BUDGET_USED),
+                        Metric::Gauge(&BUDGET_REMAINING),
+                        Metric::Counter(&BUDGET_THRESHOLD_EXCEEDED),
+                    ],
+                    series,
+                );
+            }
+        }
+
+        self.budgets_last_update = Some(Instant::now());
+        LAST_BUDGETS_UPDATE.set(unix_now());
+
+        Ok(true)
+    }
+
+    /// Usage alert rules are fetched along with devices so changes made in the Flume app show up
+    async fn notification_rules(&mut self) -> Result<bool> {
+        if let Some(last_update) = self.notification_rules_last_update {
+            if Instant::now().duration_since(last_update) < self.device_interval {
+                return Ok(false);
+            }
+        }
+
+        let user_id = self.user_id().await?;
+
+        if let Some(sensors) = &self.sensors {
+            for sensor in sensors {
+                let rules = match self.flume.notification_rules(user_id, sensor).await {
+                    Ok(rules) => rules,
+                    Err(e) if is_not_found(&e) => Vec::new(),
+                    Err(e) => return Err(e),
+                };
+
+                let series = rules
+                    .iter()
+                    .map(|rule| {
+                        let rule_id = rule.id.to_string();
+                        let threshold = rule.threshold.map(|t| t.to_string()).unwrap_or_default();
+                        let enabled = rule.enabled.to_string();
+
+                        let labels = [
+                            sensor.location_id.as_str(),
+                            sensor.location.as_str(),
+                            sensor.sensor.id.as_str(),
+                            &rule_id,
+                            &rule.name,
+                            &rule.rule_type,
+                            &threshold,
+                            &enabled,
+                        ];
+
+                        NOTIFICATION_RULE.with_label_values(&labels).set(1.0);
+                        Series::gauge(&NOTIFICATION_RULE, &labels)
+                    })
+                    .collect();
+
+                // Rules changed or deleted in the Flume app leave their old labels behind
+                self.series.replace_metrics(
+                    &sensor.sensor.id,
+                    &[Metric::Gauge(&NOTIFICATION_RULE)],
+                    series,
+                );
+            }
+        }
+
+        self.notification_rules_last_update = Some(Instant::now());
+
+        Ok(true)
+    }
+
+    /// Whether usage for today, this week, and this month is due.  It is refreshed early when
+    /// the day rolls over at any sensor's location so the usage for today resets at local
+    /// midnight.
+    fn periods_due(&self) -> bool {
+        let new_day = self.sensors.iter().flatten().any(|sensor| {
+            self.period_dates.get(&sensor.sensor.id) != Some(&sensor.now().naive_local().date())
+        });
+
+        match self.periods_last_update {
+            Some(last_update) => {
+                new_day || Instant::now().duration_since(last_update) >= self.period_interval
+            }
+            None => true,
+        }
+    }
+
+    /// Query period usage for sensors whose usage query this cycle didn't include it.
+    /// `period_dates` has the date periods were queried for at each sensor that did.
+    async fn periods(
+        &mut self,
+        due: bool,
+        mut period_dates: HashMap<String, NaiveDate>,
+    ) -> Result<bool> {
+        if !due {
+            return Ok(false);
+        }
+
+        let user_id = self.user_id().await?;
+
+        if let Some(sensors) = self.sensors.clone() {
+            for sensor in &sensors {
+                if period_dates.contains_key(&sensor.sensor.id) {
+                    continue;
+                }
+
+                let now = sensor.now().naive_local().date();
+                let usage = self.flume.query_periods(user_id, sensor).await?;
+                self.set_periods(sensor, usage);
+
+                period_dates.insert(sensor.sensor.id.clone(), now);
+            }
+        }
+
+        self.period_dates = period_dates;
+
+        self.periods_last_update = Some(Instant::now());
+        LAST_PERIODS_UPDATE.set(unix_now());
+
+        Ok(true)
+    }
+
+    fn set_periods(&mut self, sensor: &Sensor, usage: PeriodUsage) {
+        let labels = [
+            sensor.location_id.as_str(),
+            sensor.location.as_str(),
+            sensor.sensor.id.as_str(),
+        ];
+
+        USAGE_TODAY.with_label_values(&labels).set(usage.today);
+        USAGE_THIS_WEEK
+            .with_label_values(&labels)
+            .set(usage.this_week);
+        USAGE_THIS_MONTH
+            .with_label_values(&labels)
+            .set(usage.this_month);
+
+        let _ = self.updates_tx.send(Update::Periods {
+            device_id: sensor.sensor.id.clone(),
+            location_id: sensor.location_id.clone(),
+            location: sensor.location.clone(),
+            today_liters: usage.today,
+            this_week_liters: usage.this_week,
+            this_month_liters: usage.this_month,
+        });
+
+        self.series.replace(
+            &sensor.sensor.id,
+            vec![
+                Series::gauge(&USAGE_TODAY, &labels),
+                Series::gauge(&USAGE_THIS_WEEK, &labels),
+                Series::gauge(&USAGE_THIS_MONTH, &labels),
+            ],
+        );
+    }
+
+    /// Add `new_usage` to the sensor's lifetime usage.  The first time, the usage from Flume's
+    /// earliest data until `window` is added too, so the counter starts at the sensor's total.
+    async fn add_lifetime_usage(
+        &mut self,
+        user_id: i64,
+        sensor: &Sensor,
+        window: sensor::QueryWindow,
+        new_usage: f64,
+    ) {
+        let id = &sensor.sensor.id;
+        let labels = [sensor.location_id.as_str(), sensor.location.as_str(), id];
+
+        if !self.lifetime_started.contains(id) {
+            // No Flume sensor has usage from before Flume sold them
+            let since = sensor.installation().unwrap_or_else(|| {
+                sensor.start_of_day(NaiveDate::from_ymd_opt(FLUME_FIRST_SENSORS, 1, 1).unwrap())
+            });
+
+            match self
+                .flume
+                .query_before(user_id, sensor, since, window)
+                .await
+            {
+                Ok(liters) => {
+                    USAGE_LIFETIME.with_label_values(&labels).inc_by(liters);
+                    self.lifetime_started.insert(id.clone());
+                }
+                Err(e) => {
+                    warn!(
+                        device_id = id,
+                        "Unable to query usage since installation, retrying with the next query {:#}",
+                        e
+                    );
+
+                    return;
+                }
+            }
+        }
+
+        USAGE_LIFETIME.with_label_values(&labels).inc_by(new_usage);
+        self.series
+            .replace(id, vec![Series::counter(&USAGE_LIFETIME, &labels)]);
+    }
+
+    /// Query the usage of each sensor, with its period usage when `periods` are due.  Returns
+    /// the date periods were queried for at each sensor that included them.
+    async fn query(&mut self, periods: bool) -> Result<HashMap<String, NaiveDate>> {
+        let user_id = self.user_id().await?;
+        let mut period_dates = HashMap::new();
+        let query_interval = self.current_query_interval();
+        let mut queried = false;
+
+        if let Some(sensors) = self.sensors.clone() {
+            // Spread sensor queries across the query interval so they don't all hit the API at
+            // the same moment.  When polling on scrape the scrape is waiting for the queries.
+            let now = tokio::time::Instant::now();
+            let phase = query_interval / sensors.len().max(1) as u32;
+
+            for (i, sensor) in sensors.iter().enumerate() {
+                if !self.scrape_driven {
+                    let jitter = rand::thread_rng().gen_range(Duration::ZERO..=self.query_jitter);
+                    let deadline = self
+                        .query_deadlines
+                        .entry(sensor.sensor.id.clone())
+                        .or_insert_with(|| QueryDeadline::new(now + phase * i as u32, jitter));
+
+                    if deadline.due > now {
+                        continue;
+                    }
+
+                    *deadline = deadline.next(now, query_interval, jitter);
+                }
+
+                let lag = chrono::Duration::from_std(self.query_lag)?;
+                let usage_query = self.query_settings.for_device(&sensor.sensor.id).clone();
+                let until_time = sensor.query_window_end(lag, &usage_query.bucket);
+
+                // No whole local bucket has passed since the last window ended
+                let window = match sensor.query_window(until_time, &usage_query.bucket) {
+                    Some(w) => w,
+                    None => continue,
+                };
+
+                // The window grows until the sensor's own query interval has passed
+                if let Some(interval) = usage_query.interval {
+                    if (until_time - sensor.last_update)
+                        .to_std()
+                        .unwrap_or_default()
+                        < interval
+                    {
+                        continue;
+                    }
+                }
+
+                let today = sensor.now().naive_local().date();
+                let (new_usage, period_usage) = self
+                    .flume
+                    .query_sensor(user_id, sensor, window, &usage_query, periods)
+                    .await?;
+                queried = true;
+
+                if let Some(period_usage) = period_usage {
+                    self.set_periods(sensor, period_usage);
+                    period_dates.insert(sensor.sensor.id.clone(), today);
+                }
+
+                let id = &sensor.sensor.id;
+
+                debug!("Sensor {} used {} liters", id, new_usage);
+                let labels = [sensor.location_id.as_str(), sensor.location.as_str(), id];
+                let usage_labels = usage_labels(
+                    &self.device_labels,
+                    &sensor.location_id,
+                    &sensor.location,
+                    id,
+                );
+                USAGE.with_label_values(&usage_labels).inc_by(new_usage);
+
+                // Recorded right away so a failure querying a later sensor doesn't count this
+                // window again
+                if let Some(sensors) = &mut self.sensors {
+                    sensors[i] = sensor.with_updated_timestamp(until_time);
+                }
+
+                SENSOR_LAST_QUERY.with_label_values(&labels).set(unix_now());
+                self.series.replace(
+                    id,
+                    vec![
+                        Series::counter(&USAGE, &usage_labels),
+                        Series::gauge(&SENSOR_LAST_QUERY, &labels),
+                    ],
+                );
+
+                // The usage is already counted so a failed flow query leaves the last flow
+                if self.flow_metrics {
+                    match self.flume.query_flow(user_id, sensor, window).await {
+                        Ok((max, avg)) => {
+                            FLOW_MAX.with_label_values(&labels).set(max);
+                            FLOW_AVG.with_label_values(&labels).set(avg);
+                            self.series.replace(
+                                id,
+                                vec![
+                                    Series::gauge(&FLOW_MAX, &labels),
+                                    Series::gauge(&FLOW_AVG, &labels),
+                                ],
+                            );
+                        }
+                        Err(e) => {
+                            warn!(
+                                device_id = id,
+                                "Unable to query flow, retrying with the next query {:#}", e
+                            );
+                        }
+                    }
+                }
+
+                // The first window after startup or a restore starts when the sensor was last
+                // seen or the state was saved, so its average isn't a current flow rate
+                let minutes = (until_time - sensor.last_update).num_seconds() as f64 / 60.0;
+                let first_window = self.flow_rate_started.insert(id.clone());
+                let flow_rate = (!first_window).then(|| {
+                    if minutes > 0.0 {
+                        new_usage / minutes
+                    } else {
+                        0.0
+                    }
+                });
+
+                // Any window without usage ends the run of continuous flow.  Usage in a long
+                // window, such as the first after a restart, may not have been continuous.
+                let interval_minutes = usage_query
+                    .interval
+                    .map_or(query_interval, |i| i.max(query_interval))
+                    .as_secs_f64()
+                    / 60.0;
+                let longest_window =
+                    2.0 * interval_minutes.max(bucket_minutes(&usage_query.bucket));
+                let continuous_flow = self.continuous_flow.entry(id.clone()).or_default();
+                if new_usage > 0.0 && minutes <= longest_window {
+                    *continuous_flow += minutes;
+                } else {
+                    *continuous_flow = 0.0;
+                }
+                let continuous_flow_minutes = *continuous_flow;
+                CONTINUOUS_FLOW
+                    .with_label_values(&labels)
+                    .set(continuous_flow_minutes);
+                self.series
+                    .replace(id, vec![Series::gauge(&CONTINUOUS_FLOW, &labels)]);
+
+                let _ = self.updates_tx.send(Update::Usage {
+                    device_id: id.clone(),
+                    location_id: sensor.location_id.clone(),
+                    location: sensor.location.clone(),
+                    since: sensor.last_update.fixed_offset(),
+                    until: until_time.fixed_offset(),
+                    liters: new_usage,
+                    total_liters: USAGE.with_label_values(&usage_labels).get(),
+                    flow_rate,
+                    continuous_flow_minutes,
+                });
+
+                self.add_lifetime_usage(user_id, sensor, window, new_usage)
+                    .await;
+            }
+        }
+
+        // Sensors waiting for a whole bucket or their own interval weren't queried
+        if queried {
+            LAST_QUERY_UPDATE.set(unix_now());
+        }
+
+        Ok(period_dates)
+    }
+}
+
+/// When a sensor's usage is next queried.  Each sensor keeps its slot in the query interval,
+/// `due` adds the jitter.
+struct QueryDeadline {
+    slot: tokio::time::Instant,
+    due: tokio::time::Instant,
+}
+
+impl QueryDeadline {
+    fn new(slot: tokio::time::Instant, jitter: Duration) -> Self {
+        QueryDeadline {
+            slot,
+            due: slot + jitter,
+        }
+    }
+
+    /// Deadline for the query after this one, skipping slots that passed during a slow cycle
+    fn next(&self, now: tokio::time::Instant, interval: Duration, jitter: Duration) -> Self {
+        let mut slot = self.slot + interval;
+
+        while slot <= now {
+            slot += interval;
+        }
+
+        QueryDeadline::new(slot, jitter)
+    }
+}
+
+/// A budget's usage in gallons as Flume last reported it and the start of the period it is for
+struct BudgetReading {
+    period: BudgetPeriod,
+    start: NaiveDate,
+    actual: f64,
+    /// Usage of the period that ended while Flume still reports it for the new period
+    stale: Option<f64>,
+}
+
+/// First day of the budget `period` containing `date`, weeks start on Sunday like the Flume app
+fn budget_period_start(period: &BudgetPeriod, date: NaiveDate) -> NaiveDate {
+    match period {
+        BudgetPeriod::DAILY => date,
+        BudgetPeriod::WEEKLY => {
+            date - chrono::Duration::days(date.weekday().num_days_from_sunday() as i64)
+        }
+        BudgetPeriod::MONTHLY => date.with_day(1).unwrap_or(date),
+    }
+}
+
+/// Nominal length of a query bucket in minutes
+fn bucket_minutes(bucket: &QueryBucket) -> f64 {
+    match bucket {
+        QueryBucket::MIN => 1.0,
+        QueryBucket::HR => 60.0,
+        QueryBucket::DAY => 1440.0,
+        QueryBucket::MON => 31.0 * 1440.0,
+        QueryBucket::YR => 366.0 * 1440.0,
+    }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// Labels of the usage counter for a sensor, including its configured device labels
+fn usage_labels<'a>(
+    device_labels: &'a DeviceLabels,
+    location_id: &'a str,
+    location: &'a str,
+    device_id: &'a str,
+) -> Vec<&'a str> {
+    with_values(
+        &[location_id, location, device_id],
+        &device_labels.values(device_id),
+    )
+}
+
+/// Label values `base` followed by the device label values `device_labels`
+fn with_values<'a>(base: &[&'a str], device_labels: &[&'a str]) -> Vec<&'a str> {
+    let mut values = base.to_vec();
+    values.extend_from_slice(device_labels);
+
+    values
+}
+
+fn update_bridge(bridge: &Bridge, device_labels: &[&str]) -> Vec<Series> {
+    let location_id = &bridge.location_id;
+    let location = &bridge.location;
+    let device_id = &bridge.id;
+    let product = &bridge.product;
+    let connected = if bridge.connected { 1.0 } else { 0.0 };
+    let last_seen = bridge.last_seen.timestamp() as f64;
+
+    let labels = with_values(&[location_id, location, device_id], device_labels);
+    let product_labels = with_values(&[location_id, location, device_id, product], device_labels);
+
+    BRIDGE_PRODUCT.with_label_values(&product_labels).set(1.0);
+    BRIDGE_CONNECTED.with_label_values(&labels).set(connected);
+    BRIDGE_LAST_SEEN.with_label_values(&labels).set(last_seen);
+
+    let mut series = vec![
+        Series::gauge(&BRIDGE_PRODUCT, &product_labels),
+        Series::gauge(&BRIDGE_CONNECTED, &labels),
+        Series::gauge(&BRIDGE_LAST_SEEN, &labels),
+    ];
+
+    series.extend(update_versions(
+        device_id,
+        &bridge.firmware_version,
+        &bridge.hardware_revision,
+    ));
+
+    series
+}
+
+fn update_sensor(sensor: &Sensor, device_labels: &[&str]) -> Vec<Series> {
+    let location_id = &sensor.location_id;
+    let location = &sensor.location;
+    let last_seen = sensor.last_seen.timestamp() as f64;
+    let sensor = &sensor.sensor;
+    let device_id = &sensor.id;
+    let bridge_id = &sensor.bridge_id;
+    let product = &sensor.product;
+
+    let connected = if sensor.connected { 1.0 } else { 0.0 };
+    let oriented = if sensor.oriented { 1.0 } else { 0.0 };
+    let battery_level = if BATTERY_HIGH == sensor.battery_level {
+        1.0
+    } else if BATTERY_MEDIUM == sensor.battery_level {
+        0.5
+    } else if BATTERY_LOW == sensor.battery_level {
+        0.25
+    } else {
+        0.0
+    };
+
+    let labels = with_values(
+        &[location_id, location, device_id, bridge_id],
+        device_labels,
+    );
+    let product_labels = with_values(
+        &[location_id, location, device_id, bridge_id, product],
+        device_labels,
+    );
+    let battery_labels = with_values(
+        &[
+            location_id,
+            location,
+            device_id,
+            bridge_id,
+            &sensor.battery_level,
+        ],
+        device_labels,
+    );
+
+    SENSOR_PRODUCT.with_label_values(&product_labels).set(1.0);
+    SENSOR_BATTERY.with_label_values(&labels).set(battery_level);
+    SENSOR_BATTERY_INFO
+        .with_label_values(&battery_labels)
+        .set(1.0);
+    SENSOR_CONNECTED.with_label_values(&labels).set(connected);
+    SENSOR_ORIENTED.with_label_values(&labels).set(oriented);
+    SENSOR_LAST_SEEN.with_label_values(&labels).set(last_seen);
+
+    // Replacing the series for the sensor removes the previous battery level and versions
+    let mut series = vec![
+        Series::gauge(&SENSOR_PRODUCT, &product_labels),
+        Series::gauge(&SENSOR_BATTERY, &labels),
+        Series::gauge(&SENSOR_BATTERY_INFO, &battery_labels),
+        Series::gauge(&SENSOR_CONNECTED, &labels),
+        Series::gauge(&SENSOR_ORIENTED, &labels),
+        Series::gauge(&SENSOR_LAST_SEEN, &labels),
+    ];
+
+    series.extend(update_versions(
+        device_id,
+        &sensor.firmware_version,
+        &sensor.hardware_revision,
+    ));
+
+    series
+}
+
+/// Export the firmware version and hardware revision of a device when Flume returns them
+fn update_versions(
+    device_id: &str,
+    firmware_version: &Option<String>,
+    hardware_revision: &Option<String>,
+) -> Vec<Series> {
+    let mut series = vec![];
+
+    if let Some(version) = firmware_version {
+        let labels = [device_id, version];
+
+        DEVICE_FIRMWARE.with_label_values(&labels).set(1.0);
+        series.push(Series::gauge(&DEVICE_FIRMWARE, &labels));
+    }
+
+    if let Some(revision) = hardware_revision {
+        let labels = [device_id, revision];
+
+        DEVICE_HARDWARE.with_label_values(&labels).set(1.0);
+        series.push(Series::gauge(&DEVICE_HARDWARE, &labels));
+    }
+
+    series
+}
+
+/// Next tick of `interval`, or `next_query` when a sensor's usage query is due first
+async fn next_tick(interval: &mut Interval, next_query: Option<tokio::time::Instant>) {
+    match next_query {
+        Some(next_query) => tokio::select! {
+            _ = interval.tick() => (),
+            _ = sleep_until(next_query) => (),
+        },
+        None => {
+            interval.tick().await;
+        }
+    }
+}
+
+/// Next `Flume` to restart with, never ready without a restart channel
+async fn restarted<A>(restart_rx: &mut Option<mpsc::Receiver<Flume<A>>>) -> Option<Flume<A>> {
+    match restart_rx {
+        Some(restart_rx) => restart_rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Turn away mode on or off for `location_id`, looking up the user id when it isn't known yet
+async fn set_away_mode<A: FlumeApi>(
+    mut flume: Flume<A>,
+    user_id: Option<i64>,
+    location_id: &str,
+    away_mode: bool,
+) -> Result<()> {
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None => flume.user_id().await?,
+    };
+
+    flume.set_away_mode(user_id, location_id, away_mode).await?;
+
+    info!(
+        location_id,
+        "Turned away mode {}",
+        if away_mode { "on" } else { "off" }
+    );
+
+    Ok(())
+}
+
+async fn away_mode_requested(
+    away_mode_rx: &mut Option<mpsc::Receiver<AwayModeRequest>>,
+) -> Option<AwayModeRequest> {
+    match away_mode_rx {
+        Some(away_mode_rx) => away_mode_rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The Flume API responded 404, as for budgets of a sensor without any
+fn is_not_found(error: &Error) -> bool {
+    error
+        .chain()
+        .any(|cause| matches!(cause.downcast_ref::<RequestFailed>(), Some(e) if e.http_code == 404))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::flume_api::mock;
+    use crate::flume_api::mock::MockApi;
+
+    use prometheus::core::Collector;
+
+    fn downloader(api: &MockApi) -> (Downloader<MockApi>, mpsc::Receiver<Error>) {
+        let (error_tx, error_rx) = mpsc::channel(8);
+        let hour = Duration::from_secs(60 * 60);
+
+        let downloader = Downloader::new(
+            api.flume(),
+            hour,
+            hour,
+            hour,
+            Duration::from_secs(60),
+            Duration::ZERO,
+            error_tx,
+        );
+
+        (downloader, error_rx)
+    }
+
+    /// Whether `metric` has a series for `device_id`
+    fn exported(metric: &dyn Collector, device_id: &str) -> bool {
+        metric.collect().iter().any(|family| {
+            family.get_metric().iter().any(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "device_id" && label.get_value() == device_id)
+            })
+        })
+    }
+
+    fn usage_queries(api: &MockApi) -> Vec<crate::client::Query> {
+        api.queries()
+            .into_iter()
+            .filter(|query| !matches!(query.bucket, QueryBucket::YR))
+            .collect()
+    }
+
+    #[test]
+    fn budget_period_start_weeks_start_sunday() {
+        let date = |date| NaiveDate::parse_from_str(date, "%F").unwrap();
+
+        // A Wednesday
+        let wednesday = date("2024-05-15");
+
+        assert_eq!(
+            wednesday,
+            budget_period_start(&BudgetPeriod::DAILY, wednesday)
+        );
+        assert_eq!(
+            date("2024-05-12"),
+            budget_period_start(&BudgetPeriod::WEEKLY, wednesday)
+        );
+        assert_eq!(
+            date("2024-05-12"),
+            budget_period_start(&BudgetPeriod::WEEKLY, date("2024-05-12"))
+        );
+        assert_eq!(
+            date("2024-05-01"),
+            budget_period_start(&BudgetPeriod::MONTHLY, wednesday)
+        );
+    }
+
+    #[tokio::test]
+    async fn query_advances_window() {
+        let id = "3000000000000000001";
+        let api = MockApi::default();
+        api.set_devices(vec![mock::sensor(
+            id,
+            Utc::now() - chrono::Duration::minutes(10),
+        )]);
+        let (mut downloader, _error_rx) = downloader(&api);
+
+        downloader.devices().await.unwrap();
+        let first = downloader.sensors.as_ref().unwrap()[0].last_update;
+
+        downloader.query(false).await.unwrap();
+        let last_update = downloader.sensors.as_ref().unwrap()[0].last_update;
+
+        let queries = usage_queries(&api);
+        assert_eq!(1, queries.len());
+        assert_eq!(
+            first.format("%F %H:%M:00").to_string(),
+            queries[0].since_datetime
+        );
+        // The next window starts at the minute after the last one queried
+        assert_eq!(
+            Some(
+                (last_update - chrono::Duration::minutes(1))
+                    .format("%F %H:%M:00")
+                    .to_string()
+            ),
+            queries[0].until_datetime
+        );
+
+        // Fetching the devices again continues the window instead of restarting at last seen
+        downloader.devices_last_update = None;
+        downloader.devices().await.unwrap();
+
+        assert_eq!(
+            last_update,
+            downloader.sensors.as_ref().unwrap()[0].last_update
+        );
+    }
+
+    #[tokio::test]
+    async fn query_counts_usage() {
+        let id = "3000000000000000002";
+        let api = MockApi::default();
+        api.set_devices(vec![mock::sensor(
+            id,
+            Utc::now() - chrono::Duration::minutes(10),
+        )]);
+        api.set_liters(2.5);
+        let (mut downloader, _error_rx) = downloader(&api);
+        let mut updates = downloader.updates();
+
+        downloader.devices().await.unwrap();
+        downloader.query(false).await.unwrap();
+
+        let device_labels = DeviceLabels::default();
+        let labels = usage_labels(&device_labels, "5678", "Home", id);
+        assert_eq!(2.5, USAGE.with_label_values(&labels).get());
+
+        let usage = std::iter::from_fn(|| updates.try_recv().ok())
+            .find(|update| matches!(update, Update::Usage { .. }));
+
+        match usage {
+            Some(Update::Usage {
+                device_id,
+                liters,
+                total_liters,
+                flow_rate,
+                ..
+            }) => {
+                assert_eq!(id, device_id);
+                assert_eq!(2.5, liters);
+                assert_eq!(2.5, total_liters);
+                // The first window after startup has no current flow rate
+                assert_eq!(None, flow_rate);
+            }
+            other => panic!("expected a usage update, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn query_failure_keeps_counted_windows() {
+        let counted = "3000000000000000009";
+        let api = MockApi::default();
+        api.set_devices(vec![
+            mock::sensor(counted, Utc::now() - chrono::Duration::minutes(10)),
+            mock::sensor(
+                "3000000000000000010",
+                Utc::now() - chrono::Duration::minutes(10),
+            ),
+        ]);
+        api.set_liters(2.5);
+        api.fail_queries(Some(|device_id, _| device_id == "3000000000000000010"));
+        let (mut downloader, _error_rx) = downloader(&api);
+        // Query every sensor on each call instead of on its deadline
+        downloader.scrape_driven = true;
+
+        downloader.devices().await.unwrap();
+        let first = downloader.sensors.as_ref().unwrap()[1].last_update;
+
+        assert!(downloader.query(false).await.is_err());
+
+        let device_labels = DeviceLabels::default();
+        let labels = usage_labels(&device_labels, "5678", "Home", counted);
+        assert_eq!(2.5, USAGE.with_label_values(&labels).get());
+        assert_eq!(first, downloader.sensors.as_ref().unwrap()[1].last_update);
+
+        let counted_until = downloader.sensors.as_ref().unwrap()[0].last_update;
+
+        api.fail_queries(None);
+        downloader.query(false).await.unwrap();
+
+        // Only a window after the counted one adds usage
+        let windows = if downloader.sensors.as_ref().unwrap()[0].last_update == counted_until {
+            1.0
+        } else {
+            2.0
+        };
+        assert_eq!(2.5 * windows, USAGE.with_label_values(&labels).get());
+    }
+
+    #[tokio::test]
+    async fn query_flow_failure_counts_usage_once() {
+        let id = "3000000000000000008";
+        let api = MockApi::default();
+        api.set_devices(vec![mock::sensor(
+            id,
+            Utc::now() - chrono::Duration::minutes(10),
+        )]);
+        api.set_liters(2.5);
+        api.fail_queries(Some(|_, query| {
+            matches!(query.operation, Some(crate::client::QueryOperation::MAX))
+        }));
+        let (downloader, _error_rx) = downloader(&api);
+        let mut downloader = downloader.flow_metrics(true);
+
+        downloader.devices().await.unwrap();
+        let first = downloader.sensors.as_ref().unwrap()[0].last_update;
+
+        downloader.query(false).await.unwrap();
+
+        let device_labels = DeviceLabels::default();
+        let labels = usage_labels(&device_labels, "5678", "Home", id);
+        assert_eq!(2.5, USAGE.with_label_values(&labels).get());
+        assert!(!exported(&*FLOW_MAX, id));
+
+        // The counted window isn't queried again
+        assert!(first < downloader.sensors.as_ref().unwrap()[0].last_update);
+    }
+
+    #[tokio::test]
+    async fn devices_removes_stale_series() {
+        let kept = "3000000000000000003";
+        let removed = "3000000000000000004";
+        let last_seen = Utc::now() - chrono::Duration::minutes(10);
+        let api = MockApi::default();
+        api.set_devices(vec![
+            mock::sensor(kept, last_seen),
+            mock::sensor(removed, last_seen),
+        ]);
+        let (mut downloader, _error_rx) = downloader(&api);
+
+        downloader.devices().await.unwrap();
+
+        assert!(exported(&*SENSOR_CONNECTED, kept));
+        assert!(exported(&*SENSOR_CONNECTED, removed));
+
+        api.set_devices(vec![mock::sensor(kept, last_seen)]);
+        downloader.devices_last_update = None;
+        downloader.devices().await.unwrap();
+
+        assert!(exported(&*SENSOR_CONNECTED, kept));
+        assert!(!exported(&*SENSOR_CONNECTED, removed));
+        assert_eq!(1, downloader.sensors.as_ref().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn budgets_count_threshold_crossings() {
+        let fixture: serde_json::Value =
+            serde_json::from_str(include_str!("../tests/fixtures/budgets.json")).unwrap();
+        let mut budgets: Vec<crate::client::Budget> =
+            serde_json::from_value(fixture["data"].clone()).unwrap();
+
+        let api = MockApi::default();
+        api.set_devices(vec![mock::sensor(
+            "3000000000000000005",
+            Utc::now() - chrono::Duration::minutes(10),
+        )]);
+        api.set_budgets(budgets.clone());
+        let (mut downloader, _error_rx) = downloader(&api);
+
+        let exceeded = |threshold: &str| {
+            BUDGET_THRESHOLD_EXCEEDED
+                .with_label_values(&["5678", "Home", "monthly", "Monthly budget", threshold])
+                .get()
+        };
+
+        downloader.devices().await.unwrap();
+        downloader.budgets().await.unwrap();
+
+        // 1500 gallons were used before the first fetch
+        assert_eq!(0.0, exceeded("1500"));
+        assert_eq!(0.0, exceeded("2250"));
+
+        budgets[0].actual = 2300.0;
+        api.set_budgets(budgets);
+        downloader.budgets_last_update = None;
+        downloader.budgets().await.unwrap();
+
+        assert_eq!(0.0, exceeded("1500"));
+        assert_eq!(1.0, exceeded("2250"));
+        assert_eq!(0.0, exceeded("2700"));
+    }
+
+    /// A downloader polling adaptively that found `count` sensors
+    async fn adaptive_downloader(api: &MockApi, count: usize) -> Downloader<MockApi> {
+        api.set_devices(
+            (0..count)
+                .map(|i| {
+                    mock::sensor(
+                        &format!("40000000000000000{:02}", i),
+                        Utc::now() - chrono::Duration::minutes(10),
+                    )
+                })
+                .collect(),
+        );
+        let (downloader, _error_rx) = downloader(api);
+        let mut downloader = downloader.adaptive_polling(true);
+
+        downloader.devices().await.unwrap();
+
+        downloader
+    }
+
+    #[tokio::test]
+    async fn adaptive_query_interval_floor() {
+        let downloader = adaptive_downloader(&MockApi::default(), 1).await;
+
+        // 104 requests an hour are left for one sensor, the query interval is slower
+        assert_eq!(Duration::from_secs(60), downloader.current_query_interval());
+    }
+
+    #[tokio::test]
+    async fn adaptive_query_interval_lengthens_with_sensors() {
+        // Each sensor's periods, budgets, and notification rules take 3 of the 108 requests an
+        // hour, the devices request takes 1
+        let downloader = adaptive_downloader(&MockApi::default(), 4).await;
+
+        // 4 queries in the 95 requests left, 151.6s rounded up
+        assert_eq!(
+            Duration::from_secs(152),
+            downloader.current_query_interval()
+        );
+
+        let downloader = adaptive_downloader(&MockApi::default(), 10).await;
+
+        // 10 queries in the 77 requests left, 467.5s rounded up
+        assert_eq!(
+            Duration::from_secs(468),
+            downloader.current_query_interval()
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_doubles_query_interval() {
+        let mut downloader = adaptive_downloader(&MockApi::default(), 1).await;
+
+        downloader.handle_error(Error::new(RateLimited)).await;
+        assert_eq!(
+            Duration::from_secs(120),
+            downloader.current_query_interval()
+        );
+
+        downloader.handle_error(Error::new(RateLimited)).await;
+        assert_eq!(
+            Duration::from_secs(240),
+            downloader.current_query_interval()
+        );
+
+        for _ in 0..8 {
+            downloader.handle_error(Error::new(RateLimited)).await;
+        }
+        assert_eq!(
+            Duration::from_secs(60) * MAX_THROTTLE_FACTOR,
+            downloader.current_query_interval()
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_forwards_errors() {
+        let api = MockApi::default();
+        api.fail_with(Some("devices unavailable"));
+        let (mut downloader, mut error_rx) = downloader(&api);
+
+        downloader.poll().await;
+
+        let error = error_rx.try_recv().unwrap();
+        assert_eq!("devices unavailable", error.to_string());
+        assert!(downloader.sensors.is_none());
+    }
+
+    fn monthly_budget(name: &str, actual: f64) -> crate::client::Budget {
+        crate::client::Budget {
+            id: 1,
+            name: name.to_string(),
+            period: BudgetPeriod::MONTHLY,
+            value: 3000,
+            thresholds: vec![1500],
+            actual,
+        }
+    }
+
+    /// Pretend the budgets were last fetched in the period before this one when `actual` gallons
+    /// were used
+    fn fetched_last_period(downloader: &mut Downloader<MockApi>, actual: f64) {
+        downloader.budget_readings.values_mut().for_each(|reading| {
+            reading.start -= chrono::Duration::days(1);
+            reading.actual = actual;
+        });
+        downloader.budgets_last_update = None;
+    }
+
+    #[tokio::test]
+    async fn budgets_roll_over() {
+        let api = MockApi::default();
+        api.set_devices(vec![mock::sensor(
+            "3000000000000000006",
+            Utc::now() - chrono::Duration::minutes(10),
+        )]);
+        api.set_budgets(vec![monthly_budget("Rollover budget", 2800.0)]);
+        let (mut downloader, _error_rx) = downloader(&api);
+
+        let labels = ["5678", "Home", "monthly", "Rollover budget"];
+        let exceeded = || {
+            BUDGET_THRESHOLD_EXCEEDED
+                .with_label_values(&["5678", "Home", "monthly", "Rollover budget", "1500"])
+                .get()
+        };
+
+        downloader.devices().await.unwrap();
+        downloader.budgets().await.unwrap();
+
+        assert_eq!(0.0, exceeded());
+
+        // Usage dropping in the new period is not a negative change
+        fetched_last_period(&mut downloader, 2800.0);
+        api.set_budgets(vec![monthly_budget("Rollover budget", 100.0)]);
+        downloader.budgets().await.unwrap();
+
+        assert_eq!(0.0, exceeded());
+        assert_eq!(100.0 / 3000.0, BUDGET_USED.with_label_values(&labels).get());
+        assert_eq!(
+            2900.0 * LITERS_PER_GALLON,
+            BUDGET_REMAINING.with_label_values(&labels).get()
+        );
+
+        // Thresholds count again in the new period
+        api.set_budgets(vec![monthly_budget("Rollover budget", 1600.0)]);
+        downloader.budgets_last_update = None;
+        downloader.budgets().await.unwrap();
+
+        assert_eq!(1.0, exceeded());
+    }
+
+    #[tokio::test]
+    async fn budgets_ignore_stale_period() {
+        let api = MockApi::default();
+        api.set_devices(vec![mock::sensor(
+            "3000000000000000007",
+            Utc::now() - chrono::Duration::minutes(10),
+        )]);
+        api.set_budgets(vec![monthly_budget("Stale budget", 2800.0)]);
+        let (mut downloader, _error_rx) = downloader(&api);
+        // Any time this month is soon enough after the period started for a stale reading
+        downloader.budget_interval = Duration::from_secs(40 * 24 * 60 * 60);
+
+        let labels = ["5678", "Home", "monthly", "Stale budget"];
+        let used = || BUDGET_USED.with_label_values(&labels).get();
+
+        downloader.devices().await.unwrap();
+        downloader.budgets().await.unwrap();
+
+        assert_eq!(2800.0 / 3000.0, used());
+
+        // Flume still reports last period's usage
+        fetched_last_period(&mut downloader, 2800.0);
+        downloader.budgets().await.unwrap();
+
+        assert_eq!(0.0, used());
+
+        downloader.budgets_last_update = None;
+        downloader.budgets().await.unwrap();
+
+        assert_eq!(0.0, used());
+
+        api.set_budgets(vec![monthly_budget("Stale budget", 50.0)]);
+        downloader.budgets_last_update = None;
+        downloader.budgets().await.unwrap();
+
+        assert_eq!(50.0 / 3000.0, used());
+    }
 }
@@ -0,0 +1,201 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::arguments::QueryArguments;
+use crate::client;
+use crate::client::QueryBucket;
+use crate::configuration::Configuration;
+use crate::device::Device;
+use crate::flume_builder::FlumeBuilder;
+use crate::query;
+
+use chrono::NaiveDateTime;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use tracing::info;
+
+/// Buckets fetched by each query so MIN exports over long ranges are split into many requests
+const PAGE_BUCKETS: i32 = 1200;
+
+const DATETIME_FORMAT: &str = "%F %H:%M:%S";
+
+/// One bucket of usage for a sensor
+pub struct Row {
+    pub datetime: String,
+    pub liters: f64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    Parquet,
+}
+
+/// Write usage for each sensor between `--since` and `--until` to one file per sensor in the
+/// output directory
+pub async fn run(configuration: &Configuration, arguments: &QueryArguments) -> Result<()> {
+    let since = arguments
+        .since
+        .as_ref()
+        .ok_or_else(|| anyhow!("export requires --since"))?;
+    let since = parse_datetime(since)?;
+    let until = arguments.until.as_deref().map(parse_datetime).transpose()?;
+
+    let (bucket, step) = match query::bucket(arguments.bucket.as_deref().unwrap_or("HR"))? {
+        QueryBucket::MIN => (QueryBucket::MIN, chrono::Duration::minutes(1)),
+        QueryBucket::HR => (QueryBucket::HR, chrono::Duration::hours(1)),
+        _ => return Err(anyhow!("export supports the MIN and HR buckets")),
+    };
+
+    let format = match arguments.format.as_deref().unwrap_or("csv") {
+        "csv" => Format::Csv,
+        "parquet" if cfg!(feature = "parquet") => Format::Parquet,
+        "parquet" => return Err(anyhow!("Built without the parquet feature")),
+        f => return Err(anyhow!("Unknown format {}, use csv or parquet", f)),
+    };
+
+    let output = PathBuf::from(arguments.output.as_deref().unwrap_or("."));
+    let device_filter = configuration.device_filter();
+
+    let mut flume = FlumeBuilder::from_configuration(configuration.clone())
+        .build()
+        .await?;
+
+    let user_id = flume.user_id().await?;
+
+    let sensors = flume
+        .devices(user_id)
+        .await?
+        .into_iter()
+        .filter_map(|d| match d {
+            Device::Sensor(s) if device_filter.includes(&s.sensor.id) => Some(s),
+            _ => None,
+        });
+
+    for sensor in sensors {
+        let device_id = &sensor.sensor.id;
+        let until = until.unwrap_or_else(|| sensor.now().naive_local());
+        let mut rows = vec![];
+        let mut page_start = since;
+
+        while page_start <= until {
+            let page_end = (page_start + step * (PAGE_BUCKETS - 1)).min(until);
+
+            let query = client::Query {
+                request_id: page_start.format(DATETIME_FORMAT).to_string(),
+                bucket: bucket.clone(),
+                since_datetime: page_start.format(DATETIME_FORMAT).to_string(),
+                until_datetime: Some(page_end.format(DATETIME_FORMAT).to_string()),
+                units: Some(client::QueryUnits::LITERS),
+                ..Default::default()
+            };
+
+            let results = flume.query_results(user_id, device_id, query).await?;
+
+            rows.extend(results.into_iter().map(|result| Row {
+                datetime: result.datetime.unwrap_or_default(),
+                liters: result.value,
+            }));
+
+            page_start = page_end + step;
+        }
+
+        let extension = match format {
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        };
+        let path = output.join(format!("{}.{}", device_id, extension));
+
+        match format {
+            Format::Csv => write_csv(&path, device_id, &rows),
+            Format::Parquet => write_parquet(&path, device_id, &rows),
+        }
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+
+        info!("Wrote {} rows to {}", rows.len(), path.display());
+    }
+
+    Ok(())
+}
+
+fn parse_datetime(datetime: &str) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(datetime, DATETIME_FORMAT)
+        .with_context(|| format!("Unable to parse {}, use YYYY-MM-DD HH:MM:SS", datetime))
+}
+
+fn write_csv(path: &Path, device_id: &str, rows: &[Row]) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(file, "device_id,datetime,liters")?;
+
+    for row in rows {
+        writeln!(file, "{},{},{}", device_id, row.datetime, row.liters)?;
+    }
+
+    file.flush()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(path: &Path, device_id: &str, rows: &[Row]) -> Result<()> {
+    use parquet::data_type::ByteArray;
+    use parquet::data_type::ByteArrayType;
+    use parquet::data_type::DoubleType;
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    use std::sync::Arc;
+
+    let schema = parse_message_type(
+        "message usage {
+            required binary device_id (UTF8);
+            required binary datetime (UTF8);
+            required double liters;
+        }",
+    )?;
+
+    let file = File::create(path)?;
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(file, Arc::new(schema), properties)?;
+    let mut row_group = writer.next_row_group()?;
+
+    let device_ids: Vec<ByteArray> = rows.iter().map(|_| device_id.into()).collect();
+    let datetimes: Vec<ByteArray> = rows.iter().map(|r| r.datetime.as_str().into()).collect();
+    let liters: Vec<f64> = rows.iter().map(|r| r.liters).collect();
+
+    for values in [&device_ids, &datetimes] {
+        let mut column = row_group
+            .next_column()?
+            .ok_or_else(|| anyhow!("Missing parquet column, bug?"))?;
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(values, None, None)?;
+        column.close()?;
+    }
+
+    let mut column = row_group
+        .next_column()?
+        .ok_or_else(|| anyhow!("Missing parquet column, bug?"))?;
+    column
+        .typed::<DoubleType>()
+        .write_batch(&liters, None, None)?;
+    column.close()?;
+
+    row_group.close()?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_path: &Path, _device_id: &str, _rows: &[Row]) -> Result<()> {
+    Err(anyhow!("Built without the parquet feature"))
+}
@@ -1,64 +1,556 @@
 use anyhow::Context;
 use anyhow::Result;
 
-use log::info;
+use crate::api;
+use crate::client;
+use crate::dashboard;
+use crate::debug_state::DebugState;
+use crate::downloader::AwayModeRequest;
+use crate::downloader::AwayModeSender;
+use crate::downloader::ScrapeSender;
+use crate::downloader::UnknownLocation;
+use crate::marked;
+use crate::openmetrics;
+use crate::readings::Readings;
+use crate::state::State;
+use crate::supervisor::Unrecoverable;
+use crate::update::Update;
 
-use prometheus_hyper::Server;
+use hyper::header;
+use hyper::service::make_service_fn;
+use hyper::service::service_fn;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
 
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+use socket2::Domain;
+use socket2::Protocol;
+use socket2::Socket;
+use socket2::Type;
+
+use prometheus::Encoder;
+use prometheus::Registry;
+use prometheus::TextEncoder;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::sync::watch;
 use tokio::sync::Notify;
+use tokio::time::timeout;
 
 type ErrorSender = mpsc::Sender<anyhow::Error>;
 
+/// Interval between comments that keep idle `/events` connections open through proxies
+const EVENTS_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// How long a scrape waits for an update when polling on scrape, below the default Prometheus
+/// scrape timeout
+const SCRAPE_UPDATE_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// How long an away mode change is waited for before it is reported as accepted
+const AWAY_MODE_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Exporter {
-    bind_address: SocketAddr,
+    bind_addresses: Vec<SocketAddr>,
     shutdown: Arc<Notify>,
+    routes: Routes,
+}
+
+struct Routes {
+    registry: Registry,
+    control_token: Option<String>,
+    state_rx: watch::Receiver<State>,
+    scrape_tx: Option<ScrapeSender>,
+    readings: Option<Readings>,
+    dashboard: bool,
+    updates_rx: Option<broadcast::Receiver<Update>>,
+    debug_rx: Option<watch::Receiver<DebugState>>,
+    away_mode_tx: Option<AwayModeSender>,
 }
 
 impl Exporter {
-    pub fn new(bind_address: String) -> Result<Self> {
-        let bind_address: SocketAddr = bind_address
-            .parse()
-            .with_context(|| format!("Can't parse listen address {}", bind_address))?;
+    pub fn new(
+        bind_addresses: Vec<String>,
+        control_token: Option<String>,
+        state_rx: watch::Receiver<State>,
+        registry: Registry,
+    ) -> Result<Self> {
+        let bind_addresses = bind_addresses
+            .iter()
+            .map(|bind_address| {
+                bind_address
+                    .parse()
+                    .with_context(|| format!("Can't parse listen address {}", bind_address))
+            })
+            .collect::<Result<Vec<SocketAddr>>>()?;
 
         let shutdown = Arc::new(Notify::new());
 
+        let routes = Routes {
+            registry,
+            control_token,
+            state_rx,
+            scrape_tx: None,
+            readings: None,
+            dashboard: false,
+            updates_rx: None,
+            debug_rx: None,
+            away_mode_tx: None,
+        };
+
         let exporter = Exporter {
-            bind_address,
+            bind_addresses,
             shutdown,
+            routes,
         };
 
         Ok(exporter)
     }
 
-    async fn run(&self, error_tx: ErrorSender) {
-        info!("Starting server on {}", self.bind_address);
+    /// Ask the downloader to update before gathering metrics for each scrape
+    pub fn update_on_scrape(mut self, scrape_tx: ScrapeSender) -> Self {
+        self.routes.scrape_tx = Some(scrape_tx);
+
+        self
+    }
+
+    /// Serve the data collected in `readings` from the `/api/v1` endpoints and the dashboard
+    pub fn readings(mut self, readings: Readings) -> Self {
+        self.routes.readings = Some(readings);
+
+        self
+    }
+
+    /// Serve the `/dashboard` page from the readings
+    pub fn dashboard(mut self, dashboard: bool) -> Self {
+        self.routes.dashboard = dashboard;
+
+        self
+    }
+
+    /// Stream usage updates from `updates_rx` to `/events` clients
+    pub fn events(mut self, updates_rx: broadcast::Receiver<Update>) -> Self {
+        self.routes.updates_rx = Some(updates_rx);
+
+        self
+    }
+
+    /// Serve the downloader state from `debug_rx` at `/debug/state`
+    pub fn debug_state(mut self, debug_rx: watch::Receiver<DebugState>) -> Self {
+        self.routes.debug_rx = Some(debug_rx);
+
+        self
+    }
+
+    /// Change the away mode of locations through the downloader from `/control/away-mode`
+    pub fn away_mode(mut self, away_mode_tx: AwayModeSender) -> Self {
+        self.routes.away_mode_tx = Some(away_mode_tx);
+
+        self
+    }
+
+    async fn run(
+        bind_address: SocketAddr,
+        dual_stack: bool,
+        routes: Arc<Routes>,
+        shutdown: Arc<Notify>,
+        error_tx: ErrorSender,
+    ) {
+        info!("Starting server on {}", bind_address);
+
+        let service = make_service_fn(move |_| {
+            let routes = routes.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let routes = routes.clone();
+
+                    async move { Ok::<_, Infallible>(routes.route(request).await) }
+                }))
+            }
+        });
+
+        let result = bind(bind_address, dual_stack)
+            .and_then(|listener| Ok(hyper::Server::from_tcp(listener)?))
+            .map(|server| server.serve(service))
+            .with_context(|| format!("Failed to start server on {}", bind_address));
 
-        let result = Server::run(
-            Arc::new(prometheus::default_registry().clone()),
-            self.bind_address,
-            self.shutdown.notified(),
-        )
-        .await
-        .with_context(|| format!("Failed to start server on {}", self.bind_address));
+        let result = match result {
+            Ok(server) => server
+                .with_graceful_shutdown(shutdown.notified())
+                .await
+                .with_context(|| format!("Server on {} failed", bind_address)),
+            Err(e) => Err(e),
+        };
 
         if let Err(e) = result {
             error_tx
-                .send(e)
+                .send(marked::mark(e, Unrecoverable))
                 .await
                 .expect("Error channel failed unexpectedly, bug?");
         }
     }
 
+    /// Start a server on each bind address
     pub async fn start(self, error_tx: ErrorSender) {
-        crate::spawn_named(
-            async move {
-                self.run(error_tx).await;
+        let routes = Arc::new(self.routes);
+
+        for &bind_address in &self.bind_addresses {
+            // An IPv6 listener on all addresses also accepts IPv4 connections on Linux unless it
+            // is IPv6-only, which would conflict with an IPv4 listener on the same port
+            let dual_stack = bind_address.is_ipv6()
+                && self
+                    .bind_addresses
+                    .iter()
+                    .any(|other| other.is_ipv4() && other.port() == bind_address.port());
+
+            let routes = routes.clone();
+            let shutdown = self.shutdown.clone();
+            let error_tx = error_tx.clone();
+
+            crate::spawn_named(
+                async move {
+                    Exporter::run(bind_address, dual_stack, routes, shutdown, error_tx).await;
+                },
+                "flume_water_exporter",
+            );
+        }
+    }
+}
+
+/// Listen on `bind_address`, IPv6-only when `ipv6_only` is set
+fn bind(bind_address: SocketAddr, ipv6_only: bool) -> Result<std::net::TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(bind_address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+
+    if ipv6_only {
+        socket.set_only_v6(true)?;
+    }
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&bind_address.into())?;
+    socket.listen(1024)?;
+
+    Ok(socket.into())
+}
+
+impl Routes {
+    async fn route(&self, request: Request<Body>) -> Response<Body> {
+        match (request.method(), request.uri().path()) {
+            (&Method::GET, "/metrics") => {
+                self.update().await;
+
+                metrics(&self.registry, openmetrics_accepted(&request))
+            }
+            (&Method::GET, "/health") => text(StatusCode::OK, "ok"),
+            (&Method::GET, "/dashboard") => match &self.readings {
+                Some(readings) if self.dashboard => html(dashboard::render(&readings.latest())),
+                _ => not_found(),
             },
-            "adsb_exporter",
-        );
+            (&Method::GET, "/api/v1/usage") => match &self.readings {
+                Some(readings) => api_response(api::usage(readings, &query_params(&request))),
+                None => not_found(),
+            },
+            (&Method::GET, "/events") => match &self.updates_rx {
+                Some(updates_rx) => events(updates_rx.resubscribe()),
+                None => not_found(),
+            },
+            (&Method::GET, "/api/v1/devices") => match &self.readings {
+                Some(readings) => api_response(api::devices(readings)),
+                None => not_found(),
+            },
+            (&Method::GET, "/control/state") => self.control(&request, |routes| routes.state()),
+            (&Method::POST, "/control/away-mode") => match self.unauthorized(&request) {
+                Some(response) => response,
+                None => self.away_mode(&request).await,
+            },
+            (&Method::GET, "/debug/state") => match &self.control_token {
+                Some(_) => self.control(&request, |routes| routes.debug_state()),
+                None => self.debug_state(),
+            },
+            _ => not_found(),
+        }
+    }
+
+    /// Wait for the downloader to update when polling on scrape
+    async fn update(&self) {
+        let scrape_tx = match &self.scrape_tx {
+            Some(s) => s,
+            None => return,
+        };
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        if scrape_tx.send(reply_tx).await.is_err() {
+            return;
+        }
+
+        if timeout(SCRAPE_UPDATE_TIMEOUT, reply_rx).await.is_err() {
+            warn!("Update did not finish before scrape, serving previous values");
+        }
+    }
+
+    /// Require the configured control token before running `handler`
+    fn control<F>(&self, request: &Request<Body>, handler: F) -> Response<Body>
+    where
+        F: FnOnce(&Self) -> Response<Body>,
+    {
+        match self.unauthorized(request) {
+            Some(response) => response,
+            None => handler(self),
+        }
+    }
+
+    /// Response refusing `request` when it doesn't carry the configured control token, not
+    /// found when there is no control token
+    fn unauthorized(&self, request: &Request<Body>) -> Option<Response<Body>> {
+        let control_token = match &self.control_token {
+            Some(t) => t,
+            None => return Some(not_found()),
+        };
+
+        let authorized = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| constant_time_eq(token.as_bytes(), control_token.as_bytes()))
+            .unwrap_or(false);
+
+        if authorized {
+            None
+        } else {
+            Some(text(StatusCode::UNAUTHORIZED, "401 unauthorized"))
+        }
+    }
+
+    /// Turn away mode on or off for the `location` id or name with `away_mode` set to `on` or
+    /// `off`
+    async fn away_mode(&self, request: &Request<Body>) -> Response<Body> {
+        let away_mode_tx = match &self.away_mode_tx {
+            Some(away_mode_tx) => away_mode_tx,
+            None => return not_found(),
+        };
+
+        let params = query_params(request);
+
+        let location = match params.get("location") {
+            Some(location) => location.clone(),
+            None => return text(StatusCode::BAD_REQUEST, "location is required"),
+        };
+
+        let away_mode = match params.get("away_mode").map(String::as_str) {
+            Some("on") => true,
+            Some("off") => false,
+            _ => return text(StatusCode::BAD_REQUEST, "away_mode must be on or off"),
+        };
+
+        let (reply, reply_rx) = oneshot::channel();
+
+        let sent = away_mode_tx
+            .send(AwayModeRequest {
+                location,
+                away_mode,
+                reply,
+            })
+            .await;
+
+        if sent.is_err() {
+            return text(StatusCode::SERVICE_UNAVAILABLE, "downloader is not running");
+        }
+
+        // The change continues after the timeout, the result is only logged
+        match timeout(AWAY_MODE_TIMEOUT, reply_rx).await {
+            Ok(Ok(Ok(()))) => text(StatusCode::OK, "ok"),
+            Ok(Ok(Err(e))) if e.downcast_ref::<UnknownLocation>().is_some() => {
+                text(StatusCode::NOT_FOUND, &format!("{:#}", e))
+            }
+            Ok(Ok(Err(e))) => text(StatusCode::BAD_GATEWAY, &format!("{:#}", e)),
+            Ok(Err(_)) => text(StatusCode::SERVICE_UNAVAILABLE, "downloader is not running"),
+            Err(_) => text(
+                StatusCode::ACCEPTED,
+                "away mode change is still in progress",
+            ),
+        }
+    }
+
+    fn debug_state(&self) -> Response<Body> {
+        let debug_rx = match &self.debug_rx {
+            Some(debug_rx) => debug_rx,
+            None => return not_found(),
+        };
+
+        let state = debug_rx.borrow().clone();
+
+        match serde_json::to_string(&state) {
+            Ok(body) => json(body),
+            Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e)),
+        }
+    }
+
+    fn state(&self) -> Response<Body> {
+        let state = self.state_rx.borrow().clone();
+
+        match serde_json::to_string(&state) {
+            Ok(body) => json(body),
+            Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e)),
+        }
+    }
+}
+
+/// Whether to serve OpenMetrics to the scraper that sent `request`.  Only OpenMetrics carries the
+/// request duration exemplars, which are recorded while spans are exported.
+fn openmetrics_accepted(request: &Request<Body>) -> bool {
+    openmetrics::exemplars_enabled()
+        && request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(openmetrics::accepted)
+}
+
+fn metrics(registry: &Registry, openmetrics: bool) -> Response<Body> {
+    let metric_families = registry.gather();
+
+    if openmetrics {
+        let body = openmetrics::encode(&metric_families, client::duration_exemplar);
+
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, openmetrics::CONTENT_TYPE)
+            .body(Body::from(body))
+            .unwrap();
     }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+
+    encoder
+        .encode(&metric_families, &mut buffer)
+        .expect("write to vec cannot fail");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buffer))
+        .unwrap()
+}
+
+fn json(body: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Server-sent events stream with a `usage` event each time a sensor query completes
+fn events(mut updates_rx: broadcast::Receiver<Update>) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+
+    crate::spawn_named(
+        async move {
+            let mut keep_alive = tokio::time::interval(EVENTS_KEEP_ALIVE);
+
+            loop {
+                let event = tokio::select! {
+                    update = updates_rx.recv() => match update {
+                        Ok(update @ Update::Usage { .. }) => match serde_json::to_string(&update) {
+                            Ok(json) => format!("event: usage\ndata: {}\n\n", json),
+                            Err(e) => {
+                                warn!("Unable to serialize usage event {:#}", e);
+                                continue;
+                            }
+                        },
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Events stream fell behind, skipped {} updates", skipped);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    },
+                    _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+                };
+
+                if sender.send_data(event.into()).await.is_err() {
+                    debug!("Events client disconnected");
+
+                    return;
+                }
+            }
+        },
+        "events",
+    );
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+/// JSON for a successful API request, otherwise the error
+fn api_response(result: Result<String>) -> Response<Body> {
+    match result {
+        Ok(body) => json(body),
+        Err(e) => text(StatusCode::BAD_REQUEST, &format!("{:#}", e)),
+    }
+}
+
+/// Decoded query string parameters of `request`
+fn query_params(request: &Request<Body>) -> HashMap<String, String> {
+    let query = request.uri().query().unwrap_or_default();
+
+    reqwest::Url::parse(&format!("http://localhost/?{}", query))
+        .map(|url| url.query_pairs().into_owned().collect())
+        .unwrap_or_default()
+}
+
+fn html(body: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Compare `a` and `b` in time that depends only on their lengths so the control token can't be
+/// guessed a byte at a time from response times
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn not_found() -> Response<Body> {
+    text(StatusCode::NOT_FOUND, "404 not found")
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(body.to_string()))
+        .unwrap()
 }
@@ -2,28 +2,45 @@ use anyhow::Result;
 
 use chrono::offset::Utc;
 use chrono::DateTime;
+use chrono::Datelike;
 use chrono_tz::Tz;
 
 use crate::client;
 use crate::client::Budget;
+use crate::client::BudgetChange;
 use crate::client::Client;
+use crate::client::NotificationRule;
 use crate::device::Device;
+use crate::flume_api::FlumeApi;
+use crate::query_settings::UsageQuery;
+use crate::sensor::QueryWindow;
 use crate::sensor::Sensor;
+use crate::state::TokenState;
+
+use tracing::warn;
 
 use std::time::Duration;
-use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+/// Flume API client that refreshes its access token when it expires.  Build one with
+/// `crate::flume_builder::FlumeBuilder`.
 #[derive(Clone)]
-pub struct Flume {
-    pub client: Client,
+pub struct Flume<A = Client> {
+    pub client: A,
 
     pub access_token: String,
     pub refresh_token: String,
-    pub token_expires_in: u64,
-    pub token_fetch_time: Instant,
+    /// Wall clock time the access token expires, which keeps counting while the machine is
+    /// suspended like the token lifetime does on the server
+    pub token_expires_at: SystemTime,
+
+    /// Configured user id, used instead of looking up the authenticated user
+    pub user_id: Option<i64>,
 }
 
-impl Flume {
+impl<A: FlumeApi> Flume<A> {
+    /// Budgets for `sensor`
     pub async fn budgets(&mut self, user_id: i64, sensor: &Sensor) -> Result<Vec<Budget>> {
         self.refresh_token_if_expired().await?;
 
@@ -34,33 +51,253 @@ impl Flume {
             .await
     }
 
-    pub async fn devices(&mut self, user_id: i64) -> Result<Vec<Device>> {
+    /// Create a budget for `sensor_id`
+    pub async fn create_budget(
+        &mut self,
+        user_id: i64,
+        sensor_id: &str,
+        budget: &BudgetChange,
+    ) -> Result<()> {
+        self.refresh_token_if_expired().await?;
+
+        self.client
+            .create_budget(&self.access_token, user_id, sensor_id, budget)
+            .await
+    }
+
+    /// Change the fields set in `budget` for budget `budget_id` of `sensor_id`
+    pub async fn update_budget(
+        &mut self,
+        user_id: i64,
+        sensor_id: &str,
+        budget_id: u64,
+        budget: &BudgetChange,
+    ) -> Result<()> {
+        self.refresh_token_if_expired().await?;
+
+        self.client
+            .update_budget(&self.access_token, user_id, sensor_id, budget_id, budget)
+            .await
+    }
+
+    /// Delete budget `budget_id` of `sensor_id`
+    pub async fn delete_budget(
+        &mut self,
+        user_id: i64,
+        sensor_id: &str,
+        budget_id: u64,
+    ) -> Result<()> {
         self.refresh_token_if_expired().await?;
 
         self.client
+            .delete_budget(&self.access_token, user_id, sensor_id, budget_id)
+            .await
+    }
+
+    /// Bridges and sensors for the user
+    pub async fn devices(&mut self, user_id: i64) -> Result<Vec<Device>> {
+        self.refresh_token_if_expired().await?;
+
+        let devices = self
+            .client
             .devices(&self.access_token, user_id)
             .await?
-            .iter()
-            .map(|d: &client::Device| Device::try_from(d.clone()))
-            .collect()
+            .into_iter()
+            .filter_map(|device| {
+                let id = device.id().to_string();
+
+                // One device Flume returns oddly shouldn't stop the others from being exported
+                match Device::try_from(device) {
+                    Ok(device) => Some(device),
+                    Err(e) => {
+                        warn!(device_id = id, "Skipping device {:#}", e);
+
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Ok(devices)
+    }
+
+    /// Usage alert rules for `sensor`
+    pub async fn notification_rules(
+        &mut self,
+        user_id: i64,
+        sensor: &Sensor,
+    ) -> Result<Vec<NotificationRule>> {
+        self.refresh_token_if_expired().await?;
+
+        self.client
+            .notification_rules(&self.access_token, user_id, &sensor.sensor.id)
+            .await
     }
 
+    /// Usage for `sensor` in liters over `window`, see `Sensor::query_window`, combined by the
+    /// `usage` query operation.  With `periods` the usage for today, this week, and this month
+    /// is queried in the same request, see `query_periods`.
+    ///
+    /// Windows are half-open on whole minutes, `[last_update, until)`, so consecutive windows
+    /// ending and starting at the same minute never count a minute twice or skip one.  The Flume
+    /// API includes the bucket at `until_datetime` so the window ends one minute before `until`.
     pub async fn query_sensor(
         &mut self,
         user_id: i64,
         sensor: &Sensor,
-    ) -> Result<(f64, DateTime<Tz>)> {
+        window: QueryWindow,
+        usage: &UsageQuery,
+        periods: bool,
+    ) -> Result<(f64, Option<PeriodUsage>)> {
         self.refresh_token_if_expired().await?;
 
-        let last_update = sensor.last_update;
-        let timezone = last_update.timezone();
-        let since_datetime = last_update.format("%F %H:%M:00").to_string();
-        let now = Utc::now().with_timezone(&timezone);
-        let until_datetime = Some(now.format("%F %H:%M:00").to_string());
+        let since_datetime = window.first.format("%F %H:%M:00").to_string();
+        let until_datetime = Some(window.last.format("%F %H:%M:00").to_string());
+
+        let mut queries = vec![client::Query {
+            request_id: since_datetime.clone(),
+            bucket: usage.bucket.clone(),
+            since_datetime,
+            until_datetime,
+            operation: Some(usage.operation.clone()),
+            units: Some(client::QueryUnits::LITERS),
+            ..Default::default()
+        }];
+
+        if periods {
+            queries.extend(period_queries(sensor));
+        }
+
+        let totals = self
+            .query_totals(user_id, &sensor.sensor.id, queries)
+            .await?;
+
+        let periods = match totals[1..] {
+            [today, this_week, this_month] => Some(PeriodUsage {
+                today,
+                this_week,
+                this_month,
+            }),
+            _ => None,
+        };
+
+        Ok((totals[0], periods))
+    }
+
+    /// Usage for `sensor` in liters from `since` until the start of `window`, summed over year
+    /// buckets
+    pub async fn query_before(
+        &mut self,
+        user_id: i64,
+        sensor: &Sensor,
+        since: DateTime<Tz>,
+        window: QueryWindow,
+    ) -> Result<f64> {
+        self.refresh_token_if_expired().await?;
+
+        let since_datetime = since.format("%F %H:%M:00").to_string();
+        let until = window.first - chrono::Duration::minutes(1);
+        let until_datetime = Some(until.format("%F %H:%M:00").to_string());
 
         let query = client::Query {
             request_id: since_datetime.clone(),
+            bucket: client::QueryBucket::YR,
+            since_datetime,
+            until_datetime,
+            operation: Some(client::QueryOperation::SUM),
+            units: Some(client::QueryUnits::LITERS),
+            ..Default::default()
+        };
+
+        self.query_total(user_id, &sensor.sensor.id, query).await
+    }
+
+    /// Usage for `sensor` in liters since the start of today, this week (starting Sunday), and
+    /// this month in the sensor's timezone, from one request
+    pub async fn query_periods(&mut self, user_id: i64, sensor: &Sensor) -> Result<PeriodUsage> {
+        self.refresh_token_if_expired().await?;
+
+        let totals = self
+            .query_totals(user_id, &sensor.sensor.id, period_queries(sensor))
+            .await?;
+
+        Ok(PeriodUsage {
+            today: totals[0],
+            this_week: totals[1],
+            this_month: totals[2],
+        })
+    }
+
+    /// Highest and average flow for `sensor` over `window` in liters per minute, from the MAX and
+    /// AVG of minute buckets in one request
+    pub async fn query_flow(
+        &mut self,
+        user_id: i64,
+        sensor: &Sensor,
+        window: QueryWindow,
+    ) -> Result<(f64, f64)> {
+        self.refresh_token_if_expired().await?;
+
+        let since_datetime = window.first.format("%F %H:%M:00").to_string();
+        let until_datetime = Some(window.last.format("%F %H:%M:00").to_string());
+
+        let query = |request_id: &str, operation| client::Query {
+            request_id: request_id.to_string(),
+            bucket: client::QueryBucket::MIN,
+            since_datetime: since_datetime.clone(),
+            until_datetime: until_datetime.clone(),
+            operation: Some(operation),
+            units: Some(client::QueryUnits::LITERS),
+            ..Default::default()
+        };
+
+        let queries = vec![
+            query("max", client::QueryOperation::MAX),
+            query("avg", client::QueryOperation::AVG),
+        ];
+
+        let totals = self
+            .query_totals(user_id, &sensor.sensor.id, queries)
+            .await?;
+
+        Ok((totals[0], totals[1]))
+    }
+
+    /// Usage for `sensor` in liters so far in the current minute, from its minute bucket
+    pub async fn query_current_minute(&mut self, user_id: i64, sensor: &Sensor) -> Result<f64> {
+        self.refresh_token_if_expired().await?;
+
+        let minute = sensor.now().format("%F %H:%M:00").to_string();
+
+        let query = client::Query {
+            request_id: minute.clone(),
             bucket: client::QueryBucket::MIN,
+            since_datetime: minute.clone(),
+            until_datetime: Some(minute),
+            units: Some(client::QueryUnits::LITERS),
+            ..Default::default()
+        };
+
+        self.query_total(user_id, &sensor.sensor.id, query).await
+    }
+
+    /// Total usage for `sensor` from `since` until now, summed over `bucket`
+    pub async fn query_usage_since(
+        &mut self,
+        user_id: i64,
+        sensor: &Sensor,
+        since: DateTime<Tz>,
+        bucket: client::QueryBucket,
+    ) -> Result<f64> {
+        self.refresh_token_if_expired().await?;
+
+        let since_datetime = since.format("%F %H:%M:00").to_string();
+        let now = Utc::now().with_timezone(&since.timezone());
+        let until_datetime = Some(now.format("%F %H:%M:00").to_string());
+
+        let query = client::Query {
+            request_id: since_datetime.clone(),
+            bucket,
             since_datetime,
             until_datetime,
             operation: Some(client::QueryOperation::SUM),
@@ -68,18 +305,72 @@ impl Flume {
             ..Default::default()
         };
 
-        let new_usage = self
+        self.query_total(user_id, &sensor.sensor.id, query).await
+    }
+
+    /// First result of `query`, the total for a query with an operation
+    async fn query_total(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        query: client::Query,
+    ) -> Result<f64> {
+        let results = self
+            .client
+            .query_results(&self.access_token, user_id, device_id, query)
+            .await?;
+
+        Ok(results.first().map(|r| r.value).unwrap_or(0.0))
+    }
+
+    /// First result of each of `queries`, made in one request
+    async fn query_totals(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        queries: Vec<client::Query>,
+    ) -> Result<Vec<f64>> {
+        let results = self
             .client
-            .query_samples(&self.access_token, user_id, &sensor.sensor.id, query)
+            .query_batch(&self.access_token, user_id, device_id, queries)
             .await?;
 
-        Ok((new_usage, now))
+        Ok(results
+            .iter()
+            .map(|results| results.first().map(|r| r.value).unwrap_or(0.0))
+            .collect())
     }
 
-    async fn refresh_token_if_expired(&mut self) -> Result<bool> {
-        let expiry = Duration::from_secs(self.token_expires_in);
+    /// Results of an arbitrary `query` for `device_id`
+    pub async fn query_results(
+        &mut self,
+        user_id: i64,
+        device_id: &str,
+        query: client::Query,
+    ) -> Result<Vec<client::QueryResult>> {
+        self.refresh_token_if_expired().await?;
+
+        self.client
+            .query_results(&self.access_token, user_id, device_id, query)
+            .await
+    }
+
+    /// Turn away mode on or off for `location_id`
+    pub async fn set_away_mode(
+        &mut self,
+        user_id: i64,
+        location_id: &str,
+        away_mode: bool,
+    ) -> Result<()> {
+        self.refresh_token_if_expired().await?;
 
-        if Instant::now().duration_since(self.token_fetch_time) < expiry {
+        self.client
+            .set_away_mode(&self.access_token, user_id, location_id, away_mode)
+            .await
+    }
+
+    async fn refresh_token_if_expired(&mut self) -> Result<bool> {
+        if SystemTime::now() < self.token_expires_at {
             return Ok(false);
         };
 
@@ -87,15 +378,78 @@ impl Flume {
 
         self.access_token = token.access_token;
         self.refresh_token = token.refresh_token;
-        self.token_expires_in = token.expires_in;
-        self.token_fetch_time = token_fetch_time;
+        self.token_expires_at = token_fetch_time + Duration::from_secs(token.expires_in);
 
         Ok(true)
     }
 
+    /// Current tokens for a state snapshot
+    pub fn token_state(&self) -> TokenState {
+        let expires_at = self
+            .token_expires_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        TokenState {
+            access_token: self.access_token.clone(),
+            refresh_token: self.refresh_token.clone(),
+            expires_at: expires_at.as_secs(),
+        }
+    }
+
+    /// Id of the configured user, or of the authenticated user when none is configured
     pub async fn user_id(&mut self) -> Result<i64> {
+        if let Some(user_id) = self.user_id {
+            return Ok(user_id);
+        }
+
         self.refresh_token_if_expired().await?;
 
         self.client.user_id(&self.access_token).await
     }
 }
+
+/// Usage in liters since the start of today, this week, and this month at a sensor's location
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PeriodUsage {
+    pub today: f64,
+    pub this_week: f64,
+    pub this_month: f64,
+}
+
+/// Queries for the usage since the start of today, this week, and this month at `sensor`'s
+/// location, in that order
+fn period_queries(sensor: &Sensor) -> Vec<client::Query> {
+    let now = sensor.now();
+    let date = now.date_naive();
+    let days_into_week = date.weekday().num_days_from_sunday() as i64;
+
+    let periods = [
+        ("today", sensor.start_of_day(date), client::QueryBucket::DAY),
+        (
+            "this_week",
+            sensor.start_of_day(date - chrono::Duration::days(days_into_week)),
+            client::QueryBucket::DAY,
+        ),
+        (
+            "this_month",
+            sensor.start_of_day(date.with_day(1).unwrap()),
+            client::QueryBucket::MON,
+        ),
+    ];
+
+    let until_datetime = Some(now.format("%F %H:%M:00").to_string());
+
+    periods
+        .into_iter()
+        .map(|(request_id, since, bucket)| client::Query {
+            request_id: request_id.to_string(),
+            bucket,
+            since_datetime: since.format("%F %H:%M:00").to_string(),
+            until_datetime: until_datetime.clone(),
+            operation: Some(client::QueryOperation::SUM),
+            units: Some(client::QueryUnits::LITERS),
+            ..Default::default()
+        })
+        .collect()
+}
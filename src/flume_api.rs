@@ -0,0 +1,434 @@
+use anyhow::Result;
+
+use crate::client::Budget;
+use crate::client::BudgetChange;
+use crate::client::Client;
+use crate::client::Device;
+use crate::client::NotificationRule;
+use crate::client::Query;
+use crate::client::QueryResult;
+use crate::client::Token;
+
+use std::future::Future;
+use std::time::SystemTime;
+
+/// Requests made to the Flume API.
+///
+/// `Client` makes real requests, tests can implement this to drive `Flume` and the `Downloader`
+/// without the network.
+pub trait FlumeApi: Clone + Send + Sync + 'static {
+    /// Authenticate with a username and password
+    fn access_token(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> impl Future<Output = Result<(Token, SystemTime)>> + Send;
+
+    /// Budgets for a sensor
+    fn budgets(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+    ) -> impl Future<Output = Result<Vec<Budget>>> + Send;
+
+    /// Create a budget for a sensor
+    fn create_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget: &BudgetChange,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Change the fields set in `budget` for a budget of a sensor
+    fn update_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget_id: u64,
+        budget: &BudgetChange,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Delete a budget of a sensor
+    fn delete_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget_id: u64,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Bridges and sensors for a user, including their locations
+    fn devices(
+        &self,
+        access_token: &str,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Vec<Device>>> + Send;
+
+    /// Usage alert rules for a sensor
+    fn notification_rules(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+    ) -> impl Future<Output = Result<Vec<NotificationRule>>> + Send;
+
+    /// All results for one query
+    fn query_results(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        query: Query,
+    ) -> impl Future<Output = Result<Vec<QueryResult>>> + Send;
+
+    /// All results for each of several queries made in one request, in the order given
+    fn query_batch(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        queries: Vec<Query>,
+    ) -> impl Future<Output = Result<Vec<Vec<QueryResult>>>> + Send;
+
+    /// Turn away mode on or off for a location
+    fn set_away_mode(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        location_id: &str,
+        away_mode: bool,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Exchange a refresh token for a new access token
+    fn refresh_token(
+        &self,
+        refresh_token: &str,
+    ) -> impl Future<Output = Result<(Token, SystemTime)>> + Send;
+
+    /// Id of the user the access token belongs to
+    fn user_id(&self, access_token: &str) -> impl Future<Output = Result<i64>> + Send;
+}
+
+impl FlumeApi for Client {
+    async fn access_token(&self, username: &str, password: &str) -> Result<(Token, SystemTime)> {
+        Client::access_token(self, username, password).await
+    }
+
+    async fn budgets(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+    ) -> Result<Vec<Budget>> {
+        Client::budgets(self, access_token, user_id, sensor_id).await
+    }
+
+    async fn create_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget: &BudgetChange,
+    ) -> Result<()> {
+        Client::create_budget(self, access_token, user_id, sensor_id, budget).await
+    }
+
+    async fn update_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget_id: u64,
+        budget: &BudgetChange,
+    ) -> Result<()> {
+        Client::update_budget(self, access_token, user_id, sensor_id, budget_id, budget).await
+    }
+
+    async fn delete_budget(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        budget_id: u64,
+    ) -> Result<()> {
+        Client::delete_budget(self, access_token, user_id, sensor_id, budget_id).await
+    }
+
+    async fn devices(&self, access_token: &str, user_id: i64) -> Result<Vec<Device>> {
+        Client::devices(self, access_token, user_id).await
+    }
+
+    async fn notification_rules(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+    ) -> Result<Vec<NotificationRule>> {
+        Client::notification_rules(self, access_token, user_id, sensor_id).await
+    }
+
+    async fn query_results(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        query: Query,
+    ) -> Result<Vec<QueryResult>> {
+        Client::query_results(self, access_token, user_id, sensor_id, query).await
+    }
+
+    async fn query_batch(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        sensor_id: &str,
+        queries: Vec<Query>,
+    ) -> Result<Vec<Vec<QueryResult>>> {
+        Client::query_batch(self, access_token, user_id, sensor_id, queries).await
+    }
+
+    async fn set_away_mode(
+        &self,
+        access_token: &str,
+        user_id: i64,
+        location_id: &str,
+        away_mode: bool,
+    ) -> Result<()> {
+        Client::set_away_mode(self, access_token, user_id, location_id, away_mode).await
+    }
+
+    async fn refresh_token(&self, refresh_token: &str) -> Result<(Token, SystemTime)> {
+        Client::refresh_token(self, refresh_token).await
+    }
+
+    async fn user_id(&self, access_token: &str) -> Result<i64> {
+        Client::user_id(self, access_token).await
+    }
+}
+
+/// `FlumeApi` answering from canned devices and usage so the downloader can be tested without
+/// the network
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+
+    use crate::flume::Flume;
+
+    use anyhow::anyhow;
+
+    use chrono::DateTime;
+    use chrono::SecondsFormat;
+    use chrono::Utc;
+
+    use serde_json::json;
+
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// User id of the mock account
+    pub const USER_ID: i64 = 1234;
+
+    #[derive(Clone, Default)]
+    pub struct MockApi {
+        state: Arc<Mutex<MockState>>,
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        devices: Vec<Device>,
+        budgets: Vec<Budget>,
+        liters: f64,
+        error: Option<String>,
+        failing_queries: Option<fn(&str, &Query) -> bool>,
+        queries: Vec<Query>,
+    }
+
+    impl MockApi {
+        /// A `Flume` with an unexpired token making its requests to this mock
+        pub fn flume(&self) -> Flume<MockApi> {
+            Flume {
+                client: self.clone(),
+                access_token: "ACCESS_TOKEN".to_string(),
+                refresh_token: "REFRESH_TOKEN".to_string(),
+                token_expires_at: SystemTime::now() + Duration::from_secs(3600),
+                user_id: Some(USER_ID),
+            }
+        }
+
+        /// Return `devices` from the devices request
+        pub fn set_devices(&self, devices: Vec<Device>) {
+            self.state.lock().unwrap().devices = devices;
+        }
+
+        /// Return `budgets` for every sensor
+        pub fn set_budgets(&self, budgets: Vec<Budget>) {
+            self.state.lock().unwrap().budgets = budgets;
+        }
+
+        /// Answer every query with `liters`
+        pub fn set_liters(&self, liters: f64) {
+            self.state.lock().unwrap().liters = liters;
+        }
+
+        /// Fail every request with `message` until cleared with `None`
+        pub fn fail_with(&self, message: Option<&str>) {
+            self.state.lock().unwrap().error = message.map(str::to_string);
+        }
+
+        /// Fail query requests for a device id and any query `failing` is true for, until
+        /// cleared with `None`
+        pub fn fail_queries(&self, failing: Option<fn(&str, &Query) -> bool>) {
+            self.state.lock().unwrap().failing_queries = failing;
+        }
+
+        /// Queries made so far, in order
+        pub fn queries(&self) -> Vec<Query> {
+            self.state.lock().unwrap().queries.clone()
+        }
+
+        fn check(&self) -> Result<()> {
+            match &self.state.lock().unwrap().error {
+                Some(message) => Err(anyhow!("{}", message)),
+                None => Ok(()),
+            }
+        }
+
+        fn check_queries(&self, device_id: &str, queries: &[Query]) -> Result<()> {
+            self.check()?;
+
+            match self.state.lock().unwrap().failing_queries {
+                Some(failing) if queries.iter().any(|query| failing(device_id, query)) => {
+                    Err(anyhow!("query failed"))
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn answer(&self, query: Query) -> Vec<QueryResult> {
+            let mut state = self.state.lock().unwrap();
+
+            let result = QueryResult {
+                datetime: Some(query.since_datetime.clone()),
+                value: state.liters,
+            };
+            state.queries.push(query);
+
+            vec![result]
+        }
+    }
+
+    /// A sensor at location 5678 in Los Angeles that was last seen at `last_seen`
+    pub fn sensor(id: &str, last_seen: DateTime<Utc>) -> Device {
+        serde_json::from_value(json!({
+            "id": id,
+            "type": 2,
+            "bridge_id": "1111111111111111111",
+            "oriented": true,
+            "last_seen": last_seen.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "connected": true,
+            "battery_level": "high",
+            "product": "flume2sensor",
+            "location": {
+                "id": 5678,
+                "name": "Home",
+                "tz": "America/Los_Angeles",
+                "installation": "complete",
+                "away_mode": false,
+            },
+        }))
+        .unwrap()
+    }
+
+    impl FlumeApi for MockApi {
+        async fn access_token(&self, _: &str, _: &str) -> Result<(Token, SystemTime)> {
+            Err(anyhow!("the mock is already authenticated"))
+        }
+
+        async fn budgets(&self, _: &str, _: i64, _: &str) -> Result<Vec<Budget>> {
+            self.check()?;
+
+            Ok(self.state.lock().unwrap().budgets.clone())
+        }
+
+        async fn create_budget(&self, _: &str, _: i64, _: &str, _: &BudgetChange) -> Result<()> {
+            self.check()
+        }
+
+        async fn update_budget(
+            &self,
+            _: &str,
+            _: i64,
+            _: &str,
+            _: u64,
+            _: &BudgetChange,
+        ) -> Result<()> {
+            self.check()
+        }
+
+        async fn delete_budget(&self, _: &str, _: i64, _: &str, _: u64) -> Result<()> {
+            self.check()
+        }
+
+        async fn devices(&self, _: &str, _: i64) -> Result<Vec<Device>> {
+            self.check()?;
+
+            Ok(self.state.lock().unwrap().devices.clone())
+        }
+
+        async fn notification_rules(
+            &self,
+            _: &str,
+            _: i64,
+            _: &str,
+        ) -> Result<Vec<NotificationRule>> {
+            self.check()?;
+
+            Ok(vec![])
+        }
+
+        async fn query_results(
+            &self,
+            _: &str,
+            _: i64,
+            device_id: &str,
+            query: Query,
+        ) -> Result<Vec<QueryResult>> {
+            self.check_queries(device_id, std::slice::from_ref(&query))?;
+
+            Ok(self.answer(query))
+        }
+
+        async fn query_batch(
+            &self,
+            _: &str,
+            _: i64,
+            device_id: &str,
+            queries: Vec<Query>,
+        ) -> Result<Vec<Vec<QueryResult>>> {
+            self.check_queries(device_id, &queries)?;
+
+            Ok(queries
+                .into_iter()
+                .map(|query| self.answer(query))
+                .collect())
+        }
+
+        async fn set_away_mode(&self, _: &str, _: i64, _: &str, _: bool) -> Result<()> {
+            self.check()
+        }
+
+        async fn refresh_token(&self, _: &str) -> Result<(Token, SystemTime)> {
+            Err(anyhow!("the mock token never expires"))
+        }
+
+        async fn user_id(&self, _: &str) -> Result<i64> {
+            self.check()?;
+
+            Ok(USER_ID)
+        }
+    }
+}
@@ -3,18 +3,80 @@ use anyhow::Result;
 use crate::client::Client;
 use crate::configuration::Configuration;
 use crate::flume::Flume;
+use crate::flume_api::FlumeApi;
+use crate::state::TokenState;
 
+use tracing::debug;
+
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Builds a `Flume` from a configuration, authenticating unless a saved token is restored
 pub struct FlumeBuilder {
     configuration: Configuration,
+    token: Option<TokenState>,
 }
 
 impl FlumeBuilder {
     pub fn from_configuration(configuration: Configuration) -> Self {
-        FlumeBuilder { configuration }
+        FlumeBuilder {
+            configuration,
+            token: None,
+        }
+    }
+
+    /// Reuse tokens from a state snapshot instead of authenticating.  An expired access token is
+    /// refreshed with the snapshot's refresh token.
+    pub fn restore_token(mut self, token: Option<TokenState>) -> Self {
+        self.token = token;
+
+        self
     }
 
+    /// Authenticate, or reuse the restored token, and build the `Flume`
     pub async fn build(self) -> Result<Flume> {
-        let mut client = Client::new(&self.configuration);
+        let client = Client::new(&self.configuration);
+
+        self.build_with(client).await
+    }
+
+    /// Build a `Flume` that makes requests with `client`
+    pub async fn build_with<A: FlumeApi>(self, client: A) -> Result<Flume<A>> {
+        if let Some(token) = self.token {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+
+            if token.expires_at > now {
+                return Ok(Flume {
+                    client,
+
+                    access_token: token.access_token,
+                    refresh_token: token.refresh_token,
+                    token_expires_at: UNIX_EPOCH + Duration::from_secs(token.expires_at),
+
+                    user_id: self.configuration.user_id(),
+                });
+            }
+
+            // The password is only sent again when Flume won't refresh the token
+            match client.refresh_token(&token.refresh_token).await {
+                Ok((token, token_fetch_time)) => {
+                    return Ok(Flume {
+                        client,
+
+                        access_token: token.access_token,
+                        refresh_token: token.refresh_token,
+                        token_expires_at: token_fetch_time + Duration::from_secs(token.expires_in),
+
+                        user_id: self.configuration.user_id(),
+                    });
+                }
+                Err(e) => debug!("Unable to refresh the restored token {:#}", e),
+            }
+        }
 
         let (token, token_fetch_time) = client
             .access_token(
@@ -28,8 +90,9 @@ impl FlumeBuilder {
 
             access_token: token.access_token,
             refresh_token: token.refresh_token,
-            token_expires_in: token.expires_in,
-            token_fetch_time,
+            token_expires_at: token_fetch_time + Duration::from_secs(token.expires_in),
+
+            user_id: self.configuration.user_id(),
         })
     }
 }
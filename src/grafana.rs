@@ -0,0 +1,246 @@
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::configuration::Configuration;
+use crate::downloader::LITERS_PER_GALLON;
+
+use serde_json::json;
+use serde_json::Value;
+
+/// Volume units the dashboard displays
+struct Units {
+    /// Multiplies liters into the displayed unit
+    factor: f64,
+    /// Grafana unit for volumes
+    volume: &'static str,
+    /// Grafana unit for flow per minute
+    flow: &'static str,
+}
+
+impl Units {
+    fn parse(units: Option<&str>) -> Result<Self> {
+        match units.unwrap_or("liters") {
+            "liters" => Ok(Units {
+                factor: 1.0,
+                volume: "litre",
+                flow: "flowlpm",
+            }),
+            "gallons" => Ok(Units {
+                factor: 1.0 / LITERS_PER_GALLON,
+                volume: "gallons",
+                flow: "flowgpm",
+            }),
+            units => Err(anyhow!("Unknown units {}, use liters or gallons", units)),
+        }
+    }
+
+    /// `expression` in liters converted to the displayed unit
+    fn convert(&self, expression: &str) -> String {
+        if self.factor == 1.0 {
+            expression.to_string()
+        } else {
+            format!("({}) * {}", expression, self.factor)
+        }
+    }
+}
+
+/// Print a Grafana dashboard for the exporter's metrics, with volumes in `units`
+pub fn run(configuration: &Configuration, units: Option<&str>) -> Result<()> {
+    let dashboard = dashboard(configuration, &Units::parse(units)?);
+
+    println!("{}", serde_json::to_string_pretty(&dashboard)?);
+
+    Ok(())
+}
+
+fn dashboard(configuration: &Configuration, units: &Units) -> Value {
+    let location = r#"location=~"$location""#;
+
+    let mut panels = vec![
+        panel(
+            "Flow rate",
+            "timeseries",
+            units.flow,
+            &[(
+                units.convert(&format!(
+                    "rate(flume_water_usage_liters{{{}}}[5m]) * 60",
+                    location
+                )),
+                "{{location}}",
+            )],
+        ),
+        panel(
+            "Usage today",
+            "stat",
+            units.volume,
+            &[(
+                units.convert(&format!("flume_water_usage_today_liters{{{}}}", location)),
+                "{{location}}",
+            )],
+        ),
+        panel(
+            "Usage this week and month",
+            "stat",
+            units.volume,
+            &[
+                (
+                    units.convert(&format!(
+                        "flume_water_usage_this_week_liters{{{}}}",
+                        location
+                    )),
+                    "{{location}} this week",
+                ),
+                (
+                    units.convert(&format!(
+                        "flume_water_usage_this_month_liters{{{}}}",
+                        location
+                    )),
+                    "{{location}} this month",
+                ),
+            ],
+        ),
+        panel(
+            "Budgets used",
+            "bargauge",
+            "percentunit",
+            &[(
+                format!("flume_water_budget_used_ratio{{{}}}", location),
+                "{{location}} {{name}}",
+            )],
+        ),
+        panel(
+            "Continuous flow",
+            "timeseries",
+            "m",
+            &[(
+                format!("flume_water_continuous_flow_minutes{{{}}}", location),
+                "{{location}}",
+            )],
+        ),
+        panel(
+            "Sensor battery level",
+            "stat",
+            "percentunit",
+            &[(
+                format!("flume_water_sensor_battery_info{{{}}}", location),
+                "{{device_id}}",
+            )],
+        ),
+        panel(
+            "Devices connected",
+            "stat",
+            "none",
+            &[
+                (
+                    format!("flume_water_bridge_connected{{{}}}", location),
+                    "bridge {{device_id}}",
+                ),
+                (
+                    format!("flume_water_sensor_connected{{{}}}", location),
+                    "sensor {{device_id}}",
+                ),
+            ],
+        ),
+        panel(
+            "Flume API requests in the last hour",
+            "timeseries",
+            "none",
+            &[("flume_water_api_requests_last_hour".to_string(), "requests")],
+        ),
+    ];
+
+    if configuration.flow_metrics() {
+        panels.push(panel(
+            "Highest and average flow",
+            "timeseries",
+            units.flow,
+            &[
+                (
+                    units.convert(&format!(
+                        "flume_water_flow_max_liters_per_minute{{{}}}",
+                        location
+                    )),
+                    "{{location}} max",
+                ),
+                (
+                    units.convert(&format!(
+                        "flume_water_flow_avg_liters_per_minute{{{}}}",
+                        location
+                    )),
+                    "{{location}} avg",
+                ),
+            ],
+        ));
+    }
+
+    // Two panels per row
+    let panels: Vec<Value> = panels
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut panel)| {
+            panel["id"] = json!(index + 1);
+            panel["gridPos"] = json!({
+                "h": 8,
+                "w": 12,
+                "x": (index % 2) * 12,
+                "y": (index / 2) * 8,
+            });
+
+            panel
+        })
+        .collect();
+
+    json!({
+        "title": "Flume water",
+        "uid": "flume-water",
+        "tags": ["flume"],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "refresh": "1m",
+        "time": { "from": "now-24h", "to": "now" },
+        "templating": {
+            "list": [
+                {
+                    "name": "datasource",
+                    "type": "datasource",
+                    "query": "prometheus",
+                },
+                {
+                    "name": "location",
+                    "type": "query",
+                    "datasource": { "type": "prometheus", "uid": "${datasource}" },
+                    "query": "label_values(flume_water_usage_liters, location)",
+                    "refresh": 2,
+                    "multi": true,
+                    "includeAll": true,
+                    "current": { "text": "All", "value": "$__all" },
+                },
+            ],
+        },
+        "panels": panels,
+    })
+}
+
+/// A panel of `panel_type` graphing each target expression with its legend
+fn panel(title: &str, panel_type: &str, unit: &str, targets: &[(String, &str)]) -> Value {
+    let targets: Vec<Value> = targets
+        .iter()
+        .zip('A'..)
+        .map(|((expression, legend), ref_id)| {
+            json!({
+                "datasource": { "type": "prometheus", "uid": "${datasource}" },
+                "expr": expression,
+                "legendFormat": legend,
+                "refId": ref_id.to_string(),
+            })
+        })
+        .collect();
+
+    json!({
+        "title": title,
+        "type": panel_type,
+        "datasource": { "type": "prometheus", "uid": "${datasource}" },
+        "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+        "targets": targets,
+    })
+}
@@ -0,0 +1,92 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::configuration::Configuration;
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// How long the healthcheck waits for the exporter to respond
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Check that the exporter for `configuration` is healthy.
+///
+/// When the HTTP server is enabled its `/health` endpoint is requested, otherwise the state
+/// file must have been saved within two query intervals.
+pub async fn check(configuration: &Configuration) -> Result<()> {
+    if configuration.http_server() {
+        return check_server(configuration).await;
+    }
+
+    match configuration.state_file() {
+        Some(state_file) => check_state_file(configuration, &state_file),
+        None => Err(anyhow!(
+            "Nothing to check, enable http_server or set a state_file"
+        )),
+    }
+}
+
+async fn check_server(configuration: &Configuration) -> Result<()> {
+    let bind_addresses = configuration.bind_addresses();
+    let bind_address = bind_addresses
+        .first()
+        .ok_or_else(|| anyhow!("No bind_address to check"))?;
+    let mut address: SocketAddr = bind_address
+        .parse()
+        .with_context(|| format!("Can't parse listen address {}", bind_address))?;
+
+    // A server listening on all addresses is reachable on loopback
+    if address.ip().is_unspecified() {
+        let loopback = match address.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        };
+
+        address.set_ip(loopback);
+    }
+
+    let uri = format!("http://{}/health", address);
+
+    let response = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()?
+        .get(&uri)
+        .send()
+        .await
+        .with_context(|| format!("Unable to reach {}", uri))?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} returned {}", uri, response.status()))
+    }
+}
+
+fn check_state_file(configuration: &Configuration, state_file: &std::path::Path) -> Result<()> {
+    let modified = std::fs::metadata(state_file)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Unable to check {}", state_file.display()))?;
+
+    // The state file is saved after each update, allow one update to be missed
+    let max_age = configuration.query_interval() * 2 + configuration.request_timeout();
+
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+
+    if age <= max_age {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} was last saved {}s ago, more than {}s",
+            state_file.display(),
+            age.as_secs(),
+            max_age.as_secs()
+        ))
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::configuration::HistoryConfiguration;
+use crate::update::Update;
+
+use rusqlite::params;
+use rusqlite::Connection;
+
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// How often history older than the retention is deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS usage (
+    device_id TEXT NOT NULL,
+    location_id TEXT NOT NULL,
+    location TEXT NOT NULL,
+    since INTEGER NOT NULL,
+    until INTEGER NOT NULL,
+    liters REAL NOT NULL,
+    flow_rate REAL,
+    recorded_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS usage_device_until ON usage (device_id, until);
+CREATE INDEX IF NOT EXISTS usage_recorded_at ON usage (recorded_at);
+
+CREATE TABLE IF NOT EXISTS device_status (
+    device_id TEXT NOT NULL,
+    device_type TEXT NOT NULL,
+    location_id TEXT NOT NULL,
+    location TEXT NOT NULL,
+    product TEXT NOT NULL,
+    connected INTEGER NOT NULL,
+    oriented INTEGER,
+    battery_level TEXT,
+    recorded_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS device_status_device ON device_status (device_id, recorded_at);
+CREATE INDEX IF NOT EXISTS device_status_recorded_at ON device_status (recorded_at);
+";
+
+/// Records every usage query result and device status from the downloader in a SQLite database,
+/// deleting rows older than the retention
+pub struct History {
+    configuration: HistoryConfiguration,
+    connection: Connection,
+    last_prune: Option<Instant>,
+}
+
+impl History {
+    /// Open or create the configured database
+    pub fn new(configuration: HistoryConfiguration) -> Result<Self> {
+        let path = configuration.path();
+
+        let connection = Connection::open(&path)
+            .with_context(|| format!("Unable to open history database {}", path.display()))?;
+
+        connection
+            .execute_batch(SCHEMA)
+            .with_context(|| format!("Unable to create history tables in {}", path.display()))?;
+
+        Ok(History {
+            configuration,
+            connection,
+            last_prune: None,
+        })
+    }
+
+    pub fn start(mut self, mut updates_rx: broadcast::Receiver<Update>) {
+        info!(
+            "Recording history to {}",
+            self.configuration.path().display()
+        );
+
+        // SQLite blocks, so history is written from a blocking thread
+        tokio::task::spawn_blocking(move || loop {
+            let update = match updates_rx.blocking_recv() {
+                Ok(u) => u,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("History fell behind, skipped {} updates", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            if let Err(e) = self.record(&update) {
+                error!("Unable to record history {:#}", e);
+            }
+
+            if let Err(e) = self.prune() {
+                error!("Unable to delete old history {:#}", e);
+            }
+        });
+    }
+
+    fn record(&self, update: &Update) -> Result<()> {
+        let now = unix_now();
+
+        match update {
+            Update::Usage {
+                device_id,
+                location_id,
+                location,
+                since,
+                until,
+                liters,
+                flow_rate,
+                ..
+            } => {
+                self.connection.execute(
+                    "INSERT INTO usage \
+                     (device_id, location_id, location, since, until, liters, flow_rate, recorded_at) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        device_id,
+                        location_id,
+                        location,
+                        since.timestamp(),
+                        until.timestamp(),
+                        liters,
+                        flow_rate,
+                        now
+                    ],
+                )?;
+            }
+            Update::Bridge {
+                device_id,
+                location_id,
+                location,
+                product,
+                connected,
+            } => {
+                self.insert_status(
+                    device_id,
+                    "bridge",
+                    location_id,
+                    location,
+                    product,
+                    *connected,
+                    None,
+                    None,
+                    now,
+                )?;
+            }
+            Update::Sensor {
+                device_id,
+                location_id,
+                location,
+                product,
+                connected,
+                oriented,
+                battery_level,
+                ..
+            } => {
+                self.insert_status(
+                    device_id,
+                    "sensor",
+                    location_id,
+                    location,
+                    product,
+                    *connected,
+                    Some(*oriented),
+                    Some(battery_level),
+                    now,
+                )?;
+            }
+            Update::Periods { .. } | Update::Budget { .. } => (),
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn insert_status(
+        &self,
+        device_id: &str,
+        device_type: &str,
+        location_id: &str,
+        location: &str,
+        product: &str,
+        connected: bool,
+        oriented: Option<bool>,
+        battery_level: Option<&String>,
+        now: i64,
+    ) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO device_status \
+             (device_id, device_type, location_id, location, product, connected, oriented, \
+             battery_level, recorded_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                device_id,
+                device_type,
+                location_id,
+                location,
+                product,
+                connected,
+                oriented,
+                battery_level,
+                now
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Delete history older than the retention at most once per `PRUNE_INTERVAL`
+    fn prune(&mut self) -> Result<()> {
+        if let Some(last_prune) = self.last_prune {
+            if last_prune.elapsed() < PRUNE_INTERVAL {
+                return Ok(());
+            }
+        }
+
+        self.last_prune = Some(Instant::now());
+
+        let cutoff = unix_now() - self.configuration.retention().as_secs() as i64;
+
+        let usage = self
+            .connection
+            .execute("DELETE FROM usage WHERE recorded_at < ?1", params![cutoff])?;
+        let statuses = self.connection.execute(
+            "DELETE FROM device_status WHERE recorded_at < ?1",
+            params![cutoff],
+        )?;
+
+        if usage + statuses > 0 {
+            info!(
+                "Deleted {} usage and {} device status rows past the history retention",
+                usage, statuses
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
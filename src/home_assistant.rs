@@ -0,0 +1,118 @@
+use crate::configuration::MqttConfiguration;
+use crate::update::Update;
+
+use serde_json::json;
+use serde_json::Value;
+
+/// Home Assistant MQTT discovery messages for the entities of a device.
+///
+/// Returns no messages for usage and budget updates, devices are discovered from bridge and sensor updates.
+pub fn discovery_messages(
+    configuration: &MqttConfiguration,
+    update: &Update,
+) -> Vec<(String, String)> {
+    let prefix = configuration.discovery_prefix();
+    let device_id = update.device_id();
+    let topic = |template: String| template.replace("{device_id}", device_id);
+
+    let entities = match update {
+        Update::Bridge {
+            location, product, ..
+        } => {
+            let device = device(device_id, &format!("Flume bridge {}", location), product);
+
+            vec![(
+                "binary_sensor",
+                "connected",
+                json!({
+                    "name": "Connected",
+                    "device_class": "connectivity",
+                    "state_topic": topic(configuration.connected_topic()),
+                    "payload_on": "true",
+                    "payload_off": "false",
+                    "device": device,
+                }),
+            )]
+        }
+        Update::Sensor {
+            location, product, ..
+        } => {
+            let device = device(device_id, &format!("Flume sensor {}", location), product);
+
+            vec![
+                (
+                    "sensor",
+                    "usage",
+                    json!({
+                        "name": "Water usage",
+                        "device_class": "water",
+                        "state_class": "total_increasing",
+                        "unit_of_measurement": "L",
+                        "state_topic": topic(configuration.usage_topic()),
+                        "device": device,
+                    }),
+                ),
+                (
+                    "sensor",
+                    "flow_rate",
+                    json!({
+                        "name": "Water flow",
+                        "state_class": "measurement",
+                        "unit_of_measurement": "L/min",
+                        "icon": "mdi:water-pump",
+                        "state_topic": topic(configuration.flow_rate_topic()),
+                        "device": device,
+                    }),
+                ),
+                (
+                    "sensor",
+                    "battery",
+                    json!({
+                        "name": "Battery",
+                        "icon": "mdi:battery",
+                        "entity_category": "diagnostic",
+                        "state_topic": topic(configuration.battery_topic()),
+                        "device": device,
+                    }),
+                ),
+                (
+                    "binary_sensor",
+                    "connected",
+                    json!({
+                        "name": "Connected",
+                        "device_class": "connectivity",
+                        "entity_category": "diagnostic",
+                        "state_topic": topic(configuration.connected_topic()),
+                        "payload_on": "true",
+                        "payload_off": "false",
+                        "device": device,
+                    }),
+                ),
+            ]
+        }
+        Update::Usage { .. } | Update::Periods { .. } | Update::Budget { .. } => vec![],
+    };
+
+    entities
+        .into_iter()
+        .map(|(component, entity, mut config)| {
+            let unique_id = format!("flume_water_{}_{}", device_id, entity);
+
+            config["unique_id"] = Value::String(unique_id.clone());
+            config["object_id"] = Value::String(unique_id.clone());
+
+            let topic = format!("{}/{}/{}/config", prefix, component, unique_id);
+
+            (topic, config.to_string())
+        })
+        .collect()
+}
+
+fn device(device_id: &str, name: &str, product: &str) -> Value {
+    json!({
+        "identifiers": [format!("flume_water_{}", device_id)],
+        "name": name,
+        "manufacturer": "Flume",
+        "model": product,
+    })
+}
@@ -0,0 +1,436 @@
+//! Client for the [Flume Water API](https://flumetech.readme.io/reference) and the Prometheus
+//! exporter built on it.
+//!
+//! Use [`flume_builder::FlumeBuilder`] to authenticate and build a
+//! [`flume::Flume`], which refreshes its access token as needed and fetches devices,
+//! budgets, and usage queries.  [`client::Client`] makes the API requests, and its request
+//! and response types ([`client::Query`], [`client::Budget`], ...) are public for building
+//! queries directly.  `Flume` makes requests through the [`flume_api::FlumeApi`] trait so a
+//! mock implementation can stand in for `Client` in tests.
+//!
+//! [`run`] starts the exporter the same way the `flume_water_exporter` binary does.  The
+//! exporter's metrics are global to the process, so run at most one exporter per process.
+
+mod alerts;
+mod api;
+pub mod arguments;
+mod auth_test;
+mod away_mode;
+pub mod bridge;
+mod budget;
+pub mod client;
+pub mod configuration;
+mod dashboard;
+mod debug_state;
+pub mod device;
+pub mod device_filter;
+pub mod device_labels;
+mod downloader;
+mod export;
+mod exporter;
+pub mod flume;
+pub mod flume_api;
+pub mod flume_builder;
+mod grafana;
+mod healthcheck;
+#[cfg(feature = "sqlite")]
+mod history;
+#[cfg(feature = "mqtt")]
+mod home_assistant;
+pub mod logging;
+mod marked;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod openmetrics;
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg(feature = "otlp")]
+mod otlp_traces;
+mod password;
+#[cfg(feature = "pushgateway")]
+mod pushgateway;
+mod query;
+pub mod query_settings;
+mod rate_limiter;
+mod readings;
+pub mod relabel;
+#[cfg(feature = "remote-write")]
+mod remote_write;
+mod request_window;
+mod response_spool;
+#[cfg(feature = "runtime-metrics")]
+mod runtime_metrics;
+pub mod sensor;
+mod series;
+pub mod state;
+mod supervisor;
+#[cfg(feature = "textfile")]
+mod textfile;
+mod update;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use lazy_static::lazy_static;
+
+use alerts::Alerts;
+use arguments::Arguments;
+use arguments::Command;
+use client::Client;
+use configuration::Configuration;
+use downloader::Downloader;
+use exporter::Exporter;
+#[cfg(feature = "sqlite")]
+use history::History;
+#[cfg(feature = "mqtt")]
+use mqtt::Mqtt;
+#[cfg(feature = "otlp")]
+use otlp::Otlp;
+#[cfg(feature = "pushgateway")]
+use pushgateway::Pushgateway;
+use readings::Readings;
+#[cfg(feature = "remote-write")]
+use remote_write::RemoteWrite;
+use state::State;
+use supervisor::Supervisor;
+#[cfg(feature = "textfile")]
+use textfile::Textfile;
+
+#[cfg(target_os = "linux")]
+use prometheus::process_collector::ProcessCollector;
+use prometheus::Gauge;
+use prometheus::GaugeVec;
+use prometheus::Opts;
+use prometheus::Registry;
+
+use tokio::sync::mpsc;
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+lazy_static! {
+    static ref START_TIME: Gauge = Gauge::new(
+        "process_start_time_seconds",
+        "Start time of the process since unix epoch in seconds.",
+    )
+    .unwrap();
+    static ref BUILD_INFO: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "flume_water_exporter_build_info",
+            "Version of the exporter and the commit and rust compiler used to build it",
+        ),
+        &["version", "commit", "rustc"],
+    )
+    .unwrap();
+}
+
+/// Registry with the exporter's metrics.  The exporter doesn't use the default registry so its
+/// metrics don't mix with those of an application that has its own.  The metrics themselves
+/// are process globals shared by every registry built here.
+fn registry() -> Result<Registry> {
+    let registry = Registry::new();
+
+    // On Linux the process collector exports the start time along with CPU, memory, and file
+    // descriptor usage
+    #[cfg(target_os = "linux")]
+    registry.register(Box::new(ProcessCollector::for_self()))?;
+    #[cfg(not(target_os = "linux"))]
+    registry.register(Box::new(START_TIME.clone()))?;
+    registry.register(Box::new(BUILD_INFO.clone()))?;
+    client::register(&registry)?;
+    rate_limiter::register(&registry)?;
+    downloader::register(&registry)?;
+    request_window::register(&registry)?;
+
+    #[cfg(feature = "runtime-metrics")]
+    registry.register(Box::new(runtime_metrics::RuntimeCollector::new(
+        tokio::runtime::Handle::current().metrics(),
+    )))?;
+
+    Ok(registry)
+}
+
+/// Build the tokio runtime selected by `runtime` and `worker_threads` in `configuration`
+pub fn runtime(configuration: &Configuration) -> Result<tokio::runtime::Runtime> {
+    let mut builder = match configuration.runtime() {
+        configuration::Runtime::CurrentThread => tokio::runtime::Builder::new_current_thread(),
+        configuration::Runtime::MultiThread => {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+
+            match configuration.worker_threads() {
+                Some(0) => return Err(anyhow!("worker_threads must be at least 1")),
+                Some(worker_threads) => {
+                    builder.worker_threads(worker_threads);
+                }
+                None => (),
+            }
+
+            builder
+        }
+    };
+
+    builder
+        .enable_all()
+        .build()
+        .context("Unable to start the tokio runtime")
+}
+
+/// Run the command given in `arguments`, returning the process exit code.
+///
+/// The exporter runs until a fatal error occurs.  Logging is left to the caller, see
+/// `logging::init`.
+pub async fn run(arguments: Arguments) -> Result<i32> {
+    let start_time = SystemTime::now().duration_since(UNIX_EPOCH).ok();
+
+    let mut configuration = Configuration::load_from_arguments(&arguments)?;
+
+    configuration.check_environment()?;
+
+    // Only commands that talk to Flume need the password
+    if !matches!(
+        arguments.command,
+        Command::Healthcheck | Command::GrafanaDashboard
+    ) {
+        configuration.read_password()?;
+    }
+
+    if let Some(buckets) = configuration.duration_buckets() {
+        client::set_duration_buckets(buckets)?;
+    }
+
+    if configuration.requests_per_hour() < 2 {
+        return Err(anyhow!("requests_per_hour must be at least 2"));
+    }
+
+    if configuration.current_minute_interval() == Some(std::time::Duration::ZERO) {
+        return Err(anyhow!("current_minute_interval must be at least 1"));
+    }
+
+    let device_labels = configuration.device_labels()?;
+    downloader::set_device_label_names(device_labels.names())?;
+
+    // After the settings above, registering creates the metrics
+    let registry = registry()?;
+
+    match arguments.command {
+        Command::Run => (),
+        Command::Healthcheck => {
+            healthcheck::check(&configuration).await?;
+
+            println!("ok");
+        }
+        Command::AuthTest => auth_test::run(&configuration).await?,
+        Command::Query => query::run(&configuration, &arguments.query).await?,
+        Command::Export => export::run(&configuration, &arguments.query).await?,
+        Command::GrafanaDashboard => grafana::run(&configuration, arguments.units.as_deref())?,
+        Command::AwayMode => away_mode::run(&configuration, &arguments).await?,
+        Command::Budget => budget::run(&configuration, &arguments.budget).await?,
+    }
+
+    if arguments.command != Command::Run {
+        return Ok(0);
+    }
+
+    configuration.check_features()?;
+
+    let restored_state = restored_state(&arguments, &configuration)?;
+
+    let (error_tx, error_rx) = mpsc::channel(1);
+
+    let client = Client::new(&configuration);
+
+    let flume = supervisor::authenticate(
+        &mut configuration,
+        &client,
+        restored_state.as_ref().and_then(|s| s.token.clone()),
+    )
+    .await?;
+
+    let mut downloader = Downloader::new(
+        flume,
+        configuration.budget_interval(),
+        configuration.device_interval(),
+        configuration.period_interval(),
+        configuration.query_interval(),
+        configuration.query_jitter(),
+        error_tx.clone(),
+    )
+    .query_lag(configuration.query_lag())
+    .query_settings(configuration.query_settings())
+    .flow_metrics(configuration.flow_metrics())
+    .current_minute_interval(configuration.current_minute_interval())
+    .adaptive_polling(configuration.adaptive_polling())
+    .filter_devices(configuration.device_filter())
+    .device_labels(device_labels)
+    .relabel(configuration.relabel()?)
+    .persist_to(configuration.state_file());
+
+    if let Some(state) = restored_state {
+        downloader = downloader.restore(state);
+    }
+
+    let state_rx = downloader.state();
+    let supervisor = Supervisor::new(
+        configuration.clone(),
+        client,
+        error_rx,
+        downloader.restart_on(),
+        state_rx.clone(),
+    );
+
+    // Without the server nothing scrapes, so poll on the query interval
+    let scrape_tx = if configuration.poll_on_scrape() && configuration.http_server() {
+        Some(downloader.poll_on_scrape())
+    } else {
+        None
+    };
+
+    // Away mode can only be changed through the control endpoints
+    let away_mode_tx = if configuration.control_token().is_some() && configuration.http_server() {
+        Some(downloader.control_away_mode())
+    } else {
+        None
+    };
+
+    if let Some(alerts) = configuration.alerts() {
+        Alerts::new(alerts).start(downloader.updates()).await;
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = configuration.mqtt() {
+        Mqtt::new(mqtt).start(downloader.updates());
+    }
+
+    #[cfg(feature = "remote-write")]
+    if let Some(remote_write) = configuration.remote_write() {
+        RemoteWrite::new(remote_write, registry.clone())
+            .start(downloader.state())
+            .await;
+    }
+
+    #[cfg(feature = "otlp")]
+    if let Some(otlp) = configuration.otlp() {
+        Otlp::new(otlp, registry.clone())
+            .start(downloader.state())
+            .await;
+    }
+
+    #[cfg(feature = "textfile")]
+    if let Some(textfile) = configuration.textfile() {
+        Textfile::new(textfile, registry.clone())
+            .start(downloader.state())
+            .await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(history) = configuration.history() {
+        History::new(history)?.start(downloader.updates());
+    }
+
+    #[cfg(feature = "pushgateway")]
+    if let Some(pushgateway) = configuration.pushgateway() {
+        Pushgateway::new(pushgateway, registry.clone())
+            .start(downloader.state())
+            .await?;
+    }
+
+    let readings = if configuration.http_server() {
+        let readings = Readings::default();
+        readings.start(downloader.updates());
+
+        Some(readings)
+    } else {
+        None
+    };
+
+    let events_rx = downloader.updates();
+    let debug_rx = downloader.debug_state();
+
+    #[cfg(unix)]
+    debug_state::dump_on_signal(downloader.debug_state());
+
+    downloader.start().await;
+
+    if configuration.http_server() {
+        let mut exporter = Exporter::new(
+            configuration.bind_addresses(),
+            configuration.control_token(),
+            state_rx,
+            registry,
+        )?;
+
+        if let Some(scrape_tx) = scrape_tx {
+            exporter = exporter.update_on_scrape(scrape_tx);
+        }
+
+        if let Some(readings) = readings {
+            exporter = exporter
+                .readings(readings)
+                .dashboard(configuration.dashboard());
+        }
+
+        if let Some(away_mode_tx) = away_mode_tx {
+            exporter = exporter.away_mode(away_mode_tx);
+        }
+
+        exporter = exporter.events(events_rx).debug_state(debug_rx);
+
+        exporter.start(error_tx.clone()).await;
+    }
+
+    BUILD_INFO
+        .with_label_values(&[
+            env!("CARGO_PKG_VERSION"),
+            env!("BUILD_COMMIT"),
+            env!("BUILD_RUSTC"),
+        ])
+        .set(1.0);
+
+    if let Some(duration) = start_time {
+        START_TIME.set(duration.as_secs_f64());
+    }
+
+    Ok(supervisor.run().await)
+}
+
+/// Process exit code for `error`, distinct for each credential problem Flume reports so
+/// deployment tooling can tell them apart, otherwise 1
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    marked::markers::<client::CredentialError>(error)
+        .next()
+        .map_or(1, client::CredentialError::exit_code)
+}
+
+/// Load the state given with `--restore-state`, falling back to the configured state file if it
+/// exists
+fn restored_state(arguments: &Arguments, configuration: &Configuration) -> Result<Option<State>> {
+    let file = match (&arguments.restore_state, configuration.state_file()) {
+        (Some(file), _) => std::path::PathBuf::from(file),
+        (None, Some(file)) if file.exists() => file,
+        _ => return Ok(None),
+    };
+
+    let state = State::load(&file)
+        .with_context(|| format!("Unable to restore state from {}", file.display()))?;
+
+    Ok(Some(state))
+}
+
+#[track_caller]
+pub(crate) fn spawn_named<T>(
+    task: impl std::future::Future<Output = T> + Send + 'static,
+    _name: &str,
+) -> tokio::task::JoinHandle<T>
+where
+    T: Send + 'static,
+{
+    #[cfg(tokio_unstable)]
+    return tokio::task::Builder::new()
+        .name(_name)
+        .spawn(task)
+        .expect("Unable to spawn task, bug?");
+
+    #[cfg(not(tokio_unstable))]
+    tokio::spawn(task)
+}
@@ -0,0 +1,208 @@
+use crate::configuration::Configuration;
+
+use chrono::offset::Utc;
+use chrono::SecondsFormat;
+
+use serde::Deserialize;
+
+use serde_json::Map;
+use serde_json::Value;
+
+use std::fmt;
+
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::Event;
+use tracing::Subscriber;
+
+use tracing_subscriber::fmt::format::JsonFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::FormatEvent;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::fmt::FormattedFields;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Start logging in the configured format.  The level is set by `RUST_LOG` and defaults to
+/// info.  Call this once before `crate::run`, programs embedding the exporter can install their
+/// own subscriber instead.
+///
+/// With the `otlp` feature spans are exported to `otlp.traces_endpoint` when it is set.  With
+/// the `console` feature task data is also served to tokio-console.
+pub fn init(configuration: &Configuration) {
+    let format = configuration.log_format();
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    // tokio-console needs the runtime's own trace events
+    #[cfg(feature = "console")]
+    let filter = filter
+        .add_directive("tokio=trace".parse().unwrap())
+        .add_directive("runtime=trace".parse().unwrap());
+
+    let text = (format == LogFormat::Text)
+        .then(|| tracing_subscriber::fmt::layer().with_writer(std::io::stderr));
+
+    let json = (format == LogFormat::Json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonLines)
+    });
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json);
+
+    #[cfg(feature = "otlp")]
+    let registry = registry.with(configuration.otlp().and_then(|otlp| {
+        let endpoint = otlp.traces_endpoint()?;
+
+        Some(crate::otlp_traces::OtlpTraces::new(otlp, endpoint))
+    }));
+
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+
+    registry.init();
+}
+
+/// JSON lines with the timestamp, level, target, and message, followed by the fields of the
+/// event and the spans it is in, such as `request_name` and `device_id`
+struct JsonLines;
+
+impl<S, N> FormatEvent<S, N> for JsonLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut line = Map::new();
+
+        line.insert(
+            "timestamp".to_string(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+                .into(),
+        );
+        line.insert(
+            "level".to_string(),
+            event.metadata().level().as_str().into(),
+        );
+        line.insert("target".to_string(), event.metadata().target().into());
+
+        // Inner spans are added last so their fields replace those of outer spans
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+
+                let fields = extensions
+                    .get::<FormattedFields<N>>()
+                    .and_then(|fields| serde_json::from_str::<Map<String, Value>>(fields).ok());
+
+                line.extend(fields.into_iter().flatten());
+            }
+        }
+
+        event.record(&mut FieldMap(&mut line));
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+/// Records tracing fields into a JSON object
+pub(crate) struct FieldMap<'a>(pub &'a mut Map<String, Value>);
+
+impl Visit for FieldMap<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[test]
+    fn json_lines_span_fields() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = output.clone();
+
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(move || Buffer(writer.clone()))
+                .fmt_fields(JsonFields::new())
+                .event_format(JsonLines),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let cycle = tracing::info_span!("cycle", device_id = "outer");
+            let _cycle = cycle.enter();
+            let request = tracing::info_span!("get", request_name = "usage", device_id = "123");
+            let _request = request.enter();
+
+            tracing::info!(status = 200, "done");
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.trim()).unwrap();
+
+        assert_eq!("INFO", line["level"]);
+        assert_eq!("done", line["message"]);
+        assert_eq!("usage", line["request_name"]);
+        assert_eq!("123", line["device_id"]);
+        assert_eq!(200, line["status"]);
+        assert!(line.get("span").is_none());
+    }
+
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+
+/// An error marked with `marker`, such as an `ErrorKind`, that the cause chain can be searched
+/// for.
+///
+/// Values attached with `anyhow::Context` don't appear as their own type in `Error::chain`, and
+/// `Error::downcast_ref` only finds the outermost one, so a budget error caused by a rejected
+/// token refresh would hide the authentication failure.  A marked error is a link in the chain.
+#[derive(Debug)]
+pub struct Marked<M> {
+    pub marker: M,
+    source: anyhow::Error,
+}
+
+impl<M: fmt::Display> fmt::Display for Marked<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.marker.fmt(f)
+    }
+}
+
+impl<M: fmt::Debug + fmt::Display> std::error::Error for Marked<M> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// `error` marked with `marker`, displayed like `error.context(marker)`
+pub fn mark<M>(error: anyhow::Error, marker: M) -> anyhow::Error
+where
+    M: fmt::Debug + fmt::Display + Send + Sync + 'static,
+{
+    anyhow::Error::new(Marked {
+        marker,
+        source: error,
+    })
+}
+
+/// Markers of type `M` on `error` and its causes, outermost first
+pub fn markers<M: fmt::Debug + fmt::Display + 'static>(
+    error: &anyhow::Error,
+) -> impl Iterator<Item = &M> {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<Marked<M>>())
+        .map(|marked| &marked.marker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers_nested() {
+        let error = mark(anyhow::anyhow!("timed out"), "inner");
+        let error = mark(error.context("request 1"), "outer");
+
+        let found: Vec<&&str> = markers::<&str>(&error).collect();
+
+        assert_eq!(vec![&"outer", &"inner"], found);
+        assert_eq!("outer: request 1: inner: timed out", format!("{:#}", error));
+    }
+}
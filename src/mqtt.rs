@@ -0,0 +1,167 @@
+use crate::configuration::MqttConfiguration;
+use crate::home_assistant;
+use crate::update::Update;
+
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+use rumqttc::AsyncClient;
+use rumqttc::MqttOptions;
+use rumqttc::QoS;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Publishes downloader updates to an MQTT broker
+pub struct Mqtt {
+    configuration: MqttConfiguration,
+}
+
+impl Mqtt {
+    pub fn new(configuration: MqttConfiguration) -> Self {
+        Mqtt { configuration }
+    }
+
+    /// Connect to the broker and publish each update from `updates_rx`
+    pub fn start(self, updates_rx: broadcast::Receiver<Update>) {
+        let configuration = &self.configuration;
+
+        let mut options = MqttOptions::new(
+            configuration.client_id(),
+            configuration.host(),
+            configuration.port(),
+        );
+        options.set_keep_alive(Duration::from_secs(30));
+
+        if let Some((username, password)) = configuration.credentials() {
+            options.set_credentials(username, password);
+        }
+
+        info!(
+            "Publishing to MQTT broker {}:{}",
+            configuration.host(),
+            configuration.port()
+        );
+
+        let (client, mut event_loop) = AsyncClient::new(options, 64);
+
+        crate::spawn_named(
+            async move {
+                loop {
+                    if let Err(e) = event_loop.poll().await {
+                        error!("MQTT connection error {}", e);
+
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            },
+            "mqtt_event_loop",
+        );
+
+        crate::spawn_named(
+            async move {
+                self.publish_updates(client, updates_rx).await;
+            },
+            "mqtt_publisher",
+        );
+    }
+
+    async fn publish_updates(
+        &self,
+        client: AsyncClient,
+        mut updates_rx: broadcast::Receiver<Update>,
+    ) {
+        let mut discovered = HashSet::new();
+
+        loop {
+            let update = match updates_rx.recv().await {
+                Ok(u) => u,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("MQTT publishing fell behind, skipped {} updates", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            if self.configuration.home_assistant_discovery()
+                && !matches!(
+                    update,
+                    Update::Usage { .. } | Update::Periods { .. } | Update::Budget { .. }
+                )
+                && discovered.insert(update.device_id().to_string())
+            {
+                // Discovery messages are always retained so Home Assistant finds devices after it
+                // restarts
+                for (topic, payload) in
+                    home_assistant::discovery_messages(&self.configuration, &update)
+                {
+                    publish(&client, topic, true, payload).await;
+                }
+            }
+
+            for (topic, payload) in self.messages(&update) {
+                publish(&client, topic, self.configuration.retain(), payload).await;
+            }
+        }
+    }
+
+    /// Topics and payloads for `update`
+    fn messages(&self, update: &Update) -> Vec<(String, String)> {
+        let configuration = &self.configuration;
+        let topic = |template: String| template.replace("{device_id}", update.device_id());
+
+        match update {
+            Update::Bridge { connected, .. } => {
+                vec![(
+                    topic(configuration.connected_topic()),
+                    connected.to_string(),
+                )]
+            }
+            Update::Sensor {
+                connected,
+                battery_level,
+                ..
+            } => vec![
+                (
+                    topic(configuration.connected_topic()),
+                    connected.to_string(),
+                ),
+                (topic(configuration.battery_topic()), battery_level.clone()),
+            ],
+            Update::Usage {
+                total_liters,
+                flow_rate,
+                ..
+            } => {
+                let mut messages =
+                    vec![(topic(configuration.usage_topic()), total_liters.to_string())];
+
+                if let Some(flow_rate) = flow_rate {
+                    messages.push((
+                        topic(configuration.flow_rate_topic()),
+                        flow_rate.to_string(),
+                    ));
+                }
+
+                messages
+            }
+            Update::Periods { .. } | Update::Budget { .. } => vec![],
+        }
+    }
+}
+
+async fn publish(client: &AsyncClient, topic: String, retain: bool, payload: String) {
+    debug!("MQTT publish {} {}", topic, payload);
+
+    if let Err(e) = client
+        .publish(topic, QoS::AtLeastOnce, retain, payload)
+        .await
+    {
+        error!("MQTT publish failed {}", e);
+    }
+}
@@ -0,0 +1,334 @@
+use prometheus::proto::LabelPair;
+use prometheus::proto::Metric;
+use prometheus::proto::MetricFamily;
+use prometheus::proto::MetricType;
+
+use std::fmt::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+static EXEMPLARS: AtomicBool = AtomicBool::new(false);
+
+/// Exemplars are recorded from now on, so scrapers that accept OpenMetrics get it
+#[cfg(feature = "otlp")]
+pub fn enable_exemplars() {
+    EXEMPLARS.store(true, Ordering::Relaxed);
+}
+
+pub fn exemplars_enabled() -> bool {
+    EXEMPLARS.load(Ordering::Relaxed)
+}
+
+/// A sample linking a histogram bucket to the trace of a request it counted
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exemplar {
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+    /// Seconds since the unix epoch
+    pub timestamp: f64,
+}
+
+/// Whether the `Accept` header value `accept` allows the OpenMetrics text format
+pub fn accepted(accept: &str) -> bool {
+    accept.split(',').any(|media_type| {
+        media_type
+            .trim()
+            .starts_with("application/openmetrics-text")
+    })
+}
+
+/// Encode `metric_families` in the OpenMetrics text format.  `exemplar` gives the exemplar for
+/// a histogram bucket by its index, the `+Inf` bucket is last.
+///
+/// Counters without a `_total` suffix are encoded as `unknown` so their samples keep the names
+/// they have in the Prometheus text format.
+pub fn encode<F>(metric_families: &[MetricFamily], exemplar: F) -> String
+where
+    F: Fn(&MetricFamily, &Metric, usize) -> Option<Exemplar>,
+{
+    let mut output = String::new();
+
+    for family in metric_families {
+        let name = family.get_name();
+
+        let (name, metric_type) = match family.get_field_type() {
+            MetricType::COUNTER => match name.strip_suffix("_total") {
+                Some(name) => (name, "counter"),
+                None => (name, "unknown"),
+            },
+            MetricType::GAUGE => (name, "gauge"),
+            MetricType::HISTOGRAM => (name, "histogram"),
+            MetricType::SUMMARY => (name, "summary"),
+            MetricType::UNTYPED => (name, "unknown"),
+        };
+
+        let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+        let _ = writeln!(output, "# HELP {} {}", name, escape(family.get_help()));
+
+        for metric in family.get_metric() {
+            match family.get_field_type() {
+                MetricType::COUNTER => sample(
+                    &mut output,
+                    family.get_name(),
+                    metric,
+                    None,
+                    metric.get_counter().get_value(),
+                    None,
+                ),
+                MetricType::GAUGE => sample(
+                    &mut output,
+                    name,
+                    metric,
+                    None,
+                    metric.get_gauge().get_value(),
+                    None,
+                ),
+                MetricType::UNTYPED => sample(
+                    &mut output,
+                    name,
+                    metric,
+                    None,
+                    metric.get_untyped().get_value(),
+                    None,
+                ),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let bucket_name = format!("{}_bucket", name);
+                    let buckets = histogram.get_bucket();
+
+                    for (i, bucket) in buckets.iter().enumerate() {
+                        let upper_bound = bucket.get_upper_bound();
+
+                        // The +Inf bucket is added below when the histogram doesn't have one
+                        if upper_bound.is_infinite() && upper_bound.is_sign_positive() {
+                            continue;
+                        }
+
+                        sample(
+                            &mut output,
+                            &bucket_name,
+                            metric,
+                            Some(("le", &upper_bound.to_string())),
+                            bucket.get_cumulative_count() as f64,
+                            exemplar(family, metric, i),
+                        );
+                    }
+
+                    let count = histogram.get_sample_count() as f64;
+
+                    sample(
+                        &mut output,
+                        &bucket_name,
+                        metric,
+                        Some(("le", "+Inf")),
+                        count,
+                        exemplar(family, metric, buckets.len()),
+                    );
+
+                    sample(
+                        &mut output,
+                        &format!("{}_sum", name),
+                        metric,
+                        None,
+                        histogram.get_sample_sum(),
+                        None,
+                    );
+                    sample(
+                        &mut output,
+                        &format!("{}_count", name),
+                        metric,
+                        None,
+                        count,
+                        None,
+                    );
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+
+                    for quantile in summary.get_quantile() {
+                        sample(
+                            &mut output,
+                            name,
+                            metric,
+                            Some(("quantile", &quantile.get_quantile().to_string())),
+                            quantile.get_value(),
+                            None,
+                        );
+                    }
+
+                    sample(
+                        &mut output,
+                        &format!("{}_sum", name),
+                        metric,
+                        None,
+                        summary.get_sample_sum(),
+                        None,
+                    );
+                    sample(
+                        &mut output,
+                        &format!("{}_count", name),
+                        metric,
+                        None,
+                        summary.get_sample_count() as f64,
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    output.push_str("# EOF\n");
+
+    output
+}
+
+fn sample(
+    output: &mut String,
+    name: &str,
+    metric: &Metric,
+    additional_label: Option<(&str, &str)>,
+    value: f64,
+    exemplar: Option<Exemplar>,
+) {
+    output.push_str(name);
+    labels(output, metric.get_label(), additional_label);
+    output.push(' ');
+    output.push_str(&float(value));
+
+    let timestamp = metric.get_timestamp_ms();
+    if timestamp != 0 {
+        let _ = write!(output, " {}", timestamp as f64 / 1000.0);
+    }
+
+    if let Some(exemplar) = exemplar {
+        let labels: Vec<String> = exemplar
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect();
+
+        let _ = write!(
+            output,
+            " # {{{}}} {} {}",
+            labels.join(","),
+            float(exemplar.value),
+            exemplar.timestamp
+        );
+    }
+
+    output.push('\n');
+}
+
+fn labels(output: &mut String, pairs: &[LabelPair], additional_label: Option<(&str, &str)>) {
+    let pairs = pairs
+        .iter()
+        .map(|pair| (pair.get_name(), pair.get_value()))
+        .chain(additional_label);
+
+    let mut separator = '{';
+    for (name, value) in pairs {
+        output.push(separator);
+        let _ = write!(output, "{}=\"{}\"", name, escape(value));
+
+        separator = ',';
+    }
+
+    if separator == ',' {
+        output.push('}');
+    }
+}
+
+fn float(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value.is_sign_positive() {
+            "+Inf"
+        } else {
+            "-Inf"
+        }
+        .to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escape a label value or help text
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use prometheus::Histogram;
+    use prometheus::HistogramOpts;
+    use prometheus::IntCounter;
+    use prometheus::Registry;
+
+    #[test]
+    fn encode_exemplar() {
+        let registry = Registry::new();
+
+        let requests = IntCounter::new("requests_total", "Requests").unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        requests.inc();
+
+        let durations = Histogram::with_opts(
+            HistogramOpts::new("duration_seconds", "Durations \"quoted\"").buckets(vec![0.5, 1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(durations.clone())).unwrap();
+        durations.observe(0.75);
+
+        let output = encode(&registry.gather(), |family, _, bucket| {
+            (family.get_name() == "duration_seconds" && bucket == 1).then(|| Exemplar {
+                labels: vec![("trace_id", "0af7651916cd43dd8448eb211c80319c".to_string())],
+                value: 0.75,
+                timestamp: 1700000000.5,
+            })
+        });
+
+        assert_eq!(
+            "# TYPE duration_seconds histogram\n\
+             # HELP duration_seconds Durations \\\"quoted\\\"\n\
+             duration_seconds_bucket{le=\"0.5\"} 0\n\
+             duration_seconds_bucket{le=\"1\"} 1 # {trace_id=\"0af7651916cd43dd8448eb211c80319c\"} 0.75 1700000000.5\n\
+             duration_seconds_bucket{le=\"+Inf\"} 1\n\
+             duration_seconds_sum 0.75\n\
+             duration_seconds_count 1\n\
+             # TYPE requests counter\n\
+             # HELP requests Requests\n\
+             requests_total 1\n\
+             # EOF\n",
+            output
+        );
+    }
+
+    #[test]
+    fn encode_counter_without_total() {
+        let registry = Registry::new();
+
+        let usage = IntCounter::new("usage_liters", "Usage").unwrap();
+        registry.register(Box::new(usage.clone())).unwrap();
+
+        let output = encode(&registry.gather(), |_, _, _| None);
+
+        assert!(output.contains("# TYPE usage_liters unknown\n"));
+        assert!(output.contains("\nusage_liters 0\n"));
+    }
+
+    #[test]
+    fn accepted_openmetrics() {
+        assert!(accepted(
+            "application/openmetrics-text;version=1.0.0,application/openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1"
+        ));
+        assert!(!accepted("text/plain;version=0.0.4"));
+    }
+}
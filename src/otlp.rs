@@ -0,0 +1,218 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::configuration::OtlpConfiguration;
+use crate::state::State;
+
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+
+use prometheus::proto::Metric;
+use prometheus::proto::MetricFamily;
+use prometheus::proto::MetricType;
+use prometheus::Registry;
+
+use serde_json::json;
+use serde_json::Value;
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use tokio::sync::watch;
+
+/// OTLP aggregation temporality for values accumulated since the exporter started
+const CUMULATIVE: u64 = 2;
+
+/// Exports the metrics to an OpenTelemetry collector with OTLP/HTTP JSON after each downloader
+/// cycle
+pub struct Otlp {
+    registry: Registry,
+    configuration: OtlpConfiguration,
+    client: reqwest::Client,
+    start_time: u128,
+}
+
+impl Otlp {
+    pub fn new(configuration: OtlpConfiguration, registry: Registry) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(configuration.timeout())
+            .build()
+            .expect("Could not build HTTP client");
+
+        Otlp {
+            registry,
+            configuration,
+            client,
+            start_time: unix_nanos(),
+        }
+    }
+
+    pub async fn start(self, mut state_rx: watch::Receiver<State>) {
+        info!("Exporting metrics to {}", self.configuration.endpoint());
+
+        crate::spawn_named(
+            async move {
+                while state_rx.changed().await.is_ok() {
+                    if let Err(e) = self.export().await {
+                        error!("{:#}", e);
+                    }
+                }
+            },
+            "otlp",
+        );
+    }
+
+    async fn export(&self) -> Result<()> {
+        let endpoint = self.configuration.endpoint();
+        let body = self.request(&self.registry.gather(), unix_nanos());
+
+        debug!("Exporting metrics to {}", endpoint);
+
+        let mut builder = self
+            .client
+            .post(&endpoint)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+
+        for (name, value) in self.configuration.headers() {
+            builder = builder.header(name, value);
+        }
+
+        let response = builder
+            .send()
+            .await
+            .with_context(|| format!("exporting metrics to {}", endpoint))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "exporting metrics to {} failed with {}",
+                endpoint,
+                response.status()
+            ))
+        }
+    }
+
+    /// OTLP ExportMetricsServiceRequest for `metric_families`
+    fn request(&self, metric_families: &[MetricFamily], time: u128) -> Value {
+        let metrics: Vec<Value> = metric_families
+            .iter()
+            .filter_map(|family| self.metric(family, time))
+            .collect();
+
+        json!({
+            "resourceMetrics": [{
+                "resource": {
+                    "attributes": [attribute("service.name", "flume_water_exporter")],
+                },
+                "scopeMetrics": [{
+                    "scope": {
+                        "name": "flume_water_exporter",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                    "metrics": metrics,
+                }],
+            }],
+        })
+    }
+
+    fn metric(&self, family: &MetricFamily, time: u128) -> Option<Value> {
+        let points = family.get_metric().iter();
+        let time = time.to_string();
+        let start_time = self.start_time.to_string();
+
+        let data = match family.get_field_type() {
+            MetricType::COUNTER => json!({
+                "sum": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "isMonotonic": true,
+                    "dataPoints": points.map(|m| json!({
+                        "attributes": attributes(m),
+                        "startTimeUnixNano": start_time,
+                        "timeUnixNano": time,
+                        "asDouble": m.get_counter().get_value(),
+                    })).collect::<Vec<Value>>(),
+                },
+            }),
+            MetricType::GAUGE => json!({
+                "gauge": {
+                    "dataPoints": points.map(|m| json!({
+                        "attributes": attributes(m),
+                        "timeUnixNano": time,
+                        "asDouble": m.get_gauge().get_value(),
+                    })).collect::<Vec<Value>>(),
+                },
+            }),
+            MetricType::HISTOGRAM => json!({
+                "histogram": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "dataPoints": points.map(|m| {
+                        let histogram = m.get_histogram();
+                        let buckets = histogram.get_bucket();
+
+                        // Prometheus buckets are cumulative, OTLP bucket counts are not
+                        let mut previous = 0;
+                        let mut bucket_counts: Vec<String> = buckets
+                            .iter()
+                            .map(|b| {
+                                let count = b.get_cumulative_count() - previous;
+                                previous = b.get_cumulative_count();
+
+                                count.to_string()
+                            })
+                            .collect();
+                        bucket_counts.push((histogram.get_sample_count() - previous).to_string());
+
+                        json!({
+                            "attributes": attributes(m),
+                            "startTimeUnixNano": start_time,
+                            "timeUnixNano": time,
+                            "count": histogram.get_sample_count().to_string(),
+                            "sum": histogram.get_sample_sum(),
+                            "bucketCounts": bucket_counts,
+                            "explicitBounds": buckets
+                                .iter()
+                                .map(|b| b.get_upper_bound())
+                                .collect::<Vec<f64>>(),
+                        })
+                    }).collect::<Vec<Value>>(),
+                },
+            }),
+            // The exporter doesn't register summaries or untyped metrics
+            MetricType::SUMMARY | MetricType::UNTYPED => return None,
+        };
+
+        let mut metric = json!({
+            "name": family.get_name(),
+            "description": family.get_help(),
+        });
+
+        if let (Some(metric), Some(data)) = (metric.as_object_mut(), data.as_object()) {
+            metric.extend(data.clone());
+        }
+
+        Some(metric)
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn attributes(metric: &Metric) -> Vec<Value> {
+    metric
+        .get_label()
+        .iter()
+        .map(|l| attribute(l.get_name(), l.get_value()))
+        .collect()
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
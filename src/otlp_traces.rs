@@ -0,0 +1,305 @@
+use crate::configuration::OtlpConfiguration;
+use crate::logging::FieldMap;
+
+use rand::Rng;
+
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use tracing::error;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::span::Record;
+use tracing::Subscriber;
+
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+use tracing_subscriber::Registry;
+
+/// Finished spans are sent to the collector in batches of up to this many
+const BATCH_SIZE: usize = 512;
+
+/// Finished spans wait at most this long to be sent
+const BATCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Spans finished while the collector is slow are dropped beyond this many
+const QUEUE_SIZE: usize = 4 * BATCH_SIZE;
+
+/// OTLP span kind for work inside the exporter
+const SPAN_KIND_INTERNAL: u64 = 1;
+
+/// OTLP trace context of a span and the data sent to the collector when it closes
+struct TraceContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    start_time: u128,
+    attributes: Map<String, Value>,
+}
+
+/// Exports the exporter's spans, such as each downloader cycle and API request, to an
+/// OpenTelemetry collector with OTLP/HTTP JSON.  Spans are sent from a background thread so a
+/// slow collector never holds up the exporter.
+pub struct OtlpTraces {
+    spans_tx: mpsc::SyncSender<Value>,
+}
+
+impl OtlpTraces {
+    pub fn new(configuration: OtlpConfiguration, endpoint: String) -> Self {
+        let (spans_tx, spans_rx) = mpsc::sync_channel(QUEUE_SIZE);
+
+        // Trace ids of the exported spans link request durations to their traces
+        crate::openmetrics::enable_exemplars();
+
+        std::thread::Builder::new()
+            .name("otlp_traces".to_string())
+            .spawn(move || export(configuration, endpoint, spans_rx))
+            .expect("Unable to start the OTLP trace exporter");
+
+        OtlpTraces { spans_tx }
+    }
+}
+
+impl<S> Layer<S> for OtlpTraces
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        // The HTTP client's own spans would be exported while exporting
+        if !attrs
+            .metadata()
+            .target()
+            .starts_with(env!("CARGO_CRATE_NAME"))
+        {
+            return;
+        }
+
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+
+        let parent = span.scope().skip(1).find_map(|parent| {
+            parent
+                .extensions()
+                .get::<TraceContext>()
+                .map(|context| (context.trace_id, context.span_id))
+        });
+
+        let mut attributes = Map::new();
+        attrs.record(&mut FieldMap(&mut attributes));
+
+        let context = TraceContext {
+            trace_id: parent.map_or_else(new_id, |(trace_id, _)| trace_id),
+            span_id: new_id(),
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+            start_time: unix_nanos(),
+            attributes,
+        };
+
+        span.extensions_mut().insert(context);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(context) = span.extensions_mut().get_mut::<TraceContext>() {
+                values.record(&mut FieldMap(&mut context.attributes));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+
+        let extensions = span.extensions();
+
+        let context = match extensions.get::<TraceContext>() {
+            Some(context) => context,
+            None => return,
+        };
+
+        let attributes: Vec<Value> = context
+            .attributes
+            .iter()
+            .map(|(key, value)| attribute(key, value))
+            .collect();
+
+        let mut otlp_span = json!({
+            "traceId": hex(&context.trace_id),
+            "spanId": hex(&context.span_id),
+            "name": span.name(),
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": context.start_time.to_string(),
+            "endTimeUnixNano": unix_nanos().to_string(),
+            "attributes": attributes,
+        });
+
+        if let Some(parent_span_id) = context.parent_span_id {
+            otlp_span["parentSpanId"] = hex(&parent_span_id).into();
+        }
+
+        // Spans are dropped rather than blocking the exporter when the queue is full
+        let _ = self.spans_tx.try_send(otlp_span);
+    }
+}
+
+/// Trace and span id of the current span as hex, if it is exported
+pub fn current_trace_context() -> Option<(String, String)> {
+    tracing::Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let registry = dispatch.downcast_ref::<Registry>()?;
+
+            registry.span(id)?.scope().find_map(|span| {
+                span.extensions()
+                    .get::<TraceContext>()
+                    .map(|context| (hex(&context.trace_id), hex(&context.span_id)))
+            })
+        })
+        .flatten()
+}
+
+/// Send batches of finished spans to `endpoint` until the layer is dropped
+fn export(configuration: OtlpConfiguration, endpoint: String, spans_rx: mpsc::Receiver<Value>) {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(configuration.timeout())
+        .build()
+        .expect("Could not build HTTP client");
+
+    while let Ok(span) = spans_rx.recv() {
+        let mut spans = vec![span];
+        let deadline = Instant::now() + BATCH_TIMEOUT;
+
+        while spans.len() < BATCH_SIZE {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+
+            match spans_rx.recv_timeout(timeout) {
+                Ok(span) => spans.push(span),
+                Err(_) => break,
+            }
+        }
+
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [attribute("service.name", &"flume_water_exporter".into())],
+                },
+                "scopeSpans": [{
+                    "scope": {
+                        "name": "flume_water_exporter",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                    "spans": spans,
+                }],
+            }],
+        });
+
+        let mut builder = client
+            .post(&endpoint)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+
+        for (name, value) in configuration.headers() {
+            builder = builder.header(name, value);
+        }
+
+        match builder.send() {
+            Ok(response) if response.status().is_success() => (),
+            Ok(response) => error!(
+                "exporting spans to {} failed with {}",
+                endpoint,
+                response.status()
+            ),
+            Err(e) => error!("exporting spans to {}: {:#}", endpoint, e),
+        }
+    }
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_f64() => json!({ "doubleValue": n }),
+        Value::Number(n) => json!({ "intValue": n.to_string() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+
+    json!({ "key": key, "value": value })
+}
+
+/// A random trace or span id, never all zeros which OTLP treats as invalid
+fn new_id<const N: usize>() -> [u8; N]
+where
+    [u8; N]: Default + AsMut<[u8]>,
+{
+    let mut id = <[u8; N]>::default();
+
+    while id.iter().all(|&b| b == 0) {
+        rand::thread_rng().fill(id.as_mut());
+    }
+
+    id
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn spans_share_trace() {
+        let (spans_tx, spans_rx) = mpsc::sync_channel(QUEUE_SIZE);
+        let subscriber = tracing_subscriber::registry().with(OtlpTraces { spans_tx });
+
+        let current = tracing::subscriber::with_default(subscriber, || {
+            let cycle = tracing::info_span!("cycle");
+            let _cycle = cycle.enter();
+
+            tracing::info_span!("get", request_name = "usage", status = 200)
+                .in_scope(current_trace_context)
+        });
+
+        let request = spans_rx.recv().unwrap();
+        let cycle = spans_rx.recv().unwrap();
+
+        let (trace_id, span_id) = current.unwrap();
+        assert_eq!(request["traceId"], trace_id);
+        assert_eq!(request["spanId"], span_id);
+
+        assert_eq!("get", request["name"]);
+        assert_eq!("cycle", cycle["name"]);
+        assert_eq!(cycle["traceId"], request["traceId"]);
+        assert_eq!(cycle["spanId"], request["parentSpanId"]);
+        assert!(cycle.get("parentSpanId").is_none());
+        assert_eq!(32, cycle["traceId"].as_str().unwrap().len());
+        assert_eq!(
+            json!([
+                { "key": "request_name", "value": { "stringValue": "usage" } },
+                { "key": "status", "value": { "intValue": "200" } },
+            ]),
+            request["attributes"]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use std::io::BufRead;
+use std::io::IsTerminal;
+use std::io::Write;
+
+/// Read the password from stdin, prompting for it without echoing when stdin is a terminal
+pub fn read() -> Result<String> {
+    let stdin = std::io::stdin();
+
+    let password = if stdin.is_terminal() {
+        eprint!("Flume password: ");
+        std::io::stderr().flush()?;
+
+        let password = without_echo(|| read_line(&stdin));
+        eprintln!();
+
+        password?
+    } else {
+        read_line(&stdin)?
+    };
+
+    if password.is_empty() {
+        return Err(anyhow!(
+            "password = \"-\" but no password was given on stdin"
+        ));
+    }
+
+    Ok(password)
+}
+
+fn read_line(stdin: &std::io::Stdin) -> Result<String> {
+    let mut line = String::new();
+    stdin
+        .lock()
+        .read_line(&mut line)
+        .context("Unable to read the password from stdin")?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Run `read` with terminal echo turned off, restoring it afterwards
+#[cfg(unix)]
+fn without_echo<T>(read: impl FnOnce() -> Result<T>) -> Result<T> {
+    let fd = libc::STDIN_FILENO;
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+
+    // SAFETY: tcgetattr initializes termios when it succeeds
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return read();
+    }
+
+    let original = unsafe { termios.assume_init() };
+    let mut silent = original;
+    silent.c_lflag &= !libc::ECHO;
+
+    // SAFETY: both termios values came from tcgetattr for the same terminal
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    let result = read();
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+
+    result
+}
+
+/// Echo can't be turned off here, the password is read as typed
+#[cfg(not(unix))]
+fn without_echo<T>(read: impl FnOnce() -> Result<T>) -> Result<T> {
+    read()
+}
@@ -0,0 +1,119 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::configuration::PushgatewayConfiguration;
+use crate::state::State;
+
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+
+use prometheus::Encoder;
+use prometheus::Registry;
+use prometheus::TextEncoder;
+
+use reqwest::Url;
+
+use tokio::sync::watch;
+
+/// Pushes the metrics to a Prometheus Pushgateway after each downloader cycle
+pub struct Pushgateway {
+    registry: Registry,
+    configuration: PushgatewayConfiguration,
+    client: reqwest::Client,
+}
+
+impl Pushgateway {
+    pub fn new(configuration: PushgatewayConfiguration, registry: Registry) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(configuration.timeout())
+            .build()
+            .expect("Could not build HTTP client");
+
+        Pushgateway {
+            registry,
+            configuration,
+            client,
+        }
+    }
+
+    pub async fn start(self, mut state_rx: watch::Receiver<State>) -> Result<()> {
+        let url = self.grouping_url()?;
+
+        info!("Pushing metrics to {}", url);
+
+        crate::spawn_named(
+            async move {
+                while state_rx.changed().await.is_ok() {
+                    if let Err(e) = self.push(&url).await {
+                        error!("{:#}", e);
+                    }
+                }
+            },
+            "pushgateway",
+        );
+
+        Ok(())
+    }
+
+    /// Pushgateway URL for the configured job and instance grouping labels
+    fn grouping_url(&self) -> Result<Url> {
+        let base = self.configuration.url();
+        let mut url =
+            Url::parse(&base).with_context(|| format!("Invalid Pushgateway url {}", base))?;
+
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| anyhow!("Invalid Pushgateway url {}", base))?;
+
+            segments.pop_if_empty();
+            segments.extend(&["metrics", "job", &self.configuration.job()]);
+
+            if let Some(instance) = self.configuration.instance() {
+                segments.extend(&["instance", &instance]);
+            }
+        }
+
+        Ok(url)
+    }
+
+    async fn push(&self, url: &Url) -> Result<()> {
+        let encoder = TextEncoder::new();
+        let mut body = vec![];
+
+        encoder
+            .encode(&self.registry.gather(), &mut body)
+            .context("encoding metrics")?;
+
+        debug!("Pushing {} bytes to {}", body.len(), url);
+
+        // PUT replaces all metrics in the group so removed series don't linger
+        let builder = self
+            .client
+            .put(url.clone())
+            .header("Content-Type", encoder.format_type())
+            .body(body);
+
+        let builder = match self.configuration.basic_auth() {
+            Some((username, password)) => builder.basic_auth(username, Some(password)),
+            None => builder,
+        };
+
+        let response = builder
+            .send()
+            .await
+            .with_context(|| format!("pushing metrics to {}", url))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "pushing metrics to {} failed with {}",
+                url,
+                response.status()
+            ))
+        }
+    }
+}
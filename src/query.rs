@@ -0,0 +1,88 @@
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::arguments::QueryArguments;
+use crate::client;
+use crate::client::QueryBucket;
+use crate::configuration::Configuration;
+use crate::flume_builder::FlumeBuilder;
+
+use serde::Serialize;
+
+/// One bucket of usage printed by the `query` command
+#[derive(Serialize)]
+struct Row {
+    datetime: String,
+    liters: f64,
+}
+
+/// Query usage for one device and print it, so exporter numbers can be compared with the Flume
+/// app
+pub async fn run(configuration: &Configuration, arguments: &QueryArguments) -> Result<()> {
+    let device_id = arguments
+        .device_id
+        .as_ref()
+        .ok_or_else(|| anyhow!("query requires a device id"))?;
+    let since = arguments
+        .since
+        .clone()
+        .ok_or_else(|| anyhow!("query requires --since"))?;
+    let bucket = bucket(arguments.bucket.as_deref().unwrap_or("HR"))?;
+    let format = arguments.format.as_deref().unwrap_or("json");
+
+    if format != "json" && format != "csv" {
+        return Err(anyhow!("Unknown format {}, use json or csv", format));
+    }
+
+    let mut flume = FlumeBuilder::from_configuration(configuration.clone())
+        .build()
+        .await?;
+
+    let user_id = flume.user_id().await?;
+
+    let query = client::Query {
+        request_id: since.clone(),
+        bucket,
+        since_datetime: since,
+        until_datetime: arguments.until.clone(),
+        units: Some(client::QueryUnits::LITERS),
+        ..Default::default()
+    };
+
+    let rows: Vec<Row> = flume
+        .query_results(user_id, device_id, query)
+        .await?
+        .into_iter()
+        .map(|result| Row {
+            datetime: result.datetime.unwrap_or_default(),
+            liters: result.value,
+        })
+        .collect();
+
+    if format == "csv" {
+        println!("datetime,liters");
+
+        for row in rows {
+            println!("{},{}", row.datetime, row.liters);
+        }
+    } else {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+    }
+
+    Ok(())
+}
+
+/// Parse a query bucket name
+pub fn bucket(name: &str) -> Result<QueryBucket> {
+    match name.to_uppercase().as_str() {
+        "MIN" => Ok(QueryBucket::MIN),
+        "HR" => Ok(QueryBucket::HR),
+        "DAY" => Ok(QueryBucket::DAY),
+        "MON" => Ok(QueryBucket::MON),
+        "YR" => Ok(QueryBucket::YR),
+        _ => Err(anyhow!(
+            "Unknown bucket {}, use MIN, HR, DAY, MON, or YR",
+            name
+        )),
+    }
+}
@@ -0,0 +1,43 @@
+use crate::client::QueryBucket;
+use crate::client::QueryOperation;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Bucket, operation, and interval for a sensor's usage queries
+#[derive(Clone, Debug)]
+pub struct UsageQuery {
+    pub bucket: QueryBucket,
+    pub operation: QueryOperation,
+    /// Shortest time between queries for the sensor, when `None` the sensor is queried every
+    /// query interval
+    pub interval: Option<Duration>,
+}
+
+impl Default for UsageQuery {
+    fn default() -> Self {
+        UsageQuery {
+            bucket: QueryBucket::MIN,
+            operation: QueryOperation::SUM,
+            interval: None,
+        }
+    }
+}
+
+/// Usage query settings for all sensors with per-sensor overrides
+#[derive(Clone, Debug, Default)]
+pub struct QuerySettings {
+    default: UsageQuery,
+    devices: HashMap<String, UsageQuery>,
+}
+
+impl QuerySettings {
+    pub fn new(default: UsageQuery, devices: HashMap<String, UsageQuery>) -> Self {
+        QuerySettings { default, devices }
+    }
+
+    /// Settings for the sensor `id`
+    pub fn for_device(&self, id: &str) -> &UsageQuery {
+        self.devices.get(id).unwrap_or(&self.default)
+    }
+}
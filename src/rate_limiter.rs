@@ -0,0 +1,136 @@
+use anyhow::Result;
+
+use lazy_static::lazy_static;
+
+use prometheus::Counter;
+use prometheus::Registry;
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+use tracing::debug;
+
+lazy_static! {
+    static ref WAIT: Counter = Counter::new(
+        "flume_water_api_rate_limit_wait_seconds_total",
+        "Time Flume API requests waited for the client-side rate limiter",
+    )
+    .unwrap();
+}
+
+/// Add the rate limiter metric to `registry`
+pub(crate) fn register(registry: &Registry) -> Result<()> {
+    registry.register(Box::new(WAIT.clone()))?;
+
+    Ok(())
+}
+
+/// Largest burst of requests made without waiting
+const MAX_BURST: u32 = 10;
+
+/// Token bucket limiting Flume API requests, shared between clones so every request type draws
+/// from the same bucket.
+///
+/// The bucket holds up to a burst of requests and refills at the rest of the hourly limit, so no
+/// hour can contain more than `requests_per_hour` requests.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+    capacity: f64,
+    refill_per_second: f64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Limit requests to `requests_per_hour`, which must be at least 2
+    pub fn new(requests_per_hour: u32) -> Self {
+        let burst = (requests_per_hour / 12).clamp(1, MAX_BURST);
+        let capacity = f64::from(burst);
+        let refill_per_second = f64::from(requests_per_hour.saturating_sub(burst).max(1)) / 3600.0;
+
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: capacity,
+                updated: Instant::now(),
+            })),
+            capacity,
+            refill_per_second,
+        }
+    }
+
+    /// Wait until a request can be made without going over the limit.
+    ///
+    /// Waiting requests hold the bucket so they are made in the order they arrived.
+    pub async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+
+        loop {
+            let now = Instant::now();
+            let refilled =
+                now.duration_since(bucket.updated).as_secs_f64() * self.refill_per_second;
+
+            bucket.tokens = (bucket.tokens + refilled).min(self.capacity);
+            bucket.updated = now;
+
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+
+                return;
+            }
+
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_second);
+            debug!("Waiting {:.1}s for the rate limiter", wait.as_secs_f64());
+
+            sleep(wait).await;
+            WAIT.inc_by(wait.as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_stays_under_hourly_limit() {
+        for requests_per_hour in [2, 12, 120, 1000] {
+            let limiter = RateLimiter::new(requests_per_hour);
+
+            // A full burst followed by an hour of refills
+            let most = limiter.capacity + limiter.refill_per_second * 3600.0;
+
+            assert!(
+                most <= f64::from(requests_per_hour) + 1e-9,
+                "{} allows {}",
+                requests_per_hour,
+                most
+            );
+            assert!(limiter.capacity >= 1.0 && limiter.capacity <= f64::from(MAX_BURST));
+        }
+    }
+
+    #[tokio::test]
+    async fn acquire_waits_after_burst() {
+        // A burst of 10 refilled at about 10 per second
+        let limiter = RateLimiter::new(36_000);
+        let started = Instant::now();
+
+        for _ in 0..MAX_BURST {
+            limiter.acquire().await;
+        }
+
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        limiter.clone().acquire().await;
+
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::update::Update;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use tracing::warn;
+
+/// How long usage windows are kept for `Readings::usage_since`
+pub const USAGE_RETENTION: chrono::Duration = chrono::Duration::days(7);
+
+/// The latest update of one kind for one device
+#[derive(Clone, Debug, Serialize)]
+pub struct Reading {
+    /// When the downloader sent the update
+    pub received: DateTime<Utc>,
+    #[serde(flatten)]
+    pub update: Update,
+}
+
+/// Latest downloader updates for each device and the usage windows of the last
+/// `USAGE_RETENTION`, shared between clones, for serving collected data outside the Prometheus
+/// metrics
+#[derive(Clone, Default)]
+pub struct Readings {
+    latest: Arc<RwLock<BTreeMap<String, Reading>>>,
+    usage: Arc<RwLock<VecDeque<Reading>>>,
+}
+
+impl Readings {
+    /// Keep the latest of each update from `updates_rx`
+    pub fn start(&self, mut updates_rx: broadcast::Receiver<Update>) {
+        let readings = self.clone();
+
+        crate::spawn_named(
+            async move {
+                loop {
+                    let update = match updates_rx.recv().await {
+                        Ok(u) => u,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Readings fell behind, skipped {} updates", skipped);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    };
+
+                    readings.insert(update);
+                }
+            },
+            "readings",
+        );
+    }
+
+    /// Latest readings ordered by kind and device id
+    pub fn latest(&self) -> Vec<Reading> {
+        self.latest.read().unwrap().values().cloned().collect()
+    }
+
+    /// Usage windows received since `since`, oldest first
+    pub fn usage_since(&self, since: DateTime<Utc>) -> Vec<Reading> {
+        self.usage
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|reading| reading.received >= since)
+            .cloned()
+            .collect()
+    }
+
+    fn insert(&self, update: Update) {
+        let reading = Reading {
+            received: Utc::now(),
+            update,
+        };
+
+        if let Update::Usage { .. } = reading.update {
+            let mut usage = self.usage.write().unwrap();
+            let oldest = reading.received - USAGE_RETENTION;
+
+            while usage.front().is_some_and(|r| r.received < oldest) {
+                usage.pop_front();
+            }
+
+            usage.push_back(reading.clone());
+        }
+
+        self.latest
+            .write()
+            .unwrap()
+            .insert(key(&reading.update), reading);
+    }
+}
+
+/// Updates with the same key replace each other
+fn key(update: &Update) -> String {
+    match update {
+        Update::Bridge { device_id, .. } | Update::Sensor { device_id, .. } => {
+            format!("device/{}", device_id)
+        }
+        Update::Usage { device_id, .. } => format!("usage/{}", device_id),
+        Update::Periods { device_id, .. } => format!("periods/{}", device_id),
+        Update::Budget {
+            device_id,
+            name,
+            period,
+            ..
+        } => format!("budget/{}/{}/{}", device_id, period, name),
+    }
+}
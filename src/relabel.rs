@@ -0,0 +1,207 @@
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::device::Device;
+
+use std::collections::HashMap;
+
+/// Labels `[relabel.drop]` can match devices on
+const DROP_LABELS: [&str; 5] = [
+    "location_id",
+    "location",
+    "device_id",
+    "bridge_id",
+    "product",
+];
+
+/// Renames label values and drops devices from the `[relabel]` configuration before their
+/// metrics are set
+#[derive(Clone, Debug, Default)]
+pub struct Relabel {
+    locations: HashMap<String, String>,
+    /// Location names by location id, used before `locations` so a location renamed in the
+    /// Flume app keeps its name
+    location_ids: HashMap<String, String>,
+    products: HashMap<String, String>,
+    drop: HashMap<String, Vec<String>>,
+}
+
+impl Relabel {
+    pub fn new(
+        locations: HashMap<String, String>,
+        location_ids: HashMap<String, String>,
+        products: HashMap<String, String>,
+        drop: HashMap<String, Vec<String>>,
+    ) -> Result<Self> {
+        if let Some(label) = drop.keys().find(|l| !DROP_LABELS.contains(&l.as_str())) {
+            return Err(anyhow!(
+                "[relabel.drop] can't match {:?}, it can only match {}",
+                label,
+                DROP_LABELS.join(", ")
+            ));
+        }
+
+        Ok(Relabel {
+            locations,
+            location_ids,
+            products,
+            drop,
+        })
+    }
+
+    /// `device` with its location and product renamed, or `None` when a label matches a
+    /// `[relabel.drop]` value.  Drop rules match the values Flume returned, before renaming.
+    pub fn apply(&self, device: Device) -> Option<Device> {
+        match device {
+            Device::Bridge(mut b) => {
+                let labels = [
+                    ("location_id", &b.location_id),
+                    ("location", &b.location),
+                    ("device_id", &b.id),
+                    ("product", &b.product),
+                ];
+
+                if self.drops(&labels) {
+                    return None;
+                }
+
+                self.rename_location(&b.location_id, &mut b.location);
+                rename(&self.products, &mut b.product);
+
+                Some(Device::Bridge(b))
+            }
+            Device::Sensor(mut s) => {
+                let labels = [
+                    ("location_id", &s.location_id),
+                    ("location", &s.location),
+                    ("device_id", &s.sensor.id),
+                    ("bridge_id", &s.sensor.bridge_id),
+                    ("product", &s.sensor.product),
+                ];
+
+                if self.drops(&labels) {
+                    return None;
+                }
+
+                self.rename_location(&s.location_id, &mut s.location);
+                rename(&self.products, &mut s.sensor.product);
+
+                Some(Device::Sensor(s))
+            }
+        }
+    }
+
+    fn rename_location(&self, location_id: &str, location: &mut String) {
+        match self.location_ids.get(location_id) {
+            Some(name) => location.clone_from(name),
+            None => rename(&self.locations, location),
+        }
+    }
+
+    fn drops(&self, labels: &[(&str, &String)]) -> bool {
+        labels.iter().any(|(label, value)| {
+            self.drop
+                .get(*label)
+                .is_some_and(|values| values.contains(value))
+        })
+    }
+}
+
+fn rename(names: &HashMap<String, String>, value: &mut String) {
+    if let Some(name) = names.get(value.as_str()) {
+        value.clone_from(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::flume_api::mock;
+
+    use chrono::Utc;
+
+    fn sensor(id: &str) -> Device {
+        Device::try_from(mock::sensor(id, Utc::now())).unwrap()
+    }
+
+    fn names(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    fn relabeled(relabel: &Relabel, device: Device) -> (String, String) {
+        match relabel.apply(device) {
+            Some(Device::Sensor(s)) => (s.location, s.sensor.product),
+            Some(Device::Bridge(b)) => (b.location, b.product),
+            None => panic!("device was dropped"),
+        }
+    }
+
+    #[test]
+    fn apply_renames() {
+        let relabel = Relabel::new(
+            names(&[("Home", "House")]),
+            HashMap::new(),
+            names(&[("flume2sensor", "Flume 2")]),
+            HashMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            ("House".to_string(), "Flume 2".to_string()),
+            relabeled(&relabel, sensor("1"))
+        );
+    }
+
+    #[test]
+    fn apply_renames_location_id_first() {
+        let relabel = Relabel::new(
+            names(&[("Home", "House")]),
+            names(&[("5678", "Cabin")]),
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            ("Cabin".to_string(), "flume2sensor".to_string()),
+            relabeled(&relabel, sensor("1"))
+        );
+    }
+
+    #[test]
+    fn apply_drops_before_renaming() {
+        let drop = HashMap::from([("location".to_string(), vec!["Home".to_string()])]);
+        let relabel = Relabel::new(
+            names(&[("Home", "House")]),
+            HashMap::new(),
+            HashMap::new(),
+            drop,
+        )
+        .unwrap();
+
+        assert!(relabel.apply(sensor("1")).is_none());
+
+        let drop = HashMap::from([("device_id".to_string(), vec!["2".to_string()])]);
+        let relabel = Relabel::new(HashMap::new(), HashMap::new(), HashMap::new(), drop).unwrap();
+
+        assert!(relabel.apply(sensor("1")).is_some());
+        assert!(relabel.apply(sensor("2")).is_none());
+    }
+
+    #[test]
+    fn new_rejects_unknown_drop_label() {
+        let drop = HashMap::from([("name".to_string(), vec!["Home".to_string()])]);
+
+        let error = Relabel::new(HashMap::new(), HashMap::new(), HashMap::new(), drop).unwrap_err();
+
+        assert!(
+            error.to_string().contains("can't match \"name\""),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::configuration::RemoteWriteConfiguration;
+use crate::state::State;
+
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+
+use prometheus::proto::MetricFamily;
+use prometheus::proto::MetricType;
+use prometheus::Registry;
+
+use prost::Message;
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use tokio::sync::watch;
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+/// Pushes the metrics to a Prometheus remote write endpoint after each downloader cycle
+pub struct RemoteWrite {
+    registry: Registry,
+    configuration: RemoteWriteConfiguration,
+    client: reqwest::Client,
+}
+
+impl RemoteWrite {
+    pub fn new(configuration: RemoteWriteConfiguration, registry: Registry) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(configuration.timeout())
+            .build()
+            .expect("Could not build HTTP client");
+
+        RemoteWrite {
+            registry,
+            configuration,
+            client,
+        }
+    }
+
+    pub async fn start(self, mut state_rx: watch::Receiver<State>) {
+        info!("Pushing metrics to {}", self.configuration.url());
+
+        crate::spawn_named(
+            async move {
+                while state_rx.changed().await.is_ok() {
+                    if let Err(e) = self.push().await {
+                        error!("{:#}", e);
+                    }
+                }
+            },
+            "remote_write",
+        );
+    }
+
+    async fn push(&self) -> Result<()> {
+        let url = self.configuration.url();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+
+        let target = target_labels(&self.configuration);
+        let request = write_request(&self.registry.gather(), &target, timestamp);
+        debug!("Pushing {} series to {}", request.timeseries.len(), url);
+
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .context("compressing remote write request")?;
+
+        let builder = self
+            .client
+            .post(&url)
+            .header("Content-Encoding", "snappy")
+            .header("Content-Type", "application/x-protobuf")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .body(body);
+
+        let builder = if let Some((username, password)) = self.configuration.basic_auth() {
+            builder.basic_auth(username, Some(password))
+        } else if let Some(token) = self.configuration.bearer_token() {
+            builder.bearer_auth(token)
+        } else {
+            builder
+        };
+
+        let response = builder
+            .send()
+            .await
+            .with_context(|| format!("pushing metrics to {}", url))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "pushing metrics to {} failed with {}",
+                url,
+                response.status()
+            ))
+        }
+    }
+}
+
+/// `job` and `instance` labels identifying the exporter in the remote write endpoint
+fn target_labels(configuration: &RemoteWriteConfiguration) -> Vec<Label> {
+    let mut target = vec![Label {
+        name: "job".to_string(),
+        value: configuration.job(),
+    }];
+    target.extend(configuration.instance().map(|instance| Label {
+        name: "instance".to_string(),
+        value: instance,
+    }));
+
+    target
+}
+
+/// Remote write request for `metric_families` with the `target` labels added to each series
+/// that doesn't have its own
+fn write_request(
+    metric_families: &[MetricFamily],
+    target: &[Label],
+    timestamp: i64,
+) -> WriteRequest {
+    let mut timeseries = vec![];
+
+    for family in metric_families {
+        let name = family.get_name();
+
+        for metric in family.get_metric() {
+            let mut labels: Vec<Label> = metric
+                .get_label()
+                .iter()
+                .map(|l| Label {
+                    name: l.get_name().to_string(),
+                    value: l.get_value().to_string(),
+                })
+                .collect();
+
+            for label in target {
+                if !labels.iter().any(|l| l.name == label.name) {
+                    labels.push(label.clone());
+                }
+            }
+
+            let mut series = |suffix: &str, extra: Option<Label>, value: f64| {
+                let mut labels = labels.clone();
+                labels.push(Label {
+                    name: "__name__".to_string(),
+                    value: format!("{}{}", name, suffix),
+                });
+                labels.extend(extra);
+                labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+                timeseries.push(TimeSeries {
+                    labels,
+                    samples: vec![Sample { value, timestamp }],
+                });
+            };
+
+            match family.get_field_type() {
+                MetricType::COUNTER => series("", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => series("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => series("", None, metric.get_untyped().get_value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+
+                    for bucket in histogram.get_bucket() {
+                        let le = Label {
+                            name: "le".to_string(),
+                            value: bucket.get_upper_bound().to_string(),
+                        };
+
+                        series("_bucket", Some(le), bucket.get_cumulative_count() as f64);
+                    }
+
+                    let le = Label {
+                        name: "le".to_string(),
+                        value: "+Inf".to_string(),
+                    };
+                    let count = histogram.get_sample_count() as f64;
+
+                    series("_bucket", Some(le), count);
+                    series("_sum", None, histogram.get_sample_sum());
+                    series("_count", None, count);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+
+                    series("_sum", None, summary.get_sample_sum());
+                    series("_count", None, summary.get_sample_count() as f64);
+                }
+            }
+        }
+    }
+
+    WriteRequest { timeseries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use prometheus::Histogram;
+    use prometheus::HistogramOpts;
+    use prometheus::IntGauge;
+    use prometheus::IntGaugeVec;
+    use prometheus::Opts;
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn configuration(toml: &str) -> RemoteWriteConfiguration {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn target_labels_default_job() {
+        let target = target_labels(&configuration(r#"url = "http://localhost:9090""#));
+
+        assert_eq!(vec![label("job", "flume_water_exporter")], target);
+    }
+
+    #[test]
+    fn target_labels_job_and_instance() {
+        let target = target_labels(&configuration(
+            r#"
+url = "http://localhost:9090"
+job = "flume"
+instance = "house"
+"#,
+        ));
+
+        assert_eq!(
+            vec![label("job", "flume"), label("instance", "house")],
+            target
+        );
+    }
+
+    #[test]
+    fn write_request_adds_target_labels() {
+        let registry = Registry::new();
+
+        let up = IntGauge::new("up", "Up").unwrap();
+        registry.register(Box::new(up.clone())).unwrap();
+        up.set(1);
+
+        let own = IntGaugeVec::new(Opts::new("own", "Own job"), &["job"]).unwrap();
+        registry.register(Box::new(own.clone())).unwrap();
+        own.with_label_values(&["mine"]).set(2);
+
+        let target = [label("job", "flume"), label("instance", "house")];
+        let request = write_request(&registry.gather(), &target, 1000);
+
+        assert_eq!(
+            vec![
+                TimeSeries {
+                    labels: vec![
+                        label("__name__", "own"),
+                        label("instance", "house"),
+                        label("job", "mine"),
+                    ],
+                    samples: vec![Sample {
+                        value: 2.0,
+                        timestamp: 1000,
+                    }],
+                },
+                TimeSeries {
+                    labels: vec![
+                        label("__name__", "up"),
+                        label("instance", "house"),
+                        label("job", "flume"),
+                    ],
+                    samples: vec![Sample {
+                        value: 1.0,
+                        timestamp: 1000,
+                    }],
+                },
+            ],
+            request.timeseries
+        );
+    }
+
+    #[test]
+    fn write_request_histogram() {
+        let registry = Registry::new();
+
+        let durations = Histogram::with_opts(
+            HistogramOpts::new("duration_seconds", "Durations").buckets(vec![1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(durations.clone())).unwrap();
+        durations.observe(0.5);
+        durations.observe(2.0);
+
+        let request = write_request(&registry.gather(), &[], 1000);
+
+        let series: Vec<(String, f64)> = request
+            .timeseries
+            .iter()
+            .map(|series| {
+                let labels: Vec<String> = series
+                    .labels
+                    .iter()
+                    .map(|l| format!("{}={}", l.name, l.value))
+                    .collect();
+
+                (labels.join(","), series.samples[0].value)
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("__name__=duration_seconds_bucket,le=1".to_string(), 1.0),
+                ("__name__=duration_seconds_bucket,le=+Inf".to_string(), 2.0),
+                ("__name__=duration_seconds_sum".to_string(), 2.5),
+                ("__name__=duration_seconds_count".to_string(), 2.0),
+            ],
+            series
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::Result;
+
+use lazy_static::lazy_static;
+
+use prometheus::IntGauge;
+use prometheus::Registry;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Flume API rate limit in requests per hour
+pub const HOURLY_LIMIT: f64 = 120.0;
+
+const WINDOW: Duration = Duration::from_secs(3600);
+
+lazy_static! {
+    static ref REQUESTS_LAST_HOUR: IntGauge = IntGauge::new(
+        "flume_water_api_requests_last_hour",
+        "Number of Flume API requests made in the last hour",
+    )
+    .unwrap();
+}
+
+/// Add the request window metric to `registry`
+pub(crate) fn register(registry: &Registry) -> Result<()> {
+    registry.register(Box::new(REQUESTS_LAST_HOUR.clone()))?;
+
+    Ok(())
+}
+
+/// Sliding one-hour window of Flume API requests, shared between clones
+#[derive(Clone, Default)]
+pub struct RequestWindow {
+    requests: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl RequestWindow {
+    /// Record a request made now and return the number of requests made in the last hour
+    pub fn record(&self) -> usize {
+        let now = Instant::now();
+        let mut requests = self.requests.lock().unwrap();
+
+        requests.push_back(now);
+
+        while let Some(oldest) = requests.front() {
+            if now.duration_since(*oldest) < WINDOW {
+                break;
+            }
+
+            requests.pop_front();
+        }
+
+        REQUESTS_LAST_HOUR.set(requests.len() as i64);
+
+        requests.len()
+    }
+}
+
+/// Requests per hour made polling once every `interval`
+pub fn per_hour(interval: Duration) -> f64 {
+    if interval.is_zero() {
+        return f64::INFINITY;
+    }
+
+    WINDOW.as_secs_f64() / interval.as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_shared_between_clones() {
+        let window = RequestWindow::default();
+
+        assert_eq!(1, window.record());
+        assert_eq!(2, window.clone().record());
+    }
+
+    #[test]
+    fn record_forgets_requests_older_than_an_hour() {
+        let window = RequestWindow::default();
+        let now = Instant::now();
+
+        // Instants can't be earlier than the clock's start, shortly after boot on some systems
+        let expired = now.checked_sub(WINDOW + Duration::from_secs(1));
+        let recent = now.checked_sub(WINDOW / 2);
+        window
+            .requests
+            .lock()
+            .unwrap()
+            .extend(expired.into_iter().chain(recent));
+
+        assert_eq!(1 + usize::from(recent.is_some()), window.record());
+    }
+
+    #[test]
+    fn per_hour_of_interval() {
+        assert_eq!(60.0, per_hour(Duration::from_secs(60)));
+        assert_eq!(f64::INFINITY, per_hour(Duration::ZERO));
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::Context;
+use anyhow::Result;
+
+use chrono::Utc;
+
+use tracing::info;
+use tracing::warn;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Fields replaced before a response is saved, they hold credentials or personal details
+const REDACTED_FIELDS: [&str; 10] = [
+    "access_token",
+    "refresh_token",
+    "client_secret",
+    "password",
+    "email_address",
+    "first_name",
+    "last_name",
+    "phone",
+    "address",
+    "address_2",
+];
+
+const REDACTED: &str = "REDACTED";
+
+/// Save `body`, a response from `request_name` that could not be deserialized, to `directory`
+/// so it can be attached to a bug report.  Failing to save is only logged.
+pub fn save(directory: &Path, request_name: &str, body: &str) {
+    match write(directory, request_name, body) {
+        Ok(path) => info!(
+            "Saved response that could not be parsed to {}",
+            path.display()
+        ),
+        Err(e) => warn!("Unable to save response that could not be parsed {:#}", e),
+    }
+}
+
+fn write(directory: &Path, request_name: &str, body: &str) -> Result<PathBuf> {
+    fs::create_dir_all(directory)
+        .with_context(|| format!("creating response spool {}", directory.display()))?;
+
+    let name = format!(
+        "{}-{}.json",
+        request_name.replace(' ', "_"),
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    );
+    let path = directory.join(name);
+
+    fs::write(&path, redact(body)).with_context(|| format!("writing {}", path.display()))?;
+
+    Ok(path)
+}
+
+/// `body` with credentials and personal details replaced.  Bodies that aren't JSON, such as an
+/// HTML error page, are saved as is since they don't contain the exporter's credentials.
+fn redact(body: &str) -> String {
+    let mut json: serde_json::Value = match serde_json::from_str(body) {
+        Ok(json) => json,
+        Err(_) => return body.to_string(),
+    };
+
+    redact_value(&mut json);
+
+    serde_json::to_string_pretty(&json).unwrap_or_else(|_| body.to_string())
+}
+
+fn redact_value(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    *value = serde_json::Value::from(REDACTED);
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use serde_json::Value;
+
+    #[test]
+    fn redact_nested_fields() {
+        let body = json!({
+            "success": true,
+            "data": [{
+                "access_token": "ACCESS_TOKEN",
+                "refresh_token": "REFRESH_TOKEN",
+                "location": { "name": "Home", "address": "1 Main St" },
+            }],
+        });
+
+        let redacted: Value = serde_json::from_str(&redact(&body.to_string())).unwrap();
+
+        assert_eq!(
+            json!({
+                "success": true,
+                "data": [{
+                    "access_token": REDACTED,
+                    "refresh_token": REDACTED,
+                    "location": { "name": "Home", "address": REDACTED },
+                }],
+            }),
+            redacted
+        );
+    }
+
+    #[test]
+    fn redact_non_json() {
+        let body = "<html><body>Bad Gateway</body></html>";
+
+        assert_eq!(body, redact(body));
+    }
+}
@@ -0,0 +1,187 @@
+use prometheus::core::Collector;
+use prometheus::core::Desc;
+use prometheus::proto::MetricFamily;
+use prometheus::CounterVec;
+#[cfg(tokio_unstable)]
+use prometheus::GaugeVec;
+#[cfg(tokio_unstable)]
+use prometheus::IntCounter;
+use prometheus::IntCounterVec;
+use prometheus::IntGauge;
+use prometheus::Opts;
+
+use std::sync::Mutex;
+
+use tokio::runtime::RuntimeMetrics;
+
+/// Exports the tokio runtime metrics each time the registry is gathered.
+///
+/// Blocking pool and poll time metrics are only available when built with
+/// `RUSTFLAGS="--cfg tokio_unstable"`.
+pub struct RuntimeCollector {
+    runtime: RuntimeMetrics,
+    workers: IntGauge,
+    alive_tasks: IntGauge,
+    global_queue_depth: IntGauge,
+    busy: CounterVec,
+    parks: IntCounterVec,
+    #[cfg(tokio_unstable)]
+    mean_poll_time: GaugeVec,
+    #[cfg(tokio_unstable)]
+    spawned_tasks: IntCounter,
+    #[cfg(tokio_unstable)]
+    blocking_threads: IntGauge,
+    #[cfg(tokio_unstable)]
+    idle_blocking_threads: IntGauge,
+    #[cfg(tokio_unstable)]
+    blocking_queue_depth: IntGauge,
+    // Gathers update the shared metrics from the runtime one at a time
+    collecting: Mutex<()>,
+}
+
+impl RuntimeCollector {
+    pub fn new(runtime: RuntimeMetrics) -> Self {
+        RuntimeCollector {
+            runtime,
+            workers: IntGauge::new("tokio_workers", "Number of tokio runtime worker threads")
+                .unwrap(),
+            alive_tasks: IntGauge::new("tokio_alive_tasks", "Number of tasks alive in the runtime")
+                .unwrap(),
+            global_queue_depth: IntGauge::new(
+                "tokio_global_queue_depth",
+                "Number of tasks waiting in the runtime's global queue",
+            )
+            .unwrap(),
+            busy: CounterVec::new(
+                Opts::new(
+                    "tokio_worker_busy_seconds_total",
+                    "Time each worker thread spent running tasks",
+                ),
+                &["worker"],
+            )
+            .unwrap(),
+            parks: IntCounterVec::new(
+                Opts::new(
+                    "tokio_worker_parks_total",
+                    "Number of times each worker thread parked waiting for work",
+                ),
+                &["worker"],
+            )
+            .unwrap(),
+            #[cfg(tokio_unstable)]
+            mean_poll_time: GaugeVec::new(
+                Opts::new(
+                    "tokio_worker_mean_poll_time_seconds",
+                    "Moving average of the time each worker thread takes to poll a task",
+                ),
+                &["worker"],
+            )
+            .unwrap(),
+            #[cfg(tokio_unstable)]
+            spawned_tasks: IntCounter::new(
+                "tokio_spawned_tasks_total",
+                "Number of tasks spawned in the runtime",
+            )
+            .unwrap(),
+            #[cfg(tokio_unstable)]
+            blocking_threads: IntGauge::new(
+                "tokio_blocking_threads",
+                "Number of threads in the runtime's blocking pool",
+            )
+            .unwrap(),
+            #[cfg(tokio_unstable)]
+            idle_blocking_threads: IntGauge::new(
+                "tokio_idle_blocking_threads",
+                "Number of idle threads in the runtime's blocking pool",
+            )
+            .unwrap(),
+            #[cfg(tokio_unstable)]
+            blocking_queue_depth: IntGauge::new(
+                "tokio_blocking_queue_depth",
+                "Number of tasks waiting for a thread in the runtime's blocking pool",
+            )
+            .unwrap(),
+            collecting: Mutex::new(()),
+        }
+    }
+
+    fn update(&self) {
+        let runtime = &self.runtime;
+        let workers = runtime.num_workers();
+
+        self.workers.set(workers as i64);
+        self.alive_tasks.set(runtime.num_alive_tasks() as i64);
+        self.global_queue_depth
+            .set(runtime.global_queue_depth() as i64);
+
+        for worker in 0..workers {
+            let label = worker.to_string();
+
+            // The runtime keeps the totals, replace the counter values with them
+            let busy = self.busy.with_label_values(&[&label]);
+            busy.reset();
+            busy.inc_by(runtime.worker_total_busy_duration(worker).as_secs_f64());
+
+            let parks = self.parks.with_label_values(&[&label]);
+            parks.reset();
+            parks.inc_by(runtime.worker_park_count(worker));
+
+            #[cfg(tokio_unstable)]
+            self.mean_poll_time
+                .with_label_values(&[&label])
+                .set(runtime.worker_mean_poll_time(worker).as_secs_f64());
+        }
+
+        #[cfg(tokio_unstable)]
+        {
+            self.spawned_tasks.reset();
+            self.spawned_tasks.inc_by(runtime.spawned_tasks_count());
+            self.blocking_threads
+                .set(runtime.num_blocking_threads() as i64);
+            self.idle_blocking_threads
+                .set(runtime.num_idle_blocking_threads() as i64);
+            self.blocking_queue_depth
+                .set(runtime.blocking_queue_depth() as i64);
+        }
+    }
+
+    fn collectors(&self) -> Vec<&dyn Collector> {
+        vec![
+            &self.workers,
+            &self.alive_tasks,
+            &self.global_queue_depth,
+            &self.busy,
+            &self.parks,
+            #[cfg(tokio_unstable)]
+            &self.mean_poll_time,
+            #[cfg(tokio_unstable)]
+            &self.spawned_tasks,
+            #[cfg(tokio_unstable)]
+            &self.blocking_threads,
+            #[cfg(tokio_unstable)]
+            &self.idle_blocking_threads,
+            #[cfg(tokio_unstable)]
+            &self.blocking_queue_depth,
+        ]
+    }
+}
+
+impl Collector for RuntimeCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.collectors()
+            .into_iter()
+            .flat_map(|collector| collector.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let _collecting = self.collecting.lock().unwrap();
+
+        self.update();
+
+        self.collectors()
+            .into_iter()
+            .flat_map(|collector| collector.collect())
+            .collect()
+    }
+}
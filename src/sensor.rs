@@ -2,23 +2,116 @@ use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 
+use chrono::offset::LocalResult;
+use chrono::offset::Utc;
 use chrono::DateTime;
+use chrono::Datelike;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::Timelike;
 use chrono_tz::Tz;
 
 use crate::client;
+use crate::client::QueryBucket;
+
+use tracing::warn;
 
 use std::convert::TryFrom;
 
+/// Local times of the first and last minute of a usage query, the Flume API takes times in the
+/// sensor's timezone without an offset
+#[derive(Clone, Copy, Debug)]
+pub struct QueryWindow {
+    pub first: NaiveDateTime,
+    pub last: NaiveDateTime,
+}
+
+/// A sensor with its location and timezone parsed, tracking the end of its last usage query
 #[derive(Clone)]
 pub struct Sensor {
     pub sensor: client::Sensor,
+    pub location: String,
+    pub location_id: String,
+    pub last_seen: DateTime<Tz>,
     pub last_update: DateTime<Tz>,
 }
 
 impl Sensor {
+    /// Current time in the sensor's timezone
+    pub fn now(&self) -> DateTime<Tz> {
+        Utc::now().with_timezone(&self.last_update.timezone())
+    }
+
+    /// Start of `date` in the sensor's timezone.
+    ///
+    /// When a daylight saving time change skips midnight the day starts at the first local time
+    /// that exists.  When a change repeats midnight the day starts at the earlier one.
+    pub fn start_of_day(&self, date: NaiveDate) -> DateTime<Tz> {
+        let timezone = self.last_update.timezone();
+        let mut time = date.and_hms_opt(0, 0, 0).unwrap();
+
+        loop {
+            match timezone.from_local_datetime(&time) {
+                LocalResult::Single(t) => return t,
+                LocalResult::Ambiguous(earliest, _) => return earliest,
+                LocalResult::None => time += chrono::Duration::minutes(15),
+            }
+        }
+    }
+
+    /// Start of the `bucket` containing `time`
+    pub fn start_of_bucket(&self, time: DateTime<Tz>, bucket: &QueryBucket) -> DateTime<Tz> {
+        let date = time.date_naive();
+
+        match bucket {
+            QueryBucket::MIN => start_of_minute(time),
+            QueryBucket::HR => start_of_minute(time.with_minute(0).unwrap_or(time)),
+            QueryBucket::DAY => self.start_of_day(date),
+            QueryBucket::MON => self.start_of_day(date.with_day(1).unwrap_or(date)),
+            QueryBucket::YR => self.start_of_day(date.with_ordinal(1).unwrap_or(date)),
+        }
+    }
+
+    /// End of a query window `lag` before now, on a whole `bucket`
+    pub fn query_window_end(&self, lag: chrono::Duration, bucket: &QueryBucket) -> DateTime<Tz> {
+        self.start_of_bucket(self.now() - lag, bucket)
+    }
+
+    /// Buckets to query from the last update until `until`, or None if there are none yet.
+    ///
+    /// The window is built from local times so it is valid across daylight saving time changes.
+    /// When clocks spring forward the skipped hour has no local minutes to query.  When clocks fall
+    /// back the repeated hour's local minutes were already queried, so no window is returned
+    /// until local time passes the last update again.
+    pub fn query_window(&self, until: DateTime<Tz>, bucket: &QueryBucket) -> Option<QueryWindow> {
+        let first = self.start_of_bucket(self.last_update, bucket).naive_local();
+        let last = (until - chrono::Duration::minutes(1)).naive_local();
+
+        (last >= first).then_some(QueryWindow { first, last })
+    }
+
+    /// Start of the day the sensor's location was installed, when Flume gives the `installation`
+    /// as a date.  Most locations only have the installation status.
+    pub fn installation(&self) -> Option<DateTime<Tz>> {
+        let installation = &self.sensor.location.as_ref()?.installation;
+
+        let date = match DateTime::parse_from_rfc3339(installation) {
+            Ok(time) => time
+                .with_timezone(&self.last_update.timezone())
+                .date_naive(),
+            Err(_) => NaiveDate::parse_from_str(installation.get(..10)?, "%F").ok()?,
+        };
+
+        Some(self.start_of_day(date))
+    }
+
     pub fn with_updated_timestamp(&self, last_update: DateTime<Tz>) -> Sensor {
         Sensor {
             sensor: self.sensor.clone(),
+            location: self.location.clone(),
+            location_id: self.location_id.clone(),
+            last_seen: self.last_seen,
             last_update,
         }
     }
@@ -28,24 +121,152 @@ impl TryFrom<client::Sensor> for Sensor {
     type Error = anyhow::Error;
 
     fn try_from(sensor: client::Sensor) -> Result<Self> {
-        let location = sensor
-            .location
-            .as_ref()
-            .ok_or_else(|| anyhow!("Fetch devices with location"))?;
-        let timezone: Tz = match location.tz.parse() {
-            Ok(tz) => tz,
-            Err(_) => {
-                return Err(anyhow!("Unknown sensor timezone {}", location.tz));
+        // Without a location the sensor is labeled by its id and queried in UTC
+        let (location, location_id, timezone) = match &sensor.location {
+            Some(location) => {
+                let timezone: Tz = match location.tz.parse() {
+                    Ok(tz) => tz,
+                    Err(_) => {
+                        return Err(anyhow!("Unknown sensor timezone {}", location.tz));
+                    }
+                };
+
+                (location.name.clone(), location.id.to_string(), timezone)
+            }
+            None => {
+                warn!(
+                    device_id = sensor.id,
+                    "Sensor has no location, labeling it by device id and using UTC"
+                );
+
+                (sensor.id.clone(), String::new(), Tz::UTC)
             }
         };
 
-        let last_update = DateTime::parse_from_rfc3339(&sensor.last_seen)
+        let last_seen = DateTime::parse_from_rfc3339(&sensor.last_seen)
             .with_context(|| format!("Unable to parse sensor last seen time {}", sensor.last_seen))?
             .with_timezone(&timezone);
 
         Ok(Sensor {
             sensor,
-            last_update,
+            location,
+            location_id,
+            last_seen,
+            last_update: start_of_minute(last_seen),
         })
     }
 }
+
+/// `time` with the seconds removed, query windows start and end on whole minutes
+pub fn start_of_minute(time: DateTime<Tz>) -> DateTime<Tz> {
+    time.with_second(0)
+        .and_then(|t| t.with_nanosecond(0))
+        .unwrap_or(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::flume_api::mock;
+
+    /// A sensor in `timezone` last updated at the local time `last_update`, the earlier one
+    /// when it is repeated
+    fn sensor(timezone: Tz, last_update: &str) -> Sensor {
+        let sensor = match mock::sensor("1", Utc::now()) {
+            client::Device::Sensor(s) => Sensor::try_from(s).unwrap(),
+            _ => unreachable!(),
+        };
+
+        sensor.with_updated_timestamp(local(timezone, last_update))
+    }
+
+    fn local(timezone: Tz, time: &str) -> DateTime<Tz> {
+        let time = NaiveDateTime::parse_from_str(time, "%F %H:%M").unwrap();
+
+        timezone.from_local_datetime(&time).earliest().unwrap()
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%F").unwrap()
+    }
+
+    #[test]
+    fn start_of_day_skipped_midnight() {
+        // Clocks sprang forward from midnight to 1am
+        let sensor = sensor(chrono_tz::America::Sao_Paulo, "2018-11-01 12:00");
+
+        let start = sensor.start_of_day(date("2018-11-04"));
+
+        assert_eq!(
+            local(chrono_tz::America::Sao_Paulo, "2018-11-04 01:00"),
+            start
+        );
+    }
+
+    #[test]
+    fn start_of_day_repeated_midnight() {
+        // Clocks fell back from 1am to midnight
+        let sensor = sensor(chrono_tz::America::Havana, "2019-11-01 12:00");
+
+        let start = sensor.start_of_day(date("2019-11-03"));
+
+        assert_eq!("2019-11-03T00:00:00-04:00", start.to_rfc3339());
+    }
+
+    #[test]
+    fn query_window_whole_minutes() {
+        let timezone = chrono_tz::America::Los_Angeles;
+        let sensor = sensor(timezone, "2024-06-01 12:00");
+
+        let window = sensor
+            .query_window(local(timezone, "2024-06-01 12:10"), &QueryBucket::MIN)
+            .unwrap();
+
+        assert_eq!(
+            "2024-06-01 12:00",
+            window.first.format("%F %H:%M").to_string()
+        );
+        assert_eq!(
+            "2024-06-01 12:09",
+            window.last.format("%F %H:%M").to_string()
+        );
+
+        // No whole minute has passed
+        assert!(sensor
+            .query_window(local(timezone, "2024-06-01 12:00"), &QueryBucket::MIN)
+            .is_none());
+    }
+
+    #[test]
+    fn query_window_spring_forward() {
+        let timezone = chrono_tz::America::Los_Angeles;
+        let sensor = sensor(timezone, "2024-03-10 01:50");
+
+        let window = sensor
+            .query_window(local(timezone, "2024-03-10 03:10"), &QueryBucket::MIN)
+            .unwrap();
+
+        assert_eq!(
+            "2024-03-10 01:50",
+            window.first.format("%F %H:%M").to_string()
+        );
+        assert_eq!(
+            "2024-03-10 03:09",
+            window.last.format("%F %H:%M").to_string()
+        );
+    }
+
+    #[test]
+    fn query_window_fall_back() {
+        let timezone = chrono_tz::America::Los_Angeles;
+        // The first 1:30am, before clocks fall back
+        let sensor = sensor(timezone, "2024-11-03 01:30");
+
+        // The second 1:10am is later but its local minutes were already queried
+        let until = local(timezone, "2024-11-03 01:10") + chrono::Duration::hours(1);
+        assert_eq!("2024-11-03T01:10:00-08:00", until.to_rfc3339());
+
+        assert!(sensor.query_window(until, &QueryBucket::MIN).is_none());
+    }
+}
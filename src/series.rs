@@ -0,0 +1,228 @@
+use prometheus::CounterVec;
+use prometheus::GaugeVec;
+use prometheus::IntGaugeVec;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+#[derive(Clone)]
+pub enum Metric {
+    Counter(&'static CounterVec),
+    Gauge(&'static GaugeVec),
+    IntGauge(&'static IntGaugeVec),
+}
+
+impl Metric {
+    fn address(&self) -> usize {
+        match self {
+            Metric::Counter(m) => *m as *const CounterVec as usize,
+            Metric::Gauge(m) => *m as *const GaugeVec as usize,
+            Metric::IntGauge(m) => *m as *const IntGaugeVec as usize,
+        }
+    }
+}
+
+/// One labeled series of a metric
+#[derive(Clone)]
+pub struct Series {
+    metric: Metric,
+    labels: Vec<String>,
+}
+
+impl Series {
+    pub fn counter(metric: &'static CounterVec, labels: &[&str]) -> Self {
+        Series::new(Metric::Counter(metric), labels)
+    }
+
+    pub fn gauge(metric: &'static GaugeVec, labels: &[&str]) -> Self {
+        Series::new(Metric::Gauge(metric), labels)
+    }
+
+    pub fn int_gauge(metric: &'static IntGaugeVec, labels: &[&str]) -> Self {
+        Series::new(Metric::IntGauge(metric), labels)
+    }
+
+    fn new(metric: Metric, labels: &[&str]) -> Self {
+        let labels = labels.iter().map(|l| l.to_string()).collect();
+
+        Series { metric, labels }
+    }
+
+    /// Remove this series from its metric so it is no longer exported
+    fn remove(&self) {
+        let labels: Vec<&str> = self.labels.iter().map(|l| l.as_str()).collect();
+
+        // The series may never have been set
+        let _ = match self.metric {
+            Metric::Counter(m) => m.remove_label_values(&labels),
+            Metric::Gauge(m) => m.remove_label_values(&labels),
+            Metric::IntGauge(m) => m.remove_label_values(&labels),
+        };
+    }
+}
+
+impl PartialEq for Series {
+    fn eq(&self, other: &Self) -> bool {
+        self.metric.address() == other.metric.address() && self.labels == other.labels
+    }
+}
+
+impl Eq for Series {}
+
+impl Hash for Series {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.metric.address().hash(state);
+        self.labels.hash(state);
+    }
+}
+
+/// Tracks the series exported for each device so they can be removed when the device disappears
+/// or its labels change
+#[derive(Default)]
+pub struct SeriesTracker {
+    devices: HashMap<String, HashSet<Series>>,
+}
+
+// Series hash the address of their metric, not its contents
+#[allow(clippy::mutable_key_type)]
+impl SeriesTracker {
+    /// Record `series` exported for `device_id` and remove any other series of the same metrics
+    /// previously exported for the device
+    pub fn replace(&mut self, device_id: &str, series: Vec<Series>) {
+        let metrics: Vec<Metric> = series.iter().map(|s| s.metric.clone()).collect();
+
+        self.replace_metrics(device_id, &metrics, series);
+    }
+
+    /// Record `series` exported for `device_id` and remove any other series of `metrics`
+    /// previously exported for the device, even when `series` is empty
+    pub fn replace_metrics(&mut self, device_id: &str, metrics: &[Metric], series: Vec<Series>) {
+        let exported = self.devices.entry(device_id.to_string()).or_default();
+        let series: HashSet<Series> = series.into_iter().collect();
+        let metrics: HashSet<usize> = metrics.iter().map(Metric::address).collect();
+
+        exported.retain(|s| {
+            if metrics.contains(&s.metric.address()) && !series.contains(s) {
+                s.remove();
+
+                false
+            } else {
+                true
+            }
+        });
+
+        exported.extend(series);
+    }
+
+    /// Remove all series for devices not in `device_ids`
+    pub fn retain(&mut self, device_ids: &HashSet<String>) {
+        self.devices.retain(|device_id, series| {
+            if device_ids.contains(device_id) {
+                return true;
+            }
+
+            series.iter().for_each(Series::remove);
+
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use prometheus::core::Collector;
+    use prometheus::Opts;
+
+    fn gauge(name: &str) -> &'static GaugeVec {
+        Box::leak(Box::new(
+            GaugeVec::new(Opts::new(name, "test"), &["device_id", "location"]).unwrap(),
+        ))
+    }
+
+    /// Label values of each series exported by `metric`
+    fn exported(metric: &GaugeVec) -> Vec<Vec<String>> {
+        let mut exported: Vec<Vec<String>> = metric
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .map(|label| label.get_value().to_string())
+                    .collect()
+            })
+            .collect();
+        exported.sort();
+
+        exported
+    }
+
+    #[test]
+    fn replace_removes_relabeled_series() {
+        let metric = gauge("replace");
+        let mut tracker = SeriesTracker::default();
+
+        metric.with_label_values(&["1", "Home"]).set(1.0);
+        tracker.replace("1", vec![Series::gauge(metric, &["1", "Home"])]);
+
+        metric.with_label_values(&["1", "Cabin"]).set(1.0);
+        tracker.replace("1", vec![Series::gauge(metric, &["1", "Cabin"])]);
+
+        assert_eq!(vec![vec!["1", "Cabin"]], exported(metric));
+    }
+
+    #[test]
+    fn replace_keeps_other_metrics() {
+        let metric = gauge("replace_other");
+        let other = gauge("replace_other_kept");
+        let mut tracker = SeriesTracker::default();
+
+        metric.with_label_values(&["1", "Home"]).set(1.0);
+        other.with_label_values(&["1", "Home"]).set(1.0);
+        tracker.replace(
+            "1",
+            vec![
+                Series::gauge(metric, &["1", "Home"]),
+                Series::gauge(other, &["1", "Home"]),
+            ],
+        );
+
+        metric.with_label_values(&["1", "Cabin"]).set(1.0);
+        tracker.replace("1", vec![Series::gauge(metric, &["1", "Cabin"])]);
+
+        assert_eq!(vec![vec!["1", "Home"]], exported(other));
+    }
+
+    #[test]
+    fn replace_metrics_removes_unreturned_series() {
+        let metric = gauge("replace_metrics");
+        let mut tracker = SeriesTracker::default();
+
+        metric.with_label_values(&["1", "Home"]).set(1.0);
+        tracker.replace("1", vec![Series::gauge(metric, &["1", "Home"])]);
+
+        tracker.replace_metrics("1", &[Metric::Gauge(metric)], vec![]);
+
+        assert!(exported(metric).is_empty());
+    }
+
+    #[test]
+    fn retain_removes_missing_devices() {
+        let metric = gauge("retain");
+        let mut tracker = SeriesTracker::default();
+
+        for device_id in ["1", "2"] {
+            metric.with_label_values(&[device_id, "Home"]).set(1.0);
+            tracker.replace(device_id, vec![Series::gauge(metric, &[device_id, "Home"])]);
+        }
+
+        tracker.retain(&HashSet::from(["2".to_string()]));
+
+        assert_eq!(vec![vec!["2", "Home"]], exported(metric));
+    }
+}
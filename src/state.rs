@@ -0,0 +1,112 @@
+use anyhow::Context;
+use anyhow::Result;
+
+use chrono::DateTime;
+use chrono::FixedOffset;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Snapshot of downloader state that can be handed to a new exporter process so it can resume
+/// without a usage gap or counting the same usage twice.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    pub user_id: Option<i64>,
+    pub token: Option<TokenState>,
+    pub sensors: Vec<SensorState>,
+    pub usage: Vec<UsageState>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenState {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Unix timestamp in seconds when the access token expires
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SensorState {
+    pub id: String,
+    /// End of the last usage window queried for this sensor
+    pub last_update: DateTime<FixedOffset>,
+}
+
+/// `flume_water_usage_liters` total for one set of labels
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UsageState {
+    pub location_id: String,
+    pub location: String,
+    pub device_id: String,
+    pub liters: f64,
+}
+
+impl State {
+    /// Load a state snapshot from `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let source = fs::read_to_string(path)?;
+
+        serde_json::from_str(&source).context("Invalid state file")
+    }
+
+    /// Save the state snapshot to `path`, replacing it atomically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string(self)?;
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        write_private(Path::new(&temporary), json.as_bytes())
+            .with_context(|| format!("Unable to write {}", Path::new(&temporary).display()))?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("Unable to replace {}", path.display()))
+    }
+}
+
+/// Write `contents` to a new file at `path` only the owner can read, since the state holds the
+/// Flume tokens
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    // A file left behind by an earlier run could have been created with broader permissions
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    options.open(path)?.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn save_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!(
+            "flume_water_exporter_state_{}.json",
+            std::process::id()
+        ));
+
+        State::default().save(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(0o600, mode & 0o777);
+    }
+}
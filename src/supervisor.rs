@@ -0,0 +1,368 @@
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+
+use crate::client::Client;
+use crate::client::ErrorKind;
+use crate::client::RateLimited;
+use crate::client::RequestFailed;
+use crate::configuration::Configuration;
+use crate::configuration::Policy;
+use crate::flume::Flume;
+use crate::flume_builder::FlumeBuilder;
+use crate::marked;
+use crate::state::State;
+use crate::state::TokenState;
+
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use tokio::sync::mpsc;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+/// Delay before the first restart after an error
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay between restarts
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Time since the last restart after which the backoff starts over
+const STABLE_AFTER: Duration = Duration::from_secs(600);
+
+/// Delay before authenticating again after Flume rejects the credentials, long enough that
+/// retrying doesn't lock the account
+const AUTH_INITIAL_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Longest delay between authentication attempts Flume rejected
+const AUTH_MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Deserialize errors in a row that exit the exporter when the deserialize policy is exit
+const REPEATED_DESERIALIZE_ERRORS: u32 = 3;
+
+/// Marks an error that restarting the downloader can't recover from
+#[derive(Debug)]
+pub struct Unrecoverable;
+
+impl std::fmt::Display for Unrecoverable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unrecoverable error")
+    }
+}
+
+impl std::error::Error for Unrecoverable {}
+
+/// Errors from network problems or rate limiting that go away by themselves
+pub fn is_transient(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        if cause.is::<RateLimited>() {
+            return true;
+        }
+
+        match cause.downcast_ref::<reqwest::Error>() {
+            Some(e) => e.is_timeout() || e.is_request() || e.is_connect(),
+            None => false,
+        }
+    })
+}
+
+fn is_unrecoverable(error: &Error) -> bool {
+    marked::markers::<Unrecoverable>(error).next().is_some()
+}
+
+/// Flume rejected the credentials, which may have been rotated
+fn is_unauthorized(error: &Error) -> bool {
+    has_kind(error, ErrorKind::Authentication)
+        || error.chain().any(
+            |cause| matches!(cause.downcast_ref::<RequestFailed>(), Some(e) if e.http_code == 401),
+        )
+}
+
+fn has_kind(error: &Error, kind: ErrorKind) -> bool {
+    marked::markers::<ErrorKind>(error).any(|marker| *marker == kind)
+}
+
+/// Authenticate before polling starts.
+///
+/// While Flume rejects the credentials and the authentication policy is retry, authentication is
+/// retried with backoff instead of exiting, since rapid repeated failures can lock the account.
+/// The secret files are re-read before each retry.
+pub async fn authenticate(
+    configuration: &mut Configuration,
+    client: &Client,
+    token: Option<TokenState>,
+) -> Result<Flume> {
+    let mut backoff = AUTH_INITIAL_BACKOFF;
+
+    loop {
+        let result = FlumeBuilder::from_configuration(configuration.clone())
+            .restore_token(token.clone())
+            .build_with(client.clone())
+            .await;
+
+        match result {
+            Ok(flume) => return Ok(flume),
+            Err(e)
+                if is_unauthorized(&e)
+                    && configuration.error_policy().authentication() == Policy::Retry =>
+            {
+                warn!(
+                    "Flume rejected the credentials, retrying in {}s {:#}",
+                    backoff.as_secs(),
+                    e
+                );
+
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(AUTH_MAX_BACKOFF);
+
+                match configuration.reload_secrets() {
+                    Ok(true) => client.set_client_secret(configuration.secret_id()),
+                    Ok(false) => (),
+                    Err(e) => warn!("{:#}", e),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Receives errors from the downloader and exporter and restarts the downloader with a new
+/// `Flume` after each one, backing off on repeated errors.  The new `Flume` shares `client` so
+/// API request tracking continues across restarts.  It keeps the downloader's token unless Flume
+/// rejected it, so only credential errors send the password again.
+///
+/// Only unrecoverable errors, such as the server failing to start, and errors the
+/// `[error_policy]` exits for, such as Flume rejecting the username and password, stop the
+/// exporter.
+pub struct Supervisor {
+    configuration: Configuration,
+    client: Client,
+    error_rx: mpsc::Receiver<Error>,
+    restart_tx: mpsc::Sender<Flume>,
+    state_rx: watch::Receiver<State>,
+    backoff: Duration,
+    auth_backoff: Duration,
+    last_restart: Option<Instant>,
+    deserialize_errors: u32,
+}
+
+impl Supervisor {
+    pub fn new(
+        configuration: Configuration,
+        client: Client,
+        error_rx: mpsc::Receiver<Error>,
+        restart_tx: mpsc::Sender<Flume>,
+        state_rx: watch::Receiver<State>,
+    ) -> Self {
+        Supervisor {
+            configuration,
+            client,
+            error_rx,
+            restart_tx,
+            state_rx,
+            backoff: INITIAL_BACKOFF,
+            auth_backoff: AUTH_INITIAL_BACKOFF,
+            last_restart: None,
+            deserialize_errors: 0,
+        }
+    }
+
+    /// Supervise until an unrecoverable error occurs, returning the process exit code
+    pub async fn run(mut self) -> i32 {
+        loop {
+            let error = match self.error_rx.recv().await {
+                Some(e) => e,
+                None => {
+                    error!("Error reporting channel closed unexpectedly, bug?");
+
+                    return 1;
+                }
+            };
+
+            error!("{:#}", error);
+
+            let unauthorized = is_unauthorized(&error);
+
+            if unauthorized && self.reload_secrets() {
+                info!("Credentials changed, authenticating with the new credentials");
+            } else if self.exits_for(&error) {
+                return crate::exit_code(&error);
+            }
+
+            if let Err(e) = self.restart(unauthorized).await {
+                error!("{:#}", e);
+
+                return crate::exit_code(&e);
+            }
+        }
+    }
+
+    /// Whether `error` stops the exporter under the error policy
+    fn exits_for(&mut self, error: &Error) -> bool {
+        if is_unrecoverable(error) {
+            return true;
+        }
+
+        let policy = self.configuration.error_policy();
+
+        if has_kind(error, ErrorKind::Deserialize) {
+            self.deserialize_errors += 1;
+        } else {
+            self.deserialize_errors = 0;
+        }
+
+        let deserialize = policy.deserialize() == Policy::Exit
+            && self.deserialize_errors >= REPEATED_DESERIALIZE_ERRORS;
+
+        deserialize
+            || (policy.authentication() == Policy::Exit
+                && has_kind(error, ErrorKind::Authentication))
+            || (policy.budgets() == Policy::Exit && has_kind(error, ErrorKind::Budgets))
+    }
+
+    /// Re-read the secret files, returning true when the client secret or password changed
+    fn reload_secrets(&mut self) -> bool {
+        match self.configuration.reload_secrets() {
+            Ok(changed) => {
+                if changed {
+                    self.client
+                        .set_client_secret(self.configuration.secret_id());
+                }
+
+                changed
+            }
+            Err(e) => {
+                warn!("{:#}", e);
+
+                false
+            }
+        }
+    }
+
+    /// Hand a new `Flume` to the downloader, authenticating again when the token was
+    /// `unauthorized`
+    async fn restart(&mut self, unauthorized: bool) -> Result<()> {
+        if let Some(last_restart) = self.last_restart {
+            if last_restart.elapsed() >= STABLE_AFTER {
+                self.backoff = INITIAL_BACKOFF;
+                self.deserialize_errors = 0;
+            }
+        }
+
+        let mut token = if unauthorized {
+            None
+        } else {
+            self.state_rx.borrow().token.clone()
+        };
+
+        loop {
+            info!("Restarting downloader in {}s", self.backoff.as_secs());
+            sleep(self.backoff).await;
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+
+            let flume = match FlumeBuilder::from_configuration(self.configuration.clone())
+                .restore_token(token.clone())
+                .build_with(self.client.clone())
+                .await
+            {
+                Ok(flume) => flume,
+                // A network problem says nothing about the credentials
+                Err(e) if is_transient(&e) => {
+                    warn!("Unable to authenticate, retrying {:#}", e);
+
+                    continue;
+                }
+                Err(e) if is_unauthorized(&e) && self.reload_secrets() => {
+                    token = None;
+
+                    warn!(
+                        "Unable to authenticate, retrying with changed credentials {:#}",
+                        e
+                    );
+
+                    continue;
+                }
+                Err(e)
+                    if is_unauthorized(&e)
+                        && self.configuration.error_policy().authentication() == Policy::Retry =>
+                {
+                    token = None;
+
+                    warn!(
+                        "Flume rejected the credentials, retrying in at least {}s {:#}",
+                        self.auth_backoff.as_secs(),
+                        e
+                    );
+
+                    self.backoff = self.backoff.max(self.auth_backoff);
+                    self.auth_backoff = (self.auth_backoff * 2).min(AUTH_MAX_BACKOFF);
+
+                    continue;
+                }
+                Err(e) if self.configuration.error_policy().authentication() == Policy::Retry => {
+                    warn!("Unable to authenticate, retrying {:#}", e);
+
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e.context("Unable to authenticate, check username and password"))
+                }
+            };
+
+            self.restart_tx
+                .send(flume)
+                .await
+                .map_err(|_| anyhow!("Downloader stopped unexpectedly, bug?"))?;
+
+            self.last_restart = Some(Instant::now());
+            self.auth_backoff = AUTH_INITIAL_BACKOFF;
+
+            // Errors sent while backing off came from before the restart
+            while let Ok(error) = self.error_rx.try_recv() {
+                if self.exits_for(&error) {
+                    return Err(error);
+                }
+
+                debug!("Discarding error from before restart {:#}", error);
+            }
+
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected() -> Error {
+        let failure = Error::from(RequestFailed {
+            http_code: 400,
+            message: "invalid_grant".to_string(),
+            detailed: serde_json::Value::Null,
+        });
+
+        marked::mark(failure, ErrorKind::Authentication)
+    }
+
+    #[test]
+    fn is_unauthorized_under_budgets() {
+        let error = marked::mark(rejected().context("budgets"), ErrorKind::Budgets);
+
+        assert!(is_unauthorized(&error));
+        assert!(has_kind(&error, ErrorKind::Budgets));
+    }
+
+    #[test]
+    fn is_unrecoverable_marked() {
+        let error = marked::mark(anyhow!("Server on 0.0.0.0:9160 failed"), Unrecoverable);
+
+        assert!(is_unrecoverable(&error));
+        assert!(!is_unrecoverable(&rejected()));
+    }
+}
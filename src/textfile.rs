@@ -0,0 +1,89 @@
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::configuration::TextfileConfiguration;
+use crate::state::State;
+
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+
+use prometheus::Encoder;
+use prometheus::Registry;
+use prometheus::TextEncoder;
+
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+
+use tokio::sync::watch;
+
+/// Writes the metrics to a file for the node_exporter textfile collector after each downloader
+/// cycle
+pub struct Textfile {
+    registry: Registry,
+    configuration: TextfileConfiguration,
+}
+
+impl Textfile {
+    pub fn new(configuration: TextfileConfiguration, registry: Registry) -> Self {
+        Textfile {
+            registry,
+            configuration,
+        }
+    }
+
+    pub async fn start(self, mut state_rx: watch::Receiver<State>) {
+        info!("Writing metrics to {}", self.configuration.path().display());
+
+        crate::spawn_named(
+            async move {
+                while state_rx.changed().await.is_ok() {
+                    if let Err(e) = self.write().await {
+                        error!("{:#}", e);
+                    }
+                }
+            },
+            "textfile",
+        );
+    }
+
+    /// Write the metrics next to the file then rename it into place so the textfile collector
+    /// never reads a partly written file
+    async fn write(&self) -> Result<()> {
+        let path = self.configuration.path();
+        let temporary = temporary_path(&path);
+
+        // The process metrics would clash with node_exporter's own
+        let metric_families: Vec<_> = self
+            .registry
+            .gather()
+            .into_iter()
+            .filter(|family| !family.get_name().starts_with("process_"))
+            .collect();
+
+        let mut body = vec![];
+        TextEncoder::new()
+            .encode(&metric_families, &mut body)
+            .context("encoding metrics")?;
+
+        debug!("Writing {} bytes to {}", body.len(), path.display());
+
+        tokio::fs::write(&temporary, body)
+            .await
+            .with_context(|| format!("writing metrics to {}", temporary.display()))?;
+
+        tokio::fs::rename(&temporary, &path)
+            .await
+            .with_context(|| format!("renaming {} to {}", temporary.display(), path.display()))
+    }
+}
+
+/// `path` with `.tmp` appended, which the textfile collector ignores since it only reads
+/// `*.prom` files
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(".tmp");
+
+    PathBuf::from(temporary)
+}
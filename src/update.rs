@@ -0,0 +1,103 @@
+use crate::bridge::Bridge;
+use crate::sensor::Sensor;
+
+use chrono::DateTime;
+use chrono::FixedOffset;
+
+use serde::Serialize;
+
+/// Data refreshed by the `Downloader`, sent to sinks other than the Prometheus metrics
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Update {
+    Bridge {
+        device_id: String,
+        location_id: String,
+        location: String,
+        product: String,
+        connected: bool,
+    },
+    Sensor {
+        device_id: String,
+        bridge_id: String,
+        location_id: String,
+        location: String,
+        product: String,
+        connected: bool,
+        oriented: bool,
+        battery_level: String,
+    },
+    Usage {
+        device_id: String,
+        location_id: String,
+        location: String,
+        /// Start of the query window in the sensor's local time
+        since: DateTime<FixedOffset>,
+        /// End of the query window in the sensor's local time
+        until: DateTime<FixedOffset>,
+        /// Usage since the previous query in liters
+        liters: f64,
+        /// Total usage counted by the exporter in liters
+        total_liters: f64,
+        /// Average flow since the previous query in liters per minute, `None` for the first
+        /// window after startup
+        flow_rate: Option<f64>,
+        /// Minutes of consecutive query windows with usage
+        continuous_flow_minutes: f64,
+    },
+    Periods {
+        device_id: String,
+        location_id: String,
+        location: String,
+        /// Usage since the start of the day at the location in liters
+        today_liters: f64,
+        /// Usage since the start of the week (Sunday) at the location in liters
+        this_week_liters: f64,
+        /// Usage since the start of the month at the location in liters
+        this_month_liters: f64,
+    },
+    Budget {
+        device_id: String,
+        location_id: String,
+        location: String,
+        name: String,
+        period: String,
+        /// Fraction of the budget used this period
+        used_ratio: f64,
+    },
+}
+
+impl Update {
+    pub fn bridge(bridge: &Bridge) -> Self {
+        Update::Bridge {
+            device_id: bridge.id.clone(),
+            location_id: bridge.location_id.clone(),
+            location: bridge.location.clone(),
+            product: bridge.product.clone(),
+            connected: bridge.connected,
+        }
+    }
+
+    pub fn sensor(sensor: &Sensor) -> Self {
+        Update::Sensor {
+            device_id: sensor.sensor.id.clone(),
+            bridge_id: sensor.sensor.bridge_id.clone(),
+            location_id: sensor.location_id.clone(),
+            location: sensor.location.clone(),
+            product: sensor.sensor.product.clone(),
+            connected: sensor.sensor.connected,
+            oriented: sensor.sensor.oriented,
+            battery_level: sensor.sensor.battery_level.clone(),
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        match self {
+            Update::Bridge { device_id, .. } => device_id,
+            Update::Sensor { device_id, .. } => device_id,
+            Update::Usage { device_id, .. } => device_id,
+            Update::Periods { device_id, .. } => device_id,
+            Update::Budget { device_id, .. } => device_id,
+        }
+    }
+}
@@ -0,0 +1,228 @@
+mod support;
+
+use std::time::Duration;
+
+use support::assert_metric;
+use support::exporter;
+use support::metric;
+use support::QUERY_LITERS;
+
+const DEVICE: &str = r#"device_id="2222222222222222222",location="Home",location_id="5678""#;
+
+const BUDGET: &str = r#"location="Home",location_id="5678",name="Monthly budget",period="monthly""#;
+
+#[tokio::test]
+async fn exports_usage() {
+    let metrics = exporter().wait_for_metrics("\nflume_water_up 1").await;
+
+    // Each query window adds QUERY_LITERS, more than one query may have run
+    let usage: f64 = metric(&metrics, &format!("flume_water_usage_liters{{{}}}", DEVICE))
+        .parse()
+        .unwrap();
+    assert!(
+        usage >= QUERY_LITERS && usage % QUERY_LITERS == 0.0,
+        "{}",
+        usage
+    );
+
+    // Starts from the usage before the first window, one more QUERY_LITERS
+    assert_metric(
+        &metrics,
+        &format!("flume_water_usage_lifetime_liters{{{}}}", DEVICE),
+        &(usage + QUERY_LITERS).to_string(),
+    );
+
+    assert!(
+        metrics.contains(&format!(
+            "\nflume_water_sensor_last_query_timestamp_seconds{{{}}} ",
+            DEVICE
+        )),
+        "{}",
+        metrics
+    );
+
+    assert_metric(
+        &metrics,
+        &format!("flume_water_usage_today_liters{{{}}}", DEVICE),
+        "10",
+    );
+    assert_metric(
+        &metrics,
+        r#"flume_water_data_stale{location="Home",location_id="5678"}"#,
+        "0",
+    );
+}
+
+#[tokio::test]
+async fn exports_devices() {
+    let metrics = exporter().wait_for_metrics("\nflume_water_up 1").await;
+
+    assert_metric(
+        &metrics,
+        r#"flume_water_bridge_connected{device_id="1111111111111111111",location="Home",location_id="5678"}"#,
+        "1",
+    );
+    assert_metric(
+        &metrics,
+        r#"flume_water_device_firmware_info{device_id="2222222222222222222",version="1.2.3"}"#,
+        "1",
+    );
+
+    #[cfg(target_os = "linux")]
+    assert!(
+        metrics.contains("\nprocess_resident_memory_bytes "),
+        "{}",
+        metrics
+    );
+}
+
+#[tokio::test]
+async fn exports_budgets() {
+    let metrics = exporter().wait_for_metrics("\nflume_water_up 1").await;
+
+    assert_metric(
+        &metrics,
+        &format!("flume_water_budget_liters{{{}}}", BUDGET),
+        "11356.235352",
+    );
+    assert_metric(
+        &metrics,
+        &format!("flume_water_budget_gallons{{{}}}", BUDGET),
+        "3000",
+    );
+    assert_metric(
+        &metrics,
+        &format!("flume_water_budget_used_ratio{{{}}}", BUDGET),
+        "0.5",
+    );
+    assert_metric(
+        &metrics,
+        &format!("flume_water_budget_remaining_liters{{{}}}", BUDGET),
+        "5678.117676",
+    );
+
+    // Thresholds already exceeded before the first update aren't counted
+    assert_metric(
+        &metrics,
+        &format!(
+            "flume_water_budget_threshold_exceeded_total{{{},threshold=\"1500\"}}",
+            BUDGET
+        ),
+        "0",
+    );
+}
+
+#[tokio::test]
+async fn exports_notification_rules() {
+    let metrics = exporter().wait_for_metrics("\nflume_water_up 1").await;
+
+    assert_metric(
+        &metrics,
+        r#"flume_water_notification_rule_info{device_id="2222222222222222222",enabled="true",location="Home",location_id="5678",name="High flow",rule_id="7",threshold="2.5",type="HIGH_FLOW"}"#,
+        "1",
+    );
+}
+
+#[tokio::test]
+async fn exports_current_minute() {
+    // Queried on its own interval once the first polling cycle found the sensors
+    let metrics = exporter()
+        .wait_for_metrics("\nflume_water_current_minute_liters{")
+        .await;
+
+    assert_metric(
+        &metrics,
+        &format!("flume_water_current_minute_liters{{{}}}", DEVICE),
+        "10",
+    );
+}
+
+#[tokio::test]
+async fn writes_textfile() {
+    let exporter = exporter();
+
+    // Written after each cycle, the first may still be renaming into place
+    let mut written = String::new();
+    for _ in 0..50 {
+        written = std::fs::read_to_string(&exporter.textfile).unwrap_or_default();
+
+        if written.contains("\nflume_water_up 1") {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    assert!(written.contains("\nflume_water_up 1"), "{}", written);
+    assert!(!written.contains("\nprocess_"), "{}", written);
+}
+
+#[tokio::test]
+async fn serves_dashboard() {
+    let dashboard = reqwest::get(exporter().url("/dashboard"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(
+        dashboard.contains("<td>Monthly budget</td>"),
+        "{}",
+        dashboard
+    );
+}
+
+#[tokio::test]
+async fn serves_usage() {
+    let usage = reqwest::get(exporter().url("/api/v1/usage?location=Home&range=1h"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let usage: serde_json::Value = serde_json::from_str(&usage).unwrap();
+
+    assert_eq!(QUERY_LITERS, usage[0]["liters"], "{}", usage);
+}
+
+#[tokio::test]
+async fn serves_debug_state() {
+    let state = reqwest::Client::new()
+        .get(exporter().url("/debug/state"))
+        .bearer_auth("CONTROL_TOKEN")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let state: serde_json::Value = serde_json::from_str(&state).unwrap();
+
+    assert_eq!(
+        "2222222222222222222", state["sensors"][0]["id"],
+        "{}",
+        state
+    );
+}
+
+#[tokio::test]
+async fn changes_away_mode() {
+    let client = reqwest::Client::new();
+
+    let away_mode = client
+        .post(exporter().url("/control/away-mode?location=Home&away_mode=on"))
+        .bearer_auth("CONTROL_TOKEN")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::OK, away_mode.status());
+
+    let away_mode = client
+        .post(exporter().url("/control/away-mode?location=Cabin&away_mode=on"))
+        .bearer_auth("CONTROL_TOKEN")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, away_mode.status());
+}
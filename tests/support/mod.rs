@@ -0,0 +1,248 @@
+//! Mock Flume API server serving the fixtures in `tests/fixtures`, and an exporter polling it
+
+use hyper::service::make_service_fn;
+use hyper::service::service_fn;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+
+use serde_json::json;
+use serde_json::Value;
+
+use flume_water_exporter::arguments::Arguments;
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Liters returned for every usage query
+pub const QUERY_LITERS: f64 = 10.0;
+
+pub struct MockFlume {
+    pub address: SocketAddr,
+}
+
+impl MockFlume {
+    /// Start the mock server on a free loopback port
+    pub fn start() -> Self {
+        let service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request| async {
+                Ok::<_, Infallible>(route(request).await)
+            }))
+        });
+
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let address = server.local_addr();
+
+        tokio::spawn(server);
+
+        MockFlume { address }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+}
+
+/// An exporter polling a `MockFlume`
+pub struct Exporter {
+    pub port: u16,
+    /// Path of the textfile sink output
+    pub textfile: PathBuf,
+}
+
+impl Exporter {
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    /// Scrape the exporter until `expected` appears
+    pub async fn wait_for_metrics(&self, expected: &str) -> String {
+        wait_for_metrics(self.port, expected).await
+    }
+}
+
+/// The exporter shared by the tests, started on first use after its first polling cycle
+/// finishes.  Its metrics are global to the process so only one exporter can run per test
+/// binary.  It runs on its own thread so it outlives the runtime of the test that started it.
+pub fn exporter() -> &'static Exporter {
+    static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+    EXPORTER.get_or_init(|| {
+        let (exporter_tx, exporter_rx) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+
+            runtime.block_on(async {
+                let exporter = start_exporter().await;
+                exporter_tx.send(exporter).unwrap();
+
+                std::future::pending::<()>().await
+            })
+        });
+
+        exporter_rx.recv().unwrap()
+    })
+}
+
+async fn start_exporter() -> Exporter {
+    let flume = MockFlume::start();
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let configuration = std::env::temp_dir().join(format!(
+        "flume_water_exporter_test_{}.toml",
+        std::process::id()
+    ));
+    let textfile = std::env::temp_dir().join(format!(
+        "flume_water_exporter_test_{}.prom",
+        std::process::id()
+    ));
+
+    std::fs::write(
+        &configuration,
+        format!(
+            r#"
+api_url = "{}"
+bind_address = "127.0.0.1:{}"
+client_id = "CLIENT_ID"
+secret_id = "CLIENT_SECRET"
+username = "user@example.com"
+password = "PASSWORD"
+control_token = "CONTROL_TOKEN"
+current_minute_interval = 1
+
+[textfile]
+path = "{}"
+"#,
+            flume.url(),
+            port,
+            textfile.display()
+        ),
+    )
+    .unwrap();
+
+    let arguments = Arguments {
+        configuration: Some(configuration.display().to_string()),
+        ..Default::default()
+    };
+
+    tokio::spawn(flume_water_exporter::run(arguments));
+
+    // Set once the first polling cycle finishes
+    wait_for_metrics(port, "\nflume_water_up 1").await;
+
+    std::fs::remove_file(&configuration).unwrap();
+
+    Exporter { port, textfile }
+}
+
+async fn wait_for_metrics(port: u16, expected: &str) -> String {
+    let uri = format!("http://127.0.0.1:{}/metrics", port);
+
+    for _ in 0..100 {
+        if let Ok(response) = reqwest::get(&uri).await {
+            let body = response.text().await.unwrap();
+
+            if body.contains(expected) {
+                return body;
+            }
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("{} did not appear in metrics", expected);
+}
+
+pub fn assert_metric(metrics: &str, series: &str, value: &str) {
+    assert_eq!(value, metric(metrics, series), "{}", series);
+}
+
+/// Value of `series` in `metrics`
+pub fn metric<'a>(metrics: &'a str, series: &str) -> &'a str {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series))
+        .unwrap_or_else(|| panic!("{} not found in\n{}", series, metrics))
+        .trim()
+}
+
+async fn route(request: Request<Body>) -> Response<Body> {
+    let path = request.uri().path().to_string();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+    match (request.method(), segments.as_slice()) {
+        (&Method::POST, ["oauth", "token"]) => fixture(include_str!("../fixtures/token.json")),
+        (&Method::GET, ["me"]) => fixture(include_str!("../fixtures/me.json")),
+        (&Method::GET, ["users", "1234", "devices"]) => {
+            fixture(include_str!("../fixtures/devices.json"))
+        }
+        (&Method::GET, ["users", "1234", "devices", _, "budgets"]) => {
+            fixture(include_str!("../fixtures/budgets.json"))
+        }
+        (&Method::GET, ["users", "1234", "devices", _, "rules", "usage-alerts"]) => {
+            fixture(include_str!("../fixtures/notification_rules.json"))
+        }
+        (&Method::POST, ["users", "1234", "devices", _, "query"]) => query(request).await,
+        (&Method::PATCH, ["users", "1234", "locations", "5678"]) => {
+            fixture(&response(json!([])).to_string())
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("{}"))
+            .unwrap(),
+    }
+}
+
+/// Answer each query in the request with `QUERY_LITERS`
+async fn query(request: Request<Body>) -> Response<Body> {
+    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+    let queries: Value = serde_json::from_slice(&body).unwrap();
+
+    let mut results = serde_json::Map::new();
+
+    for query in queries["queries"].as_array().unwrap() {
+        let request_id = query["request_id"].as_str().unwrap().to_string();
+        let datetime = query["since_datetime"].clone();
+
+        results.insert(
+            request_id,
+            json!([{ "datetime": datetime, "value": QUERY_LITERS }]),
+        );
+    }
+
+    fixture(&response(json!([results])).to_string())
+}
+
+/// Successful response envelope around `data`
+fn response(data: Value) -> Value {
+    json!({
+        "success": true,
+        "code": 602,
+        "message": "Request OK",
+        "http_code": 200,
+        "http_message": "OK",
+        "detailed": null,
+        "count": data.as_array().map_or(0, Vec::len),
+        "data": data,
+        "pagination": null,
+    })
+}
+
+fn fixture(body: &str) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}